@@ -0,0 +1,54 @@
+// Loads every .txt Prismatik profile in a directory (test_configs/ by default, together
+// with the full 0current_config.txt profile) through read_config and reports the ones that fail. Exits with an error if any did, so it can
+// run as a quick check after changes to the profile conversion or the Config structs.
+// With --strict, profile lines the conversion doesn't recognize count as failures.
+//
+//     cargo run --example parse_configs [--strict] [dir]
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use lightshow::config::{read_config_with, UnmatchedLines};
+
+fn main() -> ExitCode {
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let unmatched_lines = if flags.iter().any(|flag| flag == "--strict") {
+        UnmatchedLines::Error
+    } else {
+        UnmatchedLines::from_env()
+    };
+    let (dir, extra) = match args.into_iter().next() {
+        Some(dir) => (dir, None),
+        None => ("test_configs".to_string(), Some(PathBuf::from("0current_config.txt"))),
+    };
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .collect(),
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", dir, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    files.sort();
+    files.extend(extra.filter(|path| path.exists()));
+
+    let mut failures = 0;
+    for path in &files {
+        match read_config_with(&path.to_string_lossy(), unmatched_lines) {
+            Ok(config) => println!("ok    {} ({} LEDs)", path.display(), config.leds_array.len()),
+            Err(e) => {
+                println!("FAIL  {}: {}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    println!("{} of {} configs parsed", files.len() - failures, files.len());
+    if failures == 0 && !files.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -1,134 +1,245 @@
-use std::error::Error;
+use std::thread;
 
+use crate::error::Error;
 use crate::screen_capture::Color;
 
 // Function to check if WLED is online
-pub fn check_wled_online(web_address: &str) -> Result<(), Box<dyn Error>> {
+pub fn check_wled_online(web_address: &str) -> Result<(), Error> {
     let url = format!("http://{}/json/state", web_address);
 
     let client = reqwest::blocking::Client::new();
     let response = client.get(&url).send()?;
 
-    match response
-        .json::<serde_json::Value>()
-        .map_err(|e| e.to_string())?
-    {
+    match response.json::<serde_json::Value>()? {
         serde_json::Value::Object(map) => {
             log::info!("WLED is online");
             Ok(())
         }
-        _ => Err("Failed to connect to WLED".into()),
+        _ => Err(Error::Network("Failed to connect to WLED".to_string())),
     }
 }
 
-// Function to send pixel data to WLED
-pub fn set_pixels_red(web_address: &str) -> Result<(), Box<dyn Error>> {
+// Cheap request that keeps the connection to WLED open while no frames are sent
+pub fn keepalive(web_address: &str) -> Result<(), Error> {
+    let url = format!("http://{}/json/state", web_address);
+    reqwest::blocking::Client::new().get(&url).send()?.error_for_status()?;
+    Ok(())
+}
+
+// Number of LEDs in the segment, from `seg[].len` of `/json/state` instead of `leds.count`
+// of `/json/info`: the whole strip would be too long for a segment sharing it with others.
+// Only when the state lists no such segment is the strip's `leds.count` used.
+pub fn get_led_count(web_address: &str, segment_id: u8) -> Result<usize, Error> {
     let url = format!("http://{}/json/state", web_address);
 
     let client = reqwest::blocking::Client::new();
+    let state = client.get(&url).send()?.json::<serde_json::Value>()?;
+
+    let segment_len = state["seg"]
+        .as_array()
+        .and_then(|segments| segments.iter().find(|segment| segment["id"].as_u64() == Some(segment_id as u64)))
+        .and_then(|segment| segment["len"].as_u64());
+    if let Some(len) = segment_len {
+        return Ok(len as usize);
+    }
 
-    let response = client
-        .post(&url)
-        .json(&serde_json::json!({
-            "on": true,
-            "bri": 255,
-            "seg": [
-                {
-                    "start": 0,
-                    "stop": 500,
-                    "col": [
-                        {
-                            "r": 255,
-                            "g": 0,
-                            "b": 0
-                        }
-                    ]
-                }
-            ]
-        }))
-        .send()?;
-
-    match response
-        .json::<serde_json::Value>()
-        .map_err(|e| e.to_string())?
-    {
-        serde_json::Value::Object(map) => {
-            return Ok(());
-        }
-        _ => return Err("Failed to connect to WLED".into()),
+    log::warn!("WLED state has no segment {}, using the LED count of the whole strip", segment_id);
+    let url = format!("http://{}/json/info", web_address);
+    let info = client.get(&url).send()?.json::<serde_json::Value>()?;
+    info["leds"]["count"]
+        .as_u64()
+        .map(|count| count as usize)
+        .ok_or_else(|| Error::Network(format!("WLED has no segment {} and reports no LED count", segment_id)))
+}
+
+// Pads with black or truncates so exactly `count` colors are sent
+pub fn fit_pixel_count(pixels: &mut Vec<Color>, count: usize) {
+    let next_index = pixels.last().map(|c| c.led_index + 1).unwrap_or(0);
+    let missing = count.saturating_sub(pixels.len());
+    pixels.truncate(count);
+    pixels.extend((0..missing).map(|i| Color::new(next_index + i as i32, 0.0, 0.0, 0.0)));
+}
+
+// Stretches the sampled colors over `count` physical LEDs, each one linearly interpolated
+// between the two nearest samples. The first and last sample stay at the strip ends.
+pub fn interpolate_pixels(pixels: &[Color], count: usize) -> Vec<Color> {
+    let Some(first) = pixels.first() else {
+        return Vec::new();
+    };
+    if pixels.len() == 1 || count < 2 {
+        return (0..count).map(|i| Color::new(i as i32, first.r, first.g, first.b)).collect();
     }
+    let step = (pixels.len() - 1) as f32 / (count - 1) as f32;
+    (0..count)
+        .map(|i| {
+            let position = i as f32 * step;
+            let left = (position as usize).min(pixels.len() - 2);
+            let color = pixels[left].lerp(&pixels[left + 1], position - left as f32);
+            Color::new(i as i32, color.r, color.g, color.b)
+        })
+        .collect()
 }
 
-pub fn set_effect(web_address: &str, effect_id: u8) -> Result<(), Box<dyn Error>> {
-    let url = format!("http://{}/json/state", web_address);
+// Segment object for a `/json/state` payload, with `fields` added on top. Every payload
+// addresses the segment by id only, so its start/stop stay as configured on WLED.
+pub fn segment_state(segment_id: u8, fields: serde_json::Value) -> serde_json::Value {
+    let mut segment = serde_json::json!({ "id": segment_id });
+    if let (Some(segment), serde_json::Value::Object(fields)) = (segment.as_object_mut(), fields) {
+        segment.extend(fields);
+    }
+    segment
+}
 
-    let client = reqwest::blocking::Client::new();
+// Function to send pixel data to WLED
+pub fn set_pixels_red(web_address: &str, segment_id: u8) -> Result<(), Error> {
+    post_state(
+        web_address,
+        &serde_json::json!({
+            "on": true,
+            "seg": [segment_state(segment_id, serde_json::json!({
+                "bri": 255,
+                "col": [{ "r": 255, "g": 0, "b": 0 }]
+            }))]
+        }),
+    )
+}
 
-    let response = client
-        .post(&url)
-        .json(&serde_json::json!({
+pub fn set_effect(web_address: &str, segment_id: u8, effect_id: u8) -> Result<(), Error> {
+    post_state(
+        web_address,
+        &serde_json::json!({
             "on": true,
-            "bri": 255,
-            "seg": [
-                {
-                    "start": 0,
-                    "stop": 500,
-                    "fx": effect_id
-                }
-            ]
-        }))
-        .send()?;
-
-    match response
-        .json::<serde_json::Value>()
-        .map_err(|e| e.to_string())?
-    {
-        serde_json::Value::Object(_) => {
-            return Ok(());
-        }
-        _ => return Err("Failed to connect to WLED".into()),
-    }
+            "seg": [segment_state(segment_id, serde_json::json!({
+                "bri": 255,
+                "fx": effect_id
+            }))]
+        }),
+    )
+}
+
+// Runs an effect with `color` as the segment's primary color. The segment has to be
+// unfrozen (release_control) for the effect to animate.
+pub fn set_effect_color(web_address: &str, segment_id: u8, effect_id: u8, color: &Color) -> Result<(), Error> {
+    let [r, g, b] = color.to_rgb8();
+    post_state(web_address, &effect_color_state(segment_id, effect_id, [r, g, b]))
 }
 
-pub fn set_pixels(web_address: &str, pixels: Vec<Color>) -> Result<(), Box<dyn Error>> {
+// `/json/state` payload setting the segment's effect and primary color, nothing else
+pub fn effect_color_state(segment_id: u8, effect_id: u8, [r, g, b]: [u8; 3]) -> serde_json::Value {
+    serde_json::json!({
+        "seg": [segment_state(segment_id, serde_json::json!({
+            "fx": effect_id,
+            "col": [[r, g, b]]
+        }))]
+    })
+}
+
+// WLED accepts at most this many colors per `i` array
+pub const CHUNK_SIZE: usize = 256;
+
+// Splits the colors into WLED `i` arrays of hex strings. Every chunk after the
+// first starts with the LED index its colors begin at.
+pub fn build_pixel_chunks(pixels: Vec<Color>, chunk_size: usize) -> Vec<Vec<serde_json::Value>> {
     let formatted_pixels: Vec<String> = pixels.into_iter().map(|color| color.to_hex()).collect();
 
-    for (i, chunk) in formatted_pixels.chunks(256).enumerate() {
-        let mut chunk_vec: Vec<serde_json::Value> = chunk.iter().map(|s| serde_json::Value::String(s.clone())).collect();
-        if i != 0 {
-            chunk_vec.insert(0, serde_json::Value::Number(serde_json::Number::from(i * 256)));
-        }
-        match send_pixel_array(web_address, &chunk_vec) {
-            Ok(_) => log::info!("Chunk sent"),
-            Err(e) => return Err(e),
-        }
+    formatted_pixels
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut chunk_vec: Vec<serde_json::Value> = chunk.iter().map(|s| serde_json::Value::String(s.clone())).collect();
+            if i != 0 {
+                chunk_vec.insert(0, serde_json::Value::Number(serde_json::Number::from(i * chunk_size)));
+            }
+            chunk_vec
+        })
+        .collect()
+}
+
+// Colors are quantized to 8 bit here, the last step before sending.
+// Only the given segment is touched, other segments and the master brightness are left alone.
+// The segment keeps its bounds, the backend fits the colors to the strip length.
+pub fn set_pixels(web_address: &str, segment_id: u8, pixels: Vec<Color>) -> Result<(), Error> {
+    let chunks = build_pixel_chunks(pixels, CHUNK_SIZE);
+
+    if chunks.len() == 1 {
+        return send_pixel_array(web_address, segment_id, &chunks[0]);
     }
 
-    Ok(())
+    // Send all chunks concurrently and collect every failure instead of stopping at the first
+    let errors: Vec<String> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                scope.spawn(move || {
+                    send_pixel_array(web_address, segment_id, chunk).map_err(|e| format!("chunk {}: {}", i, e))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| match handle.join() {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e),
+                Err(_) => Some("send thread panicked".to_string()),
+            })
+            .collect()
+    });
+
+    if errors.is_empty() {
+        log::info!("{} chunks sent", chunks.len());
+        Ok(())
+    } else {
+        Err(Error::Network(format!("{} of {} chunks failed: {}", errors.len(), chunks.len(), errors.join("; "))))
+    }
 }
 
-pub fn send_pixel_array(web_address: &str, formatted_pixels: &[serde_json::Value]) -> Result<(), Box<dyn Error>> {
+pub fn send_pixel_array(web_address: &str, segment_id: u8, formatted_pixels: &[serde_json::Value]) -> Result<(), Error> {
+    post_state(web_address, &pixel_state(segment_id, formatted_pixels))
+}
+
+// `/json/state` payload writing one chunk of colors into the segment
+pub fn pixel_state(segment_id: u8, formatted_pixels: &[serde_json::Value]) -> serde_json::Value {
+    serde_json::json!({
+        "seg": [segment_state(segment_id, serde_json::json!({ "i": formatted_pixels }))]
+    })
+}
 
+// Posts a JSON state update and checks WLED answered with its state object
+fn post_state(web_address: &str, state: &serde_json::Value) -> Result<(), Error> {
     let url = format!("http://{}/json/state", web_address);
 
     let client = reqwest::blocking::Client::new();
+    let response = client.post(&url).json(state).send()?;
 
-    let response = client
-        .post(&url)
-        .json(&serde_json::json!({
-            "seg": [
-                {
-                    "i": formatted_pixels
-                }
-            ]
-        }))
-        .send()?;
-
-    match response
-        .json::<serde_json::Value>()
-        .map_err(|e| e.to_string())?
-    {
-        serde_json::Value::Object(_) =>return Ok(()),
-        _ => return Err("Failed to connect to WLED".into()),
+    match response.json::<serde_json::Value>()? {
+        serde_json::Value::Object(_) => Ok(()),
+        _ => Err(Error::Network("Failed to connect to WLED".to_string())),
     }
 }
+
+// Hands the strip back to WLED: leaves realtime mode and unfreezes the segment
+// so it returns to its normal state/effect instead of holding the last frame
+pub fn release_control(web_address: &str, segment_id: u8) -> Result<(), Error> {
+    post_state(
+        web_address,
+        &serde_json::json!({
+            "live": false,
+            "lor": 0,
+            "seg": [{ "id": segment_id, "frz": false }]
+        }),
+    )
+}
+
+// Takes the strip back before streaming resumes, freezing the segment so its effect
+// doesn't draw over the pixels we send
+pub fn acquire_control(web_address: &str, segment_id: u8) -> Result<(), Error> {
+    post_state(
+        web_address,
+        &serde_json::json!({
+            "on": true,
+            "seg": [{ "id": segment_id, "frz": true }]
+        }),
+    )
+}
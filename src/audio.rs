@@ -0,0 +1,119 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, Stream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::config::{parse_hex_color, SoundVisualizer, LED};
+use crate::screen_capture::Color;
+
+// Loudness below this is treated as silence when normalizing
+const MIN_PEAK: f32 = 0.01;
+// Per-read decay of the normalization peak
+const PEAK_DECAY: f32 = 0.995;
+
+// Captures the system audio output (WASAPI loopback) and tracks its loudness.
+// The stream is not Send, so create it on the thread that reads the level.
+pub struct AudioMeter {
+    _stream: Stream,
+    rms: Arc<AtomicU32>,
+    peak: f32,
+}
+
+impl AudioMeter {
+    // Opens the output device with the given index, falling back to the default output
+    pub fn start(device_index: u8) -> Result<Self, Box<dyn std::error::Error>> {
+        let host = cpal::default_host();
+        let device = match host.output_devices()?.nth(device_index as usize) {
+            Some(device) => device,
+            None => {
+                log::warn!("Audio device {} not found, using default output", device_index);
+                host.default_output_device()
+                    .ok_or("No audio output device available")?
+            }
+        };
+        log::info!("Audio capture from: {}", device.name().unwrap_or_default());
+
+        let supported_config = device.default_output_config()?;
+        let config = supported_config.config();
+        let rms = Arc::new(AtomicU32::new(0));
+        let err_fn = |e: cpal::StreamError| log::error!("Audio stream error: {}", e);
+
+        // Building an input stream on an output device enables loopback capture on WASAPI
+        let stream = match supported_config.sample_format() {
+            SampleFormat::F32 => {
+                let rms = Arc::clone(&rms);
+                device.build_input_stream(
+                    &config,
+                    move |data: &[f32], _: &_| store_rms(&rms, data.iter().copied()),
+                    err_fn,
+                    None,
+                )?
+            }
+            SampleFormat::I16 => {
+                let rms = Arc::clone(&rms);
+                device.build_input_stream(
+                    &config,
+                    move |data: &[i16], _: &_| {
+                        store_rms(&rms, data.iter().map(|s| *s as f32 / i16::MAX as f32))
+                    },
+                    err_fn,
+                    None,
+                )?
+            }
+            format => return Err(format!("Unsupported sample format: {:?}", format).into()),
+        };
+        stream.play()?;
+
+        Ok(AudioMeter {
+            _stream: stream,
+            rms,
+            peak: MIN_PEAK,
+        })
+    }
+
+    // Current loudness in 0..=1, normalized against a slowly decaying peak
+    pub fn level(&mut self) -> f32 {
+        let rms = f32::from_bits(self.rms.load(Ordering::Relaxed));
+        self.peak = (self.peak * PEAK_DECAY).max(rms).max(MIN_PEAK);
+        (rms / self.peak).clamp(0.0, 1.0)
+    }
+}
+
+fn store_rms(target: &AtomicU32, samples: impl Iterator<Item = f32>) {
+    let (sum, count) = samples.fold((0.0f32, 0u32), |(sum, count), s| (sum + s * s, count + 1));
+    if count > 0 {
+        target.store((sum / count as f32).sqrt().to_bits(), Ordering::Relaxed);
+    }
+}
+
+// Maps an audio level to LED colors between MinColor and MaxColor.
+// Visualizer 0 lights the whole strip, any other value draws a VU bar.
+pub fn visualizer_colors(leds_array: &[LED], settings: &SoundVisualizer, level: f32) -> Vec<Color> {
+    let (min_r, min_g, min_b) = parse_hex_color(&settings.MinColor).unwrap_or((0, 0, 0));
+    let (max_r, max_g, max_b) = parse_hex_color(&settings.MaxColor).unwrap_or((255, 255, 255));
+    let min = Color::from_rgb8(0, min_r, min_g, min_b);
+    let max = Color::from_rgb8(0, max_r, max_g, max_b);
+
+    if settings.Visualizer == 0 {
+        let color = min.lerp(&max, level);
+        return leds_array
+            .iter()
+            .map(|led| Color::new(led.index, color.r, color.g, color.b))
+            .collect();
+    }
+
+    // Bar grows along the LED index order
+    let max_index = leds_array.iter().map(|led| led.index).max().unwrap_or(0).max(1) as f32;
+    leds_array
+        .iter()
+        .map(|led| {
+            let t = led.index as f32 / max_index;
+            if t < level {
+                let color = min.lerp(&max, t);
+                Color::new(led.index, color.r, color.g, color.b)
+            } else {
+                Color::new(led.index, 0.0, 0.0, 0.0)
+            }
+        })
+        .collect()
+}
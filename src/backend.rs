@@ -1,53 +1,360 @@
 use crate::arduino;
-use crate::config;
-use crate::hardware_interaction::{get_monitor_info, SlimMonitorInfo};
-use crate::hardware_interaction::{Capture, FrameData};
+use crate::audio::{visualizer_colors, AudioMeter};
+use crate::config::{self, LED};
+use crate::hardware_interaction::{foreground_window_rect, get_monitor_info, MonitorInfo, SlimMonitorInfo};
+use crate::hardware_interaction::{run_gdi_capture, Capture, CaptureFlags, FrameData, PixelFormat};
+use crate::error::Error;
+use crate::gpu::GpuAverager;
 use crate::logger;
-use crate::screen_capture::{calculate_avg_colors, combine_screens};
-use crate::SharedState;
+use crate::output::{shared_sink, DeviceHealth, FileSink, FrameFormat, OutputSink, Reconnector, SharedSink, WledHttpSink, WledUdpSink};
+use crate::metrics::{Stage, METRICS};
+use crate::post_processing::{AutoExposure, PaletteLock, PostProcessor, Smoother};
+use crate::status_server;
+use crate::config::parse_hex_color;
+use crate::screen_capture::{
+    average_luminance, dominant_color, calculate_avg_colors, color_difference, combine_screens,
+    find_monitor_overlaps, hold_offscreen_colors, map_leds_to_rect, offscreen_led_indices, save_screenshot_with_avg_colors, scale_leds, static_colors,
+    AveragingMode, AveragingOptions, BoxBlur, Color, CornerBlend, DirtyTiles, HdrToneMap, Mirror, MonitorCorrection, OffscreenLeds, OverlapMode,
+};
+use crate::{LedLayout, Mode, OutputSettings, PipelineState, SharedState};
 use once_cell::sync::Lazy;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, mpsc::Receiver},
+    sync::{Arc, Mutex, MutexGuard, mpsc::Receiver},
     thread,
     time::{Duration, Instant},
-    sync::atomic::Ordering,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 use windows_capture::{
-    capture::GraphicsCaptureApiHandler,
+    capture::{GraphicsCaptureApiError, GraphicsCaptureApiHandler},
     monitor::Monitor,
-    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
+    settings::{CursorCaptureSettings, DrawBorderSettings, Settings},
 };
 
+// Compiled in WLED address, used unless LIGHTSHOW_WLED_ADDR is set
+const DEFAULT_WLED_ADDRESS: &str = "192.168.0.28";
+const WLED_ADDRESS_ENV: &str = "LIGHTSHOW_WLED_ADDR";
+// Time between frames of the breathing idle effect
+const IDLE_FRAME_INTERVAL: Duration = Duration::from_millis(50);
+// Smallest foreground window (in pixels per side) the focus mode samples around
+const MIN_FOCUS_SIZE: i32 = 100;
+// Every n-th pixel of the combined image goes into the auto-exposure histogram
+const AUTO_EXPOSURE_SAMPLE_STEP: usize = 16;
+// With dirty regions, every n-th frame averages all LEDs, for changes between the pixels DirtyTiles compares
+const FULL_AVERAGE_INTERVAL: u32 = 30;
+// How long a snapshot waits for every monitor to deliver a frame
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+// Capture rate with ActiveFps on auto when no monitor reports its refresh rate
+const FALLBACK_FPS: u32 = 24;
+// Interval and limit of the re-enumeration in wait_for_stable_monitors
+const STABLE_MONITORS_POLL: Duration = Duration::from_millis(500);
+const STABLE_MONITORS_TIMEOUT: Duration = Duration::from_secs(15);
+// How often a running capture thread updates its heartbeat
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+const CONFIG_FILE_NAMES: [&str; 3] = ["config.json", "config.toml", "0current_config.txt"];
+
 pub static FRAME_MAP: Lazy<Arc<Mutex<HashMap<i32, FrameData>>>> =
     Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+// Locks FRAME_MAP, recovering it if a thread panicked while holding the lock.
+// The map only holds whole frames, so a poisoned map is still safe to use and
+// one crashed thread must not take every capture and worker down with it.
+pub fn frame_map() -> MutexGuard<'static, HashMap<i32, FrameData>> {
+    FRAME_MAP.lock().unwrap_or_else(|poisoned| {
+        log::warn!("FRAME_MAP was poisoned by a panicking thread, recovering");
+        FRAME_MAP.clear_poison();
+        poisoned.into_inner()
+    })
+}
+// Last time each capture thread reported it is still running, by monitor id. Ticks while
+// the thread is alive even if no frame arrives, Windows.Graphics.Capture only delivers
+// frames when the screen changes and a static desktop is not a stalled capture.
+pub static CAPTURE_HEARTBEAT: Lazy<Mutex<HashMap<i32, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+pub fn capture_heartbeat(monitor_id: i32) {
+    CAPTURE_HEARTBEAT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(monitor_id, Instant::now());
+}
+// Time since the least recent heartbeat of `monitors`, None before any of them reported
+fn capture_heartbeat_age(monitors: &[SlimMonitorInfo]) -> Option<Duration> {
+    let heartbeats = CAPTURE_HEARTBEAT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    monitors.iter().filter_map(|monitor| heartbeats.get(&monitor.id)).map(Instant::elapsed).max()
+}
+// Capture rate the capture threads currently aim for, lowered while the desktop is static.
+// 0 is uncapped, CAPTURE_FPS_UNSET until the backend sets it.
+pub static CAPTURE_FPS: AtomicU32 = AtomicU32::new(CAPTURE_FPS_UNSET);
+pub const CAPTURE_FPS_UNSET: u32 = u32::MAX;
+
+// CAPTURE_FPS, or `fallback` (e.g. the rate a capture was started with) while it is unset
+pub fn capture_fps(fallback: u32) -> u32 {
+    match CAPTURE_FPS.load(Ordering::Relaxed) {
+        CAPTURE_FPS_UNSET => fallback,
+        fps => fps,
+    }
+}
+
+// Time between two frames at `fps`. 0 means uncapped, there is no interval to wait for.
+pub fn frame_interval(fps: u32) -> Option<Duration> {
+    (fps > 0).then(|| Duration::from_secs(1) / fps)
+}
+// Factor the capture threads shrink frames by before storing them in FRAME_MAP, 1 stores
+// them at monitor resolution. Set by the backend when Grab.IsCaptureDownscaleEnabled.
+pub static CAPTURE_DOWNSCALE: AtomicU32 = AtomicU32::new(1);
+
+// WLED address of the main pipeline. The environment variable lets headless and
+// container setups point at another controller without rebuilding; a config value
+// for it, once there is one, should be checked before the environment.
+pub(crate) static WLED_ADDRESS: Lazy<String> = Lazy::new(|| match env::var(WLED_ADDRESS_ENV) {
+    Ok(address) if !address.trim().is_empty() => {
+        log::info!("Using WLED address {} from {}", address.trim(), WLED_ADDRESS_ENV);
+        address.trim().to_string()
+    }
+    _ => DEFAULT_WLED_ADDRESS.to_string(),
+});
+    // Only accessed after check_config succeeded
     static CONFIG: Lazy<config::Config> = Lazy::new(|| {
-        let exe_path = env::current_exe().expect("Failed to get current exe path");
-        let defaultpath = PathBuf::from(".");
-        let config_path = exe_path
-            .parent()
-            .unwrap_or_else(|| defaultpath.as_path())
-            .join("0current_config.txt");
-        config::read_config(config_path.to_str().unwrap())
-            .expect("Failed to read config file")
+        let mut config = config::read_config(config_path().to_str().unwrap())
+            .expect("Failed to read config file");
+        // Relative and anchored LED coordinates and the LED edges need the current monitor layout
+        match get_monitor_info() {
+            Ok(monitors) => {
+                let monitors = export_monitors(&monitors);
+                // Size first, anchors place the LEDs by their size
+                config.resolve_relative_leds(desktop_rect(&monitors));
+                config.resolve_anchored_leds(&monitor_rects(&monitors));
+                config.classify_edges(desktop_rect(&monitors));
+            }
+            Err(e) => log::error!("Failed to get monitor info for the LED layout: {}", e),
+        }
+        config
     });
 
-pub fn main_program_start(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std::error::Error>> {
+// Directory the config is looked up in: next to the executable
+fn config_dir() -> PathBuf {
+    env::current_exe()
+        .ok()
+        .and_then(|exe_path| exe_path.parent().map(|dir| dir.to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// Prefer a structured config, fall back to the Prismatik profile
+pub fn config_path() -> PathBuf {
+    let config_dir = config_dir();
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| config_dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| config_dir.join("0current_config.txt"))
+}
+
+// Makes sure the config exists and parses, so a missing or broken file is reported
+// with a readable message instead of panicking a worker thread on first access
+pub fn check_config() -> Result<(), Error> {
+    read_checked_config().map(|_| ())
+}
+
+// The config at config_path, with errors worded for the user
+fn read_checked_config() -> Result<config::Config, Error> {
+    let path = config_path();
+    if !path.exists() {
+        return Err(Error::Config(format!(
+            "No config file found.\n\nPut a config.json, config.toml or a Prismatik profile named 0current_config.txt into:\n{}",
+            config_dir().display()
+        )));
+    }
+    config::read_config(path.to_str().unwrap()).map_err(|e| {
+        let message = format!("Failed to read config file {}:\n{}", path.display(), e);
+        match e {
+            Error::Validation(_) => Error::Validation(message),
+            _ => Error::Config(message),
+        }
+    })
+}
+
+// Re-reads the config and hands it to the running backend: the output settings, and the
+// LED layout of every pipeline that is still configured under the same name. The rest
+// (device, grab and capture options) is only read at startup and needs a restart.
+pub fn reload_config(shared_state: &Arc<Mutex<SharedState>>) -> Result<(), Error> {
+    let mut config = read_checked_config()?;
+    let monitors = export_monitors(&get_monitor_info()?);
+    config.resolve_relative_leds(desktop_rect(&monitors));
+    config.resolve_anchored_leds(&monitor_rects(&monitors));
+    config.classify_edges(desktop_rect(&monitors));
+
+    let mut layouts: Vec<(String, LedLayout)> = Vec::new();
+    if !config.leds_array.is_empty() {
+        layouts.push((
+            "Main".to_string(),
+            LedLayout {
+                leds: config.leds_array.clone(),
+                physical_led_count: config.Device.PhysicalLedCount as usize,
+            },
+        ));
+    }
+    for pipeline_config in &config.Pipelines {
+        let pipeline_monitors = pipeline_monitors(pipeline_config, &monitors);
+        if !pipeline_monitors.is_empty() {
+            layouts.push((
+                pipeline_config.Name.clone(),
+                LedLayout {
+                    leds: pipeline_leds(pipeline_config, &pipeline_monitors, &monitors),
+                    physical_led_count: pipeline_config.PhysicalLedCount as usize,
+                },
+            ));
+        }
+    }
+
+    let mut state = shared_state.lock().unwrap();
+    state.settings = OutputSettings::from_config(&config);
+    for pipeline in state.pipelines.iter_mut() {
+        match layouts.iter().find(|(name, _)| *name == pipeline.name) {
+            Some((_, layout)) => pipeline.pending_layout = Some(layout.clone()),
+            None => log::warn!("Pipeline {} is not in the reloaded config, keeping its LEDs", pipeline.name),
+        }
+    }
+    log::info!("Reloaded config from {}", config_path().display());
+    Ok(())
+}
+
+// Runs the capture-to-LED pipeline on a background thread, for use without the GUI.
+// Capture threads keep running until the process exits.
+pub struct CaptureController {
+    shared_state: Arc<Mutex<SharedState>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl CaptureController {
+    pub fn start() -> Self {
+        let shared_state = Arc::new(Mutex::new(SharedState::new()));
+        let backend_state = Arc::clone(&shared_state);
+        let handle = thread::spawn(move || {
+            if let Err(e) = main_program_start(backend_state) {
+                log::error!("Backend failed: {}", e);
+            }
+        });
+        CaptureController {
+            shared_state,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn set_active(&self, active: bool) {
+        self.shared_state.lock().unwrap().is_active = active;
+    }
+
+    pub fn set_mode(&self, mode: Mode) {
+        self.shared_state.lock().unwrap().mode = mode;
+    }
+
+    // Live brightness in percent on top of the configured brightness
+    pub fn set_brightness(&self, brightness: u8) {
+        self.shared_state.lock().unwrap().brightness = brightness.min(100);
+    }
+
+    // Switches the output protocol, the next frame goes to the new sink
+    pub fn set_output(&self, sink: Box<dyn OutputSink>) {
+        log::info!("Output switched to {}", sink.name());
+        let mut state = self.shared_state.lock().unwrap();
+        state.output.store(Arc::new(sink));
+        state.is_output_set = true;
+    }
+
+    // Applies the output settings and LED layouts of the config file as it is now
+    pub fn reload_config(&self) -> Result<(), Error> {
+        reload_config(&self.shared_state)
+    }
+
+    pub fn shared_state(&self) -> Arc<Mutex<SharedState>> {
+        Arc::clone(&self.shared_state)
+    }
+
+    // Asks the processing loop to finish (fading out if configured) and waits for it
+    pub fn stop(mut self) {
+        self.shared_state.lock().unwrap().is_shutdown_requested = true;
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("Backend thread panicked");
+            }
+        }
+    }
+}
+
+pub fn main_program_start(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Error> {
     // Initialize logging (optional)
     // logger::init_logger()?;
 
-    let target_fps = 24;
+    check_config()?;
+    apply_configured_averaging_threads();
+
+    // Displays may still be coming up when started with Windows
+    if CONFIG.General.StartupDelay > 0 {
+        log::info!("Waiting {} ms before enumerating monitors", CONFIG.General.StartupDelay);
+        thread::sleep(Duration::from_millis(CONFIG.General.StartupDelay as u64));
+    }
 
     // Retrieve monitor information (if needed)
-    let monitors = get_monitor_info()?;
+    let monitors = if CONFIG.General.IsWaitForStableMonitorsEnabled {
+        wait_for_stable_monitors()?
+    } else {
+        get_monitor_info()?
+    };
     println!("Monitors: {:?}", monitors);
 
-    // Start the processing thread
+    let target_fps = target_fps(&monitors);
+    log::info!("Pacing capture and processing at {} FPS", target_fps);
+    CAPTURE_FPS.store(target_fps, Ordering::Relaxed);
+    if CONFIG.Grab.IsAdaptiveFpsEnabled && CONFIG.Grab.IdleFps == 0 {
+        log::warn!("IdleFps 0 would uncap the capture while idle, using 1 FPS instead");
+    }
+
+    // Shrink frames once in the capture threads instead of in every processing loop
+    if CONFIG.Grab.IsCaptureDownscaleEnabled && CONFIG.Grab.DownscaleFactor > 1 {
+        log::info!("Capturing frames downscaled by {}", CONFIG.Grab.DownscaleFactor);
+        CAPTURE_DOWNSCALE.store(CONFIG.Grab.DownscaleFactor as u32, Ordering::Relaxed);
+    }
+
+    // The WLED JSON API takes 8 bit hex colors, higher depths need a protocol that supports them
+    if CONFIG.Device.ColorDepth > 255 {
+        log::warn!(
+            "ColorDepth {} is not supported by the WLED JSON API, sending 8 bit colors",
+            CONFIG.Device.ColorDepth
+        );
+    }
+
+    // Optional status endpoint for monitoring
+    if CONFIG.Server.IsEnabled {
+        if let Err(e) = status_server::start(&CONFIG.Server, Arc::clone(&shared_state), WLED_ADDRESS.as_str()) {
+            log::error!("Failed to start status server: {}", e);
+        }
+    }
+
+    // Start in the mode and with the output settings from the config
+    {
+        let mut state = shared_state.lock().unwrap();
+        state.mode = Mode::from_config(&CONFIG.General.LightpackMode);
+        state.settings = OutputSettings::from_config(&CONFIG);
+        // A sink set through CaptureController::set_output before the start is kept
+        if !state.is_output_set {
+            state.output.store(Arc::new(configured_output()));
+        }
+    }
+
+    // One processing loop per pipeline, the UI can toggle each of them
+    let pipelines = build_pipelines(&export_monitors(&monitors), &shared_state);
+    shared_state.lock().unwrap().pipelines = pipelines
+        .iter()
+        .map(|pipeline| PipelineState {
+            name: pipeline.name.clone(),
+            is_enabled: pipeline.is_enabled,
+            health: DeviceHealth::default(),
+            pending_layout: None,
+            is_static: false,
+        })
+        .collect();
+
+    // Start the processing threads
     let processing_start = Instant::now();
     let processing_handle = process_frames_setup_map(
-        monitors.clone().into_iter().map(|m| m.export()).collect(),
+        pipelines,
         target_fps,
         Arc::clone(&shared_state),
     );
@@ -55,52 +362,517 @@ pub fn main_program_start(shared_state: Arc<Mutex<SharedState>>) -> Result<(), B
     println!("Processing thread setup took: {:?}", processing_duration);
 
     // Start capture for each monitor
+    let _capture_handles = start_captures(monitors, target_fps);
+
+    // Processing only returns once a shutdown was requested
+    for handle in processing_handle {
+        if handle.join().is_err() {
+            log::error!("Processing thread panicked");
+        }
+    }
+    Ok(())
+}
+
+// Enumerates the monitors until two readings in a row have the same layout, giving up
+// after STABLE_MONITORS_TIMEOUT with the latest reading
+fn wait_for_stable_monitors() -> Result<Vec<MonitorInfo>, Error> {
+    let layout = |monitors: &[MonitorInfo]| -> Vec<(i32, i32, i32, i32, i32)> {
+        monitors.iter().map(|m| (m.id, m.pos_x, m.pos_y, m.width, m.height)).collect()
+    };
+    let start = Instant::now();
+    let mut monitors = get_monitor_info()?;
+    loop {
+        thread::sleep(STABLE_MONITORS_POLL);
+        let next = get_monitor_info()?;
+        if !next.is_empty() && layout(&next) == layout(&monitors) {
+            log::info!("Monitor layout stable after {:?}", start.elapsed());
+            return Ok(next);
+        }
+        if start.elapsed() >= STABLE_MONITORS_TIMEOUT {
+            log::warn!("Monitor layout still changing after {:?}, using the latest one", start.elapsed());
+            return Ok(next);
+        }
+        monitors = next;
+    }
+}
+
+// Sink of the main pipeline from the Output section, the WLED JSON API if the
+// configured one can't be opened
+fn configured_output() -> Box<dyn OutputSink> {
+    match CONFIG.Output.Target.as_str() {
+        "File" => match FileSink::open(&CONFIG.Output.Path, FrameFormat::from_config(&CONFIG.Output.Format)) {
+            Ok(sink) => {
+                log::info!("Writing colors to {}", CONFIG.Output.Path);
+                return Box::new(sink);
+            }
+            Err(e) => log::error!("Cannot open output {}, sending to WLED instead: {}", CONFIG.Output.Path, e),
+        },
+        "WledUdp" => match WledUdpSink::new(&WLED_ADDRESS, CONFIG.Output.UdpPort, CONFIG.Output.MaxPacketSize as usize) {
+            Ok(sink) => return Box::new(sink),
+            Err(e) => log::error!("Cannot open UDP output, using the JSON API instead: {}", e),
+        },
+        _ => {}
+    }
+    Box::new(WledHttpSink::new(&WLED_ADDRESS, CONFIG.Device.SegmentId))
+}
+
+// Starts one capture thread per monitor, each storing its frames in FRAME_MAP under the monitor id
+fn start_captures(monitors: Vec<MonitorInfo>, target_fps: u32) -> Vec<thread::JoinHandle<()>> {
+    let pixel_format = PixelFormat::from_config(&CONFIG.Grab.PixelFormat);
+    let cursor_capture = if CONFIG.Grab.IncludeCursor {
+        CursorCaptureSettings::WithCursor
+    } else {
+        CursorCaptureSettings::WithoutCursor
+    };
     let mut capture_handles = Vec::new();
     for (i, monitor_info) in monitors.into_iter().enumerate() {
         let monitor_handle = Monitor::from_raw_hmonitor(monitor_info.monitor);
+        let id = monitor_info.id;
+        let slim_info = monitor_info.export();
         let capture_start = Instant::now();
         let capture_handle = thread::spawn(move || {
-            let settings = Settings::new(
-                monitor_handle,
-                CursorCaptureSettings::Default,
-                DrawBorderSettings::WithoutBorder,
-                ColorFormat::Rgba8,
-                format!("{},{}", i, target_fps),
-            );
+            pin_current_thread(&CONFIG.Grab.CaptureCores, i, "Capture");
+            let settings = |format: PixelFormat| {
+                Settings::new(
+                    monitor_handle,
+                    cursor_capture,
+                    DrawBorderSettings::WithoutBorder,
+                    format.color_format(),
+                    CaptureFlags { monitor_id: id, fps_limit: target_fps }.to_string(),
+                )
+            };
 
-            // Start the capture and handle potential failures
-            if let Err(e) = Capture::start(settings) {
-                log::error!("Screen Capture Failed at monitor {}: {:?}", i, e);
-                return;
+            // Start the capture and fall back to GDI where Windows.Graphics.Capture isn't available
+            log::info!("Monitor {}:: Capturing {:?} with Windows.Graphics.Capture", id, pixel_format);
+            let mut result = run_capture(id, settings(pixel_format));
+            // Half float frames need a display and driver that support them, 8 bit works everywhere
+            if let (Err(e), PixelFormat::Rgba16F) = (&result, pixel_format) {
+                log::warn!("Monitor {}:: Rgba16F capture failed ({:?}), capturing Rgba8 instead", id, e);
+                result = run_capture(id, settings(PixelFormat::Rgba8));
             }
-            println!("Capture started for monitor {:?}", i);
+            if let Err(e) = result {
+                log::error!("Screen Capture Failed at monitor {}: {:?}", id, e);
+                log::warn!("Monitor {}:: Falling back to GDI capture", id);
+                if let Err(e) = run_gdi_capture(id, &slim_info, target_fps) {
+                    log::error!("GDI capture failed at monitor {}: {}", id, e);
+                }
+            }
+            println!("Capture ended for monitor {:?}", id);
+            // Drop the last frame whenever the capture stops so the monitor goes black
+            // instead of freezing while the other monitors keep updating
+            frame_map().remove(&id);
         });
 
         capture_handles.push(capture_handle);
         let capture_duration = capture_start.elapsed();
         println!(
             "Capture thread setup for monitor {} took: {:?}",
-            i, capture_duration
+            id, capture_duration
         );
     }
+    capture_handles
+}
+
+// Runs the capture on its own thread and beats CAPTURE_HEARTBEAT until it ends
+fn run_capture(
+    monitor_id: i32,
+    settings: Settings<String, Monitor>,
+) -> Result<(), GraphicsCaptureApiError<<Capture as GraphicsCaptureApiHandler>::Error>> {
+    let control = Capture::start_free_threaded(settings)?;
+    while !control.is_finished() {
+        capture_heartbeat(monitor_id);
+        thread::sleep(HEARTBEAT_INTERVAL);
+    }
+    control.wait()
+}
+
+// Pins the calling thread to `cores[index % cores.len()]`, nothing to do without cores.
+// Keeps the big per-frame buffers in one core's cache instead of migrating between cores.
+fn pin_current_thread(cores: &[usize], index: usize, kind: &str) {
+    if cores.is_empty() {
+        return;
+    }
+    let core = cores[index % cores.len()];
+    let available = core_affinity::get_core_ids().unwrap_or_default();
+    match available.into_iter().find(|core_id| core_id.id == core) {
+        Some(core_id) if core_affinity::set_for_current(core_id) => {
+            log::info!("{} thread {} pinned to core {}", kind, index, core);
+        }
+        Some(_) => log::warn!("{} thread {}: pinning to core {} failed", kind, index, core),
+        None => log::warn!("{} thread {}: core {} does not exist, not pinning", kind, index, core),
+    }
+}
+
+// Whether the averaging pool was sized, by the command line or the config
+static AVERAGING_THREADS_SET: AtomicBool = AtomicBool::new(false);
+
+// Sizes the worker pool the per-LED averaging runs on. Only the first call takes
+// effect, the pool can't be resized once frames were averaged on it.
+pub fn set_averaging_threads(threads: usize) -> Result<(), Error> {
+    if threads == 0 {
+        return Err(Error::Validation("Averaging needs at least 1 thread".to_string()));
+    }
+    let cores = thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1);
+    if threads > cores {
+        log::warn!("{} averaging threads requested, but only {} cores are available", threads, cores);
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("averaging-{}", index))
+        .build_global()
+        .map_err(|e| Error::Config(format!("Cannot set averaging threads: {}", e)))?;
+    AVERAGING_THREADS_SET.store(true, Ordering::Relaxed);
+    log::info!("Averaging on {} threads", threads);
     Ok(())
 }
 
-fn process_frames_setup_map(
+// Applies Grab.AveragingThreads unless the command line or an earlier call already set
+// the thread count. It only fails, with a warning, when frames were averaged before.
+fn apply_configured_averaging_threads() {
+    if CONFIG.Grab.AveragingThreads > 0 && !AVERAGING_THREADS_SET.load(Ordering::Relaxed) {
+        if let Err(e) = set_averaging_threads(CONFIG.Grab.AveragingThreads) {
+            log::warn!("Grab.AveragingThreads ignored: {}", e);
+        }
+    }
+}
+
+// Lookup tables for the monitors with a color correction or HDR tone mapping in the config
+fn monitor_corrections(monitors: &[SlimMonitorInfo]) -> HashMap<i32, MonitorCorrection> {
+    let calibrations = CONFIG.monitor_calibrations();
+    let tone_map = HdrToneMap::from_config(&CONFIG.Grab);
+    let is_hdr = |id: i32| tone_map.is_some() && (CONFIG.Grab.HdrMonitors.is_empty() || CONFIG.Grab.HdrMonitors.contains(&id));
+    monitors
+        .iter()
+        .filter(|monitor| calibrations.contains_key(&monitor.id) || is_hdr(monitor.id))
+        .map(|monitor| {
+            let calibration = calibrations.get(&monitor.id).copied().unwrap_or_default();
+            let tone_map = tone_map.as_ref().filter(|_| is_hdr(monitor.id));
+            (monitor.id, MonitorCorrection::new(&calibration, tone_map))
+        })
+        .collect()
+}
+
+// Persists the UI output settings into the config file in use
+pub fn save_output_settings(settings: &OutputSettings) -> Result<(), Error> {
+    // Round so the file doesn't end up with values like 1.6440000000000001
+    let gamma = (settings.gamma * 100.0).round() / 100.0;
+    let vibrance = (settings.vibrance as f64 * 100.0).round() / 100.0;
+    config::update_config_values(
+        config_path().to_str().unwrap(),
+        &[
+            ("Device", "IsGammaEnabled", serde_json::json!(settings.is_gamma_enabled)),
+            ("Device", "Gamma", serde_json::json!(gamma)),
+            ("Device", "IsSmoothingEnabled", serde_json::json!(settings.is_smoothing_enabled)),
+            ("Device", "Smooth", serde_json::json!(settings.smooth)),
+            ("Device", "Vibrance", serde_json::json!(vibrance)),
+            ("Grab", "IsApplyColorTemperatureEnabled", serde_json::json!(settings.is_color_temperature_enabled)),
+            ("Grab", "ColorTemperature", serde_json::json!(settings.color_temperature)),
+        ],
+    )?;
+    log::info!("Output settings saved to {}", config_path().display());
+    Ok(())
+}
+
+// Captures a single frame from all monitors and returns the averaged colors sorted by
+// LED index, without sending. Optionally saves the debug image with the colors drawn
+// over the LED regions. With `processed` the colors go through the configured
+// post-processing first, so the image shows what the strip would display; smoothing
+// has no previous frame to blend with and leaves a single frame unchanged.
+pub fn snapshot(image_path: Option<&str>, processed: bool) -> Result<Vec<Color>, Error> {
+    check_config()?;
+    apply_configured_averaging_threads();
+    let monitors = get_monitor_info()?;
+    let slim_monitors = export_monitors(&monitors);
+    let monitor_count = monitors.len();
+    let fps = target_fps(&monitors);
+    start_captures(monitors, fps);
+
+    // Wait for the first frame of every monitor
+    let wait_start = Instant::now();
+    while frame_map().len() < monitor_count {
+        if wait_start.elapsed() > SNAPSHOT_TIMEOUT {
+            return Err(Error::Capture("Timed out waiting for captured frames".to_string()));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let min_x = slim_monitors.iter().map(|m| m.pos_x).min().unwrap_or(0);
+    let min_y = slim_monitors.iter().map(|m| m.pos_y).min().unwrap_or(0);
+    let max_x = slim_monitors.iter().map(|m| m.pos_x + m.width).max().unwrap_or(0);
+    let max_y = slim_monitors.iter().map(|m| m.pos_y + m.height).max().unwrap_or(0);
+    let combined_width: i32 = slim_monitors.iter().map(|m| m.width).sum();
+    let combined_height: i32 = slim_monitors.iter().map(|m| m.height).max().unwrap_or(0);
+
+    let (combined_img, _) = combine_screens(
+        &slim_monitors,
+        combined_width as u32,
+        combined_height as u32,
+        0,
+        min_x,
+        min_y,
+        1,
+        &monitor_corrections(&slim_monitors),
+        OverlapMode::from_config(&CONFIG.Grab),
+        &mut Vec::new(),
+    )?;
+    let mut avg_colors = combined_img.calculate_avg_colors(
+        min_x,
+        min_y,
+        max_x,
+        max_y,
+        &CONFIG.leds_array,
+        &AveragingOptions::from_config(&CONFIG.Grab),
+    )?;
+
+    if processed {
+        let mut post_processor = PostProcessor::new(&CONFIG.Device);
+        post_processor.apply_settings(&OutputSettings::from_config(&CONFIG));
+        post_processor.set_led_brightness(led_brightness(&CONFIG.leds_array, 0));
+        post_processor.process(&mut avg_colors);
+    }
+
+    if let Some(path) = image_path {
+        save_screenshot_with_avg_colors(
+            &combined_img.into_rgba8(),
+            &CONFIG.leds_array,
+            &avg_colors,
+            path,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        )?;
+    }
+
+    avg_colors.sort_by(|a, b| a.led_index.cmp(&b.led_index));
+    Ok(avg_colors)
+}
+
+// Feeds the PNG frames of a directory (in file name order) through the color averaging
+// at a fixed rate, as if they were live captures. Needs neither a display nor WLED, so
+// the pipeline can be iterated on reproducibly. Frames are full desktop images; their top
+// left corner is the top left of the current monitor layout, or (0, 0) without one.
+// An fps of 0 replays as fast as the averaging allows.
+pub fn replay(
+    dir: &str,
+    fps: Option<u32>,
+    mut on_frame: impl FnMut(&Path, Vec<Color>),
+) -> Result<(), Error> {
+    check_config()?;
+    apply_configured_averaging_threads();
+    let frame_duration = frame_interval(fps.unwrap_or_else(|| target_fps(&[])));
+
+    let mut frames: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(Error::Capture(format!("No PNG frames found in {}", dir)));
+    }
+    frames.sort();
+
+    let (min_x, min_y) = match get_monitor_info() {
+        Ok(monitors) if !monitors.is_empty() => {
+            let (x, y, _, _) = desktop_rect(&export_monitors(&monitors));
+            (x, y)
+        }
+        _ => (0, 0),
+    };
+    let averaging_options = AveragingOptions::from_config(&CONFIG.Grab);
+
+    for path in frames {
+        let frame_start = Instant::now();
+        let image = image::open(&path)
+            .map_err(|e| Error::Capture(format!("Failed to load {}: {}", path.display(), e)))?
+            .to_rgba8();
+        let max_x = min_x + image.width() as i32;
+        let max_y = min_y + image.height() as i32;
+        let mut avg_colors = calculate_avg_colors(
+            &image,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            &CONFIG.leds_array,
+            &averaging_options,
+        )?;
+        avg_colors.sort_by(|a, b| a.led_index.cmp(&b.led_index));
+        on_frame(&path, avg_colors);
+
+        if let Some(remaining) = frame_duration.and_then(|duration| duration.checked_sub(frame_start.elapsed())) {
+            thread::sleep(remaining);
+        }
+    }
+    Ok(())
+}
+
+// One capture-to-strip zone at runtime
+struct Pipeline {
+    name: String,
+    is_enabled: bool,
     monitors: Vec<SlimMonitorInfo>,
+    leds: Vec<LED>,
+    address: String,
+    segment_id: u8,
+    output: SharedSink,
+    min_send_interval: Duration,
+    // LEDs the sampled colors are interpolated across, 0 = one LED per region
+    physical_led_count: usize,
+}
+
+impl Pipeline {
+    // Colors sent per frame before fitting them to the device
+    fn strip_len(&self) -> usize {
+        if self.physical_led_count > 0 {
+            self.physical_led_count
+        } else {
+            self.leds.len()
+        }
+    }
+}
+
+// The top-level LEDs form the main pipeline over all monitors, sending through the
+// swappable output in SharedState. Every configured pipeline gets its own output.
+fn build_pipelines(monitors: &[SlimMonitorInfo], shared_state: &Arc<Mutex<SharedState>>) -> Vec<Pipeline> {
+    let mut pipelines = Vec::new();
+    if !CONFIG.leds_array.is_empty() {
+        pipelines.push(Pipeline {
+            name: "Main".to_string(),
+            is_enabled: true,
+            monitors: monitors.to_vec(),
+            leds: CONFIG.leds_array.clone(),
+            address: WLED_ADDRESS.to_string(),
+            segment_id: CONFIG.Device.SegmentId,
+            output: Arc::clone(&shared_state.lock().unwrap().output),
+            min_send_interval: Duration::from_millis(CONFIG.Device.MinSendInterval as u64),
+            physical_led_count: CONFIG.Device.PhysicalLedCount as usize,
+        });
+    }
+
+    for pipeline_config in &CONFIG.Pipelines {
+        let pipeline_monitors = pipeline_monitors(pipeline_config, monitors);
+        if pipeline_monitors.is_empty() {
+            log::error!(
+                "Pipeline {} has none of its monitors {:?} connected, skipping it",
+                pipeline_config.Name,
+                pipeline_config.Monitors
+            );
+            continue;
+        }
+        let leds = pipeline_leds(pipeline_config, &pipeline_monitors, monitors);
+        pipelines.push(Pipeline {
+            name: pipeline_config.Name.clone(),
+            is_enabled: pipeline_config.IsEnabled,
+            monitors: pipeline_monitors,
+            leds,
+            address: pipeline_config.Address.clone(),
+            segment_id: pipeline_config.SegmentId,
+            output: shared_sink(Box::new(WledHttpSink::new(&pipeline_config.Address, pipeline_config.SegmentId))),
+            min_send_interval: Duration::from_millis(
+                pipeline_config.MinSendInterval.unwrap_or(CONFIG.Device.MinSendInterval) as u64,
+            ),
+            physical_led_count: pipeline_config.PhysicalLedCount as usize,
+        });
+    }
+    pipelines
+}
+
+// Monitors a pipeline captures, all of them if it doesn't name any
+fn pipeline_monitors(pipeline_config: &config::Pipeline, monitors: &[SlimMonitorInfo]) -> Vec<SlimMonitorInfo> {
+    monitors
+        .iter()
+        .filter(|m| pipeline_config.Monitors.is_empty() || pipeline_config.Monitors.contains(&m.id))
+        .cloned()
+        .collect()
+}
+
+// LEDs of a pipeline in desktop coordinates. Relative LEDs span the pipeline's own
+// monitors, anchors may name any monitor.
+fn pipeline_leds(
+    pipeline_config: &config::Pipeline,
+    pipeline_monitors: &[SlimMonitorInfo],
+    monitors: &[SlimMonitorInfo],
+) -> Vec<LED> {
+    let mut leds = pipeline_config.leds_array.clone();
+    config::resolve_relative_leds(&mut leds, desktop_rect(pipeline_monitors));
+    config::resolve_anchored_leds(&mut leds, &monitor_rects(monitors));
+    config::classify_edges(&mut leds, desktop_rect(pipeline_monitors));
+    leds
+}
+
+// LED.Brightness by the index the colors are sent with. With a physical LED count the
+// factors are carried as gray colors through the same interpolation as the frames.
+fn led_brightness(leds: &[LED], physical_led_count: usize) -> HashMap<i32, f32> {
+    let mut factors: Vec<Color> = leds
+        .iter()
+        .map(|led| Color::new(led.index, led.Brightness, led.Brightness, led.Brightness))
+        .collect();
+    factors.sort_by_key(|factor| factor.led_index);
+    if physical_led_count > 0 {
+        factors = arduino::interpolate_pixels(&factors, physical_led_count);
+    }
+    factors
+        .into_iter()
+        .filter(|factor| factor.r != 1.0)
+        .map(|factor| (factor.led_index, factor.r))
+        .collect()
+}
+
+// The UI can stop everything or single pipelines
+fn is_pipeline_active(state: &SharedState, index: usize) -> bool {
+    state.is_active && state.pipelines.get(index).map_or(true, |pipeline| pipeline.is_enabled)
+}
+
+// Records whether a pipeline's picture is static and sets the capture rate for all of
+// them: IdleFps once every active pipeline is static, `target_fps` as soon as one isn't.
+// The capture is shared, so one pipeline going idle must not slow down the others.
+fn update_capture_fps(state: &mut SharedState, thread_num: usize, is_static: bool, target_fps: u32) {
+    if let Some(pipeline) = state.pipelines.get_mut(thread_num) {
+        pipeline.is_static = is_static;
+    }
+    let all_static = if state.pipelines.is_empty() {
+        is_static
+    } else {
+        (0..state.pipelines.len())
+            .filter(|&i| is_pipeline_active(state, i))
+            .all(|i| state.pipelines[i].is_static)
+    };
+    // 0 would mean uncapped, see frame_interval
+    let fps = if all_static { CONFIG.Grab.IdleFps.max(1) } else { target_fps };
+    if CAPTURE_FPS.swap(fps, Ordering::Relaxed) != fps {
+        log::info!("Capture FPS set to {}", fps);
+    }
+}
+
+fn process_frames_setup_map(
+    pipelines: Vec<Pipeline>,
     target_fps: u32,
     shared_state: Arc<Mutex<SharedState>>
 ) -> Vec<thread::JoinHandle<()>> {
-    let num_threads = 1; // Number of threads for processing
-    let mut handles = Vec::with_capacity(num_threads);
+    let mut handles = Vec::with_capacity(pipelines.len());
+    let overlap = OverlapMode::from_config(&CONFIG.Grab);
 
-    let combined_monitor_width: i32 = monitors.iter().map(|m| m.width).sum();
-    let combined_monitor_height: i32 = monitors.iter().map(|m| m.height).max().unwrap();
+    for (thread_num, mut pipeline) in pipelines.into_iter().enumerate() {
+        log::info!("Thread {}:: Running pipeline {} on {} monitor(s)", thread_num, pipeline.name, pipeline.monitors.len());
+        let combined_monitor_width: i32 = pipeline.monitors.iter().map(|m| m.width).sum();
+        let combined_monitor_height: i32 = pipeline.monitors.iter().map(|m| m.height).max().unwrap();
+
+        // Mirrored or oddly placed displays cover each other in the combined image
+        for o in find_monitor_overlaps(&pipeline.monitors) {
+            log::warn!(
+                "Monitors {} and {} overlap in a {}x{} area at ({}, {}), resolved with {:?}",
+                o.first, o.second, o.width, o.height, o.x, o.y, overlap
+            );
+        }
 
-    for thread_num in 0..num_threads {
-        let value: Vec<SlimMonitorInfo> = monitors.clone();
+        let value: Vec<SlimMonitorInfo> = pipeline.monitors.clone();
         let shared_state = Arc::clone(&shared_state);
         let handle = thread::spawn(move || {
+            pin_current_thread(&CONFIG.Grab.ProcessingCores, thread_num, "Processing");
             let min_x = value.iter().map(|mi| mi.pos_x).min().unwrap_or(0);
             let min_y = value.iter().map(|mi| mi.pos_y).min().unwrap_or(0);
             let max_x = value
@@ -121,91 +893,572 @@ fn process_frames_setup_map(
                 max_y
             );
 
+            // LED regions in the coordinates of the downscaled frames
+            let scale = CONFIG.Grab.DownscaleFactor.max(1) as u32;
+            let mut scaled_leds = scale_leds(&pipeline.leds, scale);
+            let corrections = monitor_corrections(&value);
+            let mut averaging_options = AveragingOptions::from_config(&CONFIG.Grab);
+            averaging_options.sampling_depth /= scale as i32;
+            averaging_options.sampling_gap /= scale as i32;
+            // At least one pixel, a small radius would vanish in downscaled frames
+            let mut blur = CONFIG.Grab.IsBlurEnabled.then(|| BoxBlur::new((CONFIG.Grab.BlurRadius as u32 / scale).max(1)));
+
+            let transition_duration =
+                Duration::from_millis(CONFIG.General.TransitionDuration as u64);
+            let mood_color = parse_hex_color(&CONFIG.MoodLamp.Color).unwrap_or_else(|e| {
+                log::error!("Invalid MoodLamp color, using white: {}", e);
+                (255, 255, 255)
+            });
+            let mut current_mode = shared_state.lock().unwrap().mode;
+            if current_mode == Mode::EffectPassthrough {
+                if let Err(e) = arduino::release_control(&pipeline.address, pipeline.segment_id) {
+                    log::error!("Thread {}:: Failed to start the passthrough effect: {}", thread_num, e);
+                }
+            }
+            let mut last_sent: Vec<Color> = Vec::new();
+            let mut transition: Option<(Instant, Vec<Color>)> = None;
+            let mut audio_meter: Option<AudioMeter> = None;
+            let mut audio_failed = false; // Retried on the next mode switch
+            let mut last_capture: Vec<Color> = Vec::new();
+            let offscreen_leds = OffscreenLeds::from_config(&CONFIG.Grab);
+            // Raw averages of the previous frame, for OffscreenLeds::Hold
+            let mut previous_raw: Vec<Color> = Vec::new();
+            let mut last_luminance = 0.0;
+            let mut held_frames = 0;
+            let mut static_frames = 0;
+            let mut post_processor = PostProcessor::new(&CONFIG.Device);
+            post_processor.set_led_brightness(led_brightness(&pipeline.leds, pipeline.physical_led_count));
+            let mut smoother = Smoother::new(&CONFIG.Device);
+            let palette_lock = PaletteLock::new(&CONFIG.Palette);
+            let mirror = Mirror::from_config(&CONFIG.Grab);
+            let mut corner_blend = CONFIG.Grab.IsCornerBlendingEnabled.then(|| CornerBlend::new(&pipeline.leds));
+            let mut auto_exposure = AutoExposure::new(&CONFIG.Grab);
+            let idle_effect = IdleEffect::from_config(&CONFIG.General.IdleEffect);
+            let idle_color = parse_hex_color(&CONFIG.General.IdleColor).unwrap_or_else(|e| {
+                log::error!("Invalid IdleColor, using warm white: {}", e);
+                (255, 140, 60)
+            });
+            let output = Arc::clone(&pipeline.output);
+            let mut applied_settings: Option<OutputSettings> = None;
+            let mut device_led_count = query_led_count(&pipeline.address, pipeline.segment_id, pipeline.strip_len(), thread_num);
+            // Re-query the LED count once sends succeed again after a failure
+            let mut reconnect_pending = device_led_count.is_none();
+            let mut reconnector = Reconnector::default();
+            // The device answered, show it with the boot animation before the first frame
+            if let Some(count) = device_led_count {
+                if is_pipeline_active(&shared_state.lock().unwrap(), thread_num) {
+                    run_boot_animation(&output, &mut post_processor, count, thread_num);
+                }
+            }
+            let mut fps_window_start = Instant::now();
+            let mut fps_window_frames = 0;
+            let min_send_interval = pipeline.min_send_interval;
+            let effect_update_interval = Duration::from_millis(CONFIG.EffectPassthrough.UpdateInterval as u64);
+            let mut health = DeviceHealth::default();
+            let mut last_send: Option<Instant> = None;
+            let frame_deadline = (CONFIG.Grab.FrameDeadline > 0).then(|| Duration::from_millis(CONFIG.Grab.FrameDeadline as u64));
+            let mut deadline_missed = false;
+            let capture_timeout = Duration::from_millis(CONFIG.Grab.CaptureTimeout as u64);
+            let timeout_action = CaptureTimeoutAction::from_config(&CONFIG.Grab.CaptureTimeoutAction);
+            let no_signal_color = parse_hex_color(&CONFIG.Grab.NoSignalColor).unwrap_or_else(|e| {
+                log::error!("Invalid NoSignalColor, using dark grey: {}", e);
+                (32, 32, 32)
+            });
+            let mut stalled_since: Option<Instant> = None;
+            let keepalive_interval = (CONFIG.Device.KeepaliveInterval > 0)
+                .then(|| Duration::from_secs(CONFIG.Device.KeepaliveInterval as u64));
+            // Raw averages of the last frame and the frame timestamps they were made from
+            let mut previous_averages: Vec<Color> = Vec::new();
+            let mut dirty_tiles = DirtyTiles::new();
+            let mut frames_since_full_average = 0;
+            // Monitors that made it into this pipeline's last combined image
+            let mut contributing_monitors: Vec<i32> = Vec::new();
+            // The shader has no ignore-color filter and only the plain mean, those setups stay on the CPU
+            let mut gpu_averager = if CONFIG.Grab.IsGpuAveragingEnabled
+                && averaging_options.ignore_color.is_none()
+                && averaging_options.mode == AveragingMode::Mean
+            {
+                GpuAverager::new()
+            } else {
+                None
+            };
+
             loop {
 
                 let loop_start = Instant::now(); // Start timing the loop
+                let mut captured_at: Option<Instant> = None; // Age of the captured frame, screen modes only
 
-                let combined_img = combine_screens(
-                    &value,
-                    combined_monitor_width as u32,
-                    combined_monitor_height as u32,
-                    thread_num as u32,
-                    min_x,
-                    min_y,
-                )
-                .unwrap();
-
-                let avg_colors_start = Instant::now();
-                let mut avg_colors = calculate_avg_colors(
-                    &combined_img,
-                    min_x,
-                    min_y,
-                    max_x,
-                    max_y,
-                    &CONFIG.leds_array,
-                )
-                .unwrap();
-                let avg_colors_duration = avg_colors_start.elapsed();
-                log::info!(
-                    "Thread {}:: Average color calculation took: {:?}",
-                    thread_num,
-                    avg_colors_duration
-                );
+                // Pick up settings changed in the UI
+                let settings = shared_state.lock().unwrap().settings;
+                if applied_settings != Some(settings) {
+                    post_processor.apply_settings(&settings);
+                    smoother.set_smooth(settings.smooth);
+                    smoother.set_enabled(settings.is_smoothing_enabled);
+                    applied_settings = Some(settings);
+                }
 
-                // Sort the average colors by LED index
-                let avg_colors_start = Instant::now();
-                avg_colors.sort_by(|a, b| a.led_index.cmp(&b.led_index));
-                let avg_colors_duration = avg_colors_start.elapsed();
-                log::info!(
-                    "Thread {}:: Average color sorting took: {:?}",
-                    thread_num,
-                    avg_colors_duration
-                );
+                // A reloaded config replaces the LED layout, state kept per LED starts over
+                let pending_layout = shared_state
+                    .lock()
+                    .unwrap()
+                    .pipelines
+                    .get_mut(thread_num)
+                    .and_then(|state| state.pending_layout.take());
+                if let Some(layout) = pending_layout {
+                    log::info!("Thread {}:: Applying {} LEDs from the reloaded config", thread_num, layout.leds.len());
+                    let strip_changed = layout.leds.len() != pipeline.leds.len()
+                        || layout.physical_led_count != pipeline.physical_led_count;
+                    pipeline.leds = layout.leds;
+                    pipeline.physical_led_count = layout.physical_led_count;
+                    scaled_leds = scale_leds(&pipeline.leds, scale);
+                    if corner_blend.is_some() {
+                        corner_blend = Some(CornerBlend::new(&pipeline.leds));
+                    }
+                    post_processor.set_led_brightness(led_brightness(&pipeline.leds, pipeline.physical_led_count));
+                    post_processor.reset_dither();
+                    smoother.reset();
+                    previous_raw.clear();
+                    previous_averages.clear();
+                    dirty_tiles = DirtyTiles::new();
+                    last_capture.clear();
+                    // The device may have been resized along with the config
+                    if strip_changed {
+                        device_led_count = query_led_count(&pipeline.address, pipeline.segment_id, pipeline.strip_len(), thread_num);
+                        reconnect_pending = device_led_count.is_none();
+                    }
+                }
 
-                // Send average colors as pixels to WLED
-                log::info!("Thread {}:: Sending average colors as pixels", thread_num);
-                let send_start = Instant::now();
-                let result = arduino::set_pixels("192.168.0.28", avg_colors);
-                let send_duration = send_start.elapsed();
-                match result {
-                    Ok(_) => log::info!(
-                        "Average colors set as pixels, sending took: {:?}",
-                        send_duration
-                    ),
-                    Err(e) => log::error!("Error in setting average colors as pixels: {}", e),
+                // Crossfade from the last sent frame when the UI switches modes
+                let mode = shared_state.lock().unwrap().mode;
+                if mode != current_mode {
+                    log::info!("Thread {}:: Mode changed to {:?}", thread_num, mode);
+                    if !transition_duration.is_zero() && !last_sent.is_empty() {
+                        transition = Some((Instant::now(), last_sent.clone()));
+                    }
+                    // The passthrough effect needs the segment unfrozen to animate, realtime pixels need it frozen
+                    if current_mode == Mode::EffectPassthrough {
+                        if let Err(e) = arduino::acquire_control(&pipeline.address, pipeline.segment_id) {
+                            log::error!("Thread {}:: Failed to stop the passthrough effect: {}", thread_num, e);
+                        }
+                    }
+                    if mode == Mode::EffectPassthrough {
+                        if let Err(e) = arduino::release_control(&pipeline.address, pipeline.segment_id) {
+                            log::error!("Thread {}:: Failed to start the passthrough effect: {}", thread_num, e);
+                        }
+                    }
+                    current_mode = mode;
+                    audio_failed = false;
+                }
+
+                // A late frame breaks out here and goes straight to the pacing below
+                'frame: {
+                    let mut avg_colors = match current_mode {
+                        Mode::Ambilight | Mode::FocusFollow | Mode::EffectPassthrough => {
+                            // In focus mode the LED layout is squeezed onto the foreground window
+                            let focus_leds;
+                            let leds = match current_mode {
+                                Mode::FocusFollow => match focus_rect(min_x, min_y, max_x, max_y) {
+                                    Some(rect) => {
+                                        let desktop = (min_x, min_y, max_x - min_x, max_y - min_y);
+                                        focus_leds = scale_leds(&map_leds_to_rect(&pipeline.leds, desktop, rect), scale);
+                                        &focus_leds
+                                    }
+                                    None => &scaled_leds,
+                                },
+                                _ => &scaled_leds,
+                            };
+
+                            let combine_start = Instant::now();
+                            let (mut combined_img, frame_captured_at) = combine_screens(
+                                &value,
+                                combined_monitor_width as u32,
+                                combined_monitor_height as u32,
+                                thread_num as u32,
+                                min_x,
+                                min_y,
+                                scale,
+                                &corrections,
+                                overlap,
+                                &mut contributing_monitors,
+                            )
+                            .unwrap();
+                            if let Some(blur) = &mut blur {
+                                combined_img.blur(blur);
+                            }
+                            METRICS.record(Stage::Combine, combine_start.elapsed());
+                            captured_at = frame_captured_at;
+
+                            // A frame that is already late is dropped before it is averaged instead of
+                            // delaying the next one as well, unless the previous frame was dropped too
+                            // so the strip never starves
+                            let frame_late = frame_deadline.is_some_and(|deadline| !deadline_missed && loop_start.elapsed() > deadline);
+                            deadline_missed = frame_late;
+                            if frame_late {
+                                log::debug!(
+                                    "Thread {}:: Frame took {:?} to capture, over the {:?} deadline, dropping it",
+                                    thread_num,
+                                    loop_start.elapsed(),
+                                    frame_deadline.unwrap_or_default()
+                                );
+                                shared_state.lock().unwrap().dropped_frames += 1;
+                                break 'frame;
+                            }
+
+                            if let Some(auto_exposure) = &mut auto_exposure {
+                                auto_exposure.update(&combined_img.brightness_histogram(AUTO_EXPOSURE_SAMPLE_STEP));
+                            }
+
+                            let avg_colors_start = Instant::now();
+                            let (min_x, min_y, max_x, max_y) = (
+                                min_x.div_euclid(scale as i32),
+                                min_y.div_euclid(scale as i32),
+                                max_x.div_euclid(scale as i32),
+                                max_y.div_euclid(scale as i32),
+                            );
+                            // Focus mode moves the LED regions every frame, so it always averages everything
+                            let dirty = if CONFIG.Grab.IsDirtyRegionsEnabled {
+                                let dirty = dirty_tiles.update(&combined_img, min_x, min_y);
+                                frames_since_full_average = (frames_since_full_average + 1) % FULL_AVERAGE_INTERVAL;
+                                dirty.filter(|_| current_mode == Mode::Ambilight && frames_since_full_average != 0)
+                            } else {
+                                None
+                            };
+                            let mut avg_colors = match &dirty {
+                                Some(dirty) => combined_img.update_avg_colors(
+                                    min_x, min_y, max_x, max_y, leds, &averaging_options, dirty, &previous_averages,
+                                ),
+                                None => {
+                                    // The shader reads 8 bit textures, f32 images of half float frames stay on the CPU
+                                    let gpu_result = gpu_averager
+                                        .as_mut()
+                                        .zip(combined_img.as_rgba8())
+                                        .map(|(gpu, image)| gpu.average(image, min_x, min_y, leds, &averaging_options));
+                                    match gpu_result {
+                                        Some(Ok(colors)) => Ok(colors),
+                                        Some(Err(e)) => {
+                                            log::error!("Thread {}:: GPU averaging failed, switching to the CPU: {}", thread_num, e);
+                                            gpu_averager = None;
+                                            combined_img.calculate_avg_colors(min_x, min_y, max_x, max_y, leds, &averaging_options)
+                                        }
+                                        None => combined_img.calculate_avg_colors(min_x, min_y, max_x, max_y, leds, &averaging_options),
+                                    }
+                                }
+                            }
+                            .unwrap();
+                            if offscreen_leds == OffscreenLeds::Hold {
+                                let offscreen = offscreen_led_indices(min_x, min_y, max_x, max_y, leds, &averaging_options);
+                                hold_offscreen_colors(&mut avg_colors, &offscreen, &previous_raw);
+                                previous_raw = avg_colors.clone();
+                            }
+                            METRICS.record(Stage::Average, avg_colors_start.elapsed());
+                            // Averages of the focus regions can't stand in for the Ambilight ones
+                            if CONFIG.Grab.IsDirtyRegionsEnabled {
+                                previous_averages = if current_mode == Mode::Ambilight { avg_colors.clone() } else { Vec::new() };
+                            }
+                            if let Some(mirror) = &mirror {
+                                mirror.apply(leds, &mut avg_colors);
+                            }
+                            if let Some(corner_blend) = &corner_blend {
+                                corner_blend.apply(&mut avg_colors);
+                            }
+                            if let Some(auto_exposure) = &auto_exposure {
+                                auto_exposure.apply(&mut avg_colors);
+                            }
+
+                            // Drop to the idle rate after a while without visible change
+                            if CONFIG.Grab.IsAdaptiveFpsEnabled {
+                                if color_difference(&last_capture, &avg_colors) < CONFIG.Grab.StaticFrameThreshold {
+                                    static_frames += 1;
+                                } else {
+                                    static_frames = 0;
+                                }
+                                let is_static = static_frames >= CONFIG.Grab.StaticFramesBeforeIdle;
+                                update_capture_fps(&mut shared_state.lock().unwrap(), thread_num, is_static, target_fps);
+                            }
+
+                            // Hold the previous colors if the capture glitches to black
+                            let luminance = average_luminance(&avg_colors);
+                            let threshold = CONFIG.Grab.BlackFrameThreshold as f32;
+                            let colors = if luminance <= threshold
+                                && last_luminance > threshold
+                                && held_frames < CONFIG.Grab.BlackFrameHoldFrames
+                                && last_capture.len() == avg_colors.len()
+                            {
+                                held_frames += 1;
+                                log::warn!("Thread {}:: Black frame detected, holding previous colors", thread_num);
+                                last_capture.clone()
+                            } else {
+                                held_frames = 0;
+                                last_luminance = luminance;
+                                last_capture = avg_colors.clone();
+                                avg_colors
+                            };
+
+                            // A capture thread stopped reporting (display sleep, resolution change)
+                            let is_stalled = !capture_timeout.is_zero()
+                                && capture_heartbeat_age(&value).is_some_and(|age| age > capture_timeout);
+                            if is_stalled {
+                                let since = *stalled_since.get_or_insert_with(|| {
+                                    log::warn!("Thread {}:: Capture stalled, applying {:?}", thread_num, timeout_action);
+                                    Instant::now()
+                                });
+                                capture_timeout_colors(&colors, timeout_action, no_signal_color, since.elapsed(), transition_duration)
+                            } else {
+                                if stalled_since.take().is_some() {
+                                    log::info!("Thread {}:: Capture resumed", thread_num);
+                                }
+                                colors
+                            }
+                        }
+                        Mode::MoodLamp => {
+                            static_colors(&pipeline.leds, mood_color.0, mood_color.1, mood_color.2)
+                        }
+                        Mode::SoundVisualizer => {
+                            // Open the audio stream on first use, it stays open afterwards
+                            if audio_meter.is_none() && !audio_failed {
+                                audio_meter = match AudioMeter::start(CONFIG.SoundVisualizer.Device) {
+                                    Ok(meter) => Some(meter),
+                                    Err(e) => {
+                                        log::error!("Audio capture failed: {}", e);
+                                        audio_failed = true;
+                                        None
+                                    }
+                                };
+                            }
+                            let level = audio_meter.as_mut().map(|m| m.level()).unwrap_or(0.0);
+                            visualizer_colors(&pipeline.leds, &CONFIG.SoundVisualizer, level)
+                        }
+                    };
+
+                    // Sort the average colors by LED index
+                    let avg_colors_start = Instant::now();
+                    avg_colors.sort_by(|a, b| a.led_index.cmp(&b.led_index));
+                    METRICS.record(Stage::Sort, avg_colors_start.elapsed());
+
+                    // Spread the sampled colors over the physical strip
+                    if pipeline.physical_led_count > 0 {
+                        avg_colors = arduino::interpolate_pixels(&avg_colors, pipeline.physical_led_count);
+                    }
+
+                    // Smooth gradual changes when enabled, scene cuts pass through directly
+                    smoother.apply(&mut avg_colors);
+
+                    // Blend the previous mode's last frame into the new one
+                    if let Some((start, from)) = &transition {
+                        let t = start.elapsed().as_secs_f32() / transition_duration.as_secs_f32();
+                        if t >= 1.0 || from.len() != avg_colors.len() {
+                            transition = None;
+                        } else {
+                            avg_colors = from
+                                .iter()
+                                .zip(avg_colors.iter())
+                                .map(|(a, b)| a.blend(b, t, CONFIG.Device.IsPerceptualSmoothingEnabled))
+                                .collect();
+                        }
+                    }
+                    last_sent = avg_colors.clone();
+
+                    // Gamma, brightness and dithering for the output
+                    post_processor.set_live_brightness(shared_state.lock().unwrap().brightness);
+                    post_processor.process(&mut avg_colors);
+                    if let Some(palette_lock) = &palette_lock {
+                        palette_lock.apply(&mut avg_colors);
+                        post_processor.limit_power(&mut avg_colors);
+                    }
+
+                    // Match the strip length of the device
+                    if let Some(count) = device_led_count {
+                        arduino::fit_pixel_count(&mut avg_colors, count);
+                    }
+
+                    // Rate limit the sends for controllers that flicker when flooded. Skipped
+                    // frames are not queued, the next send simply carries the latest colors.
+                    // The passthrough effect animates on its own and only needs an occasional new color.
+                    let send_interval = match current_mode {
+                        Mode::EffectPassthrough => min_send_interval.max(effect_update_interval),
+                        _ => min_send_interval,
+                    };
+                    let send_due = last_send.map_or(true, |last| last.elapsed() >= send_interval);
+
+                    // A sink swapped in from the UI doesn't wait for the old one's reconnect
+                    if reconnector.is_running() && !reconnector.is_for(&output.load()) {
+                        reconnector.cancel();
+                    }
+
+                    // A device that keeps failing is left alone for a while, the other
+                    // pipelines run on their own threads and are not held up by it. Sends
+                    // also wait while the device is being reconnected.
+                    if send_due && health.is_available() && !reconnector.is_running() {
+                        last_send = Some(Instant::now());
+
+                        // Send average colors to the active output, or tint the effect with them
+                        let sink = output.load();
+                        let send_start = Instant::now();
+                        let result = match current_mode {
+                            Mode::EffectPassthrough => {
+                                log::trace!("Thread {}:: Sending effect color to {}", thread_num, pipeline.address);
+                                arduino::set_effect_color(
+                                    &pipeline.address,
+                                    pipeline.segment_id,
+                                    CONFIG.EffectPassthrough.EffectId,
+                                    &dominant_color(&avg_colors),
+                                )
+                            }
+                            _ => {
+                                log::trace!("Thread {}:: Sending average colors to {}", thread_num, sink.name());
+                                sink.send(&avg_colors)
+                            }
+                        };
+                        let send_duration = send_start.elapsed();
+                        METRICS.record(Stage::Send, send_duration);
+                        match result {
+                            Ok(_) => {
+                                health.record_success(send_duration);
+                                if reconnect_pending {
+                                    device_led_count = query_led_count(&pipeline.address, pipeline.segment_id, pipeline.strip_len(), thread_num);
+                                    reconnect_pending = false;
+                                }
+                                // Capture to LED: everything from the frame arriving until WLED accepted it
+                                if let Some(captured_at) = captured_at {
+                                    let latency = captured_at.elapsed();
+                                    log::trace!("Thread {}:: End-to-end latency: {:?}", thread_num, latency);
+                                    shared_state.lock().unwrap().latency = Some(latency);
+                                }
+                            }
+                            Err(e) => {
+                                log::error!("Error in setting average colors as pixels: {}", e);
+                                shared_state.lock().unwrap().dropped_frames += 1;
+                                reconnect_pending = true;
+                                health.record_failure(CONFIG.Device.CircuitBreakerThreshold);
+                                let reconnect_threshold = CONFIG.Device.ReconnectThreshold;
+                                if reconnect_threshold > 0 && health.consecutive_failures % reconnect_threshold == 0 {
+                                    log::warn!(
+                                        "Thread {}:: {} failed {} times in a row, reconnecting",
+                                        thread_num,
+                                        sink.name(),
+                                        health.consecutive_failures
+                                    );
+                                    reconnector.start(Arc::clone(&sink));
+                                }
+                                if let Some(backoff) = health.backoff_remaining() {
+                                    log::warn!(
+                                        "Thread {}:: {} failed {} times in a row, pausing it for {:?}",
+                                        thread_num,
+                                        pipeline.name,
+                                        health.consecutive_failures,
+                                        backoff
+                                    );
+                                }
+                            }
+                        }
+                        if let Some(state) = shared_state.lock().unwrap().pipelines.get_mut(thread_num) {
+                            state.health = health.clone();
+                        }
+                        fps_window_frames += 1;
+                    }
+                }
+
+                // Publish the output rate once per second
+                let fps_window = fps_window_start.elapsed();
+                if fps_window >= Duration::from_secs(1) {
+                    shared_state.lock().unwrap().fps = fps_window_frames as f32 / fps_window.as_secs_f32();
+                    fps_window_start = Instant::now();
+                    fps_window_frames = 0;
                 }
 
                 let loop_duration = loop_start.elapsed();
-                log::warn!(
-                    "Thread {}:: Loop iteration took: {:?}",
-                    thread_num,
-                    loop_duration
-                );
+                METRICS.record(Stage::Loop, loop_duration);
+                if let Some(deadline) = frame_deadline.filter(|deadline| loop_duration > *deadline) {
+                    log::debug!("Thread {}:: Late frame, loop took {:?} of a {:?} budget", thread_num, loop_duration, deadline);
+                }
 
-                // // Wait till the allocated time for the loop is over
-                // let frame_duration = Duration::from_secs_f32(1.0 / target_fps as f32);
-                // let remaining = frame_duration
-                //     .checked_sub(loop_duration)
-                //     .unwrap_or(Duration::from_secs(0));
-                // if remaining.as_secs_f32() > 0.0 {
-                //     thread::sleep(remaining);
-                // }
+                // The processing follows the capture rate instead of spinning, which is the
+                // display refresh rate unless ActiveFps or adaptive FPS override it. At 0 the
+                // capture is uncapped and the loop runs as fast as it can.
+                if let Some(frame_duration) = frame_interval(capture_fps(target_fps)) {
+                    if let Some(remaining) = frame_duration.checked_sub(loop_duration) {
+                        thread::sleep(remaining);
+                    }
+                }
 
                 
                 // Stop Loop if requested by the UI
                 let state = shared_state.lock().unwrap();
                 // Log activation/deactivation
-                if state.is_active {
+                if is_pipeline_active(&state, thread_num) {
                     log::info!("Backend activated");
                 } else {
                     drop(state); // Unlock the mutex before sleeping
-                    while !shared_state.lock().unwrap().is_active {
-                        log::info!("Thread {}:: Backend deactivated", thread_num);
-                        // sleep 500ms
-                        thread::sleep(Duration::from_millis(500));
+                    match idle_effect {
+                        IdleEffect::Off => {
+                            if let Err(e) = arduino::release_control(&pipeline.address, pipeline.segment_id) {
+                                log::error!("Thread {}:: Failed to release WLED: {}", thread_num, e);
+                            }
+                        }
+                        IdleEffect::FadeToBlack => {
+                            fade_out(&last_sent, transition_duration, &mut post_processor, &output);
+                            last_sent = static_colors(&pipeline.leds, 0, 0, 0);
+                        }
+                        IdleEffect::Static | IdleEffect::Breathing => {}
+                    }
+                    log::info!("Thread {}:: Backend deactivated", thread_num);
+                    let idle_start = Instant::now();
+                    let mut idle_frame_sent = false;
+                    let mut last_keepalive = Instant::now();
+                    loop {
+                        let state = shared_state.lock().unwrap();
+                        if is_pipeline_active(&state, thread_num) || state.is_shutdown_requested {
+                            break;
+                        }
+                        drop(state);
+
+                        // Static only needs one frame, breathing keeps animating
+                        let animate = idle_effect == IdleEffect::Breathing
+                            || (idle_effect == IdleEffect::Static && !idle_frame_sent);
+                        if animate {
+                            let mut frame = idle_colors(&pipeline.leds, idle_effect, idle_color, idle_start.elapsed());
+                            last_sent = frame.clone();
+                            post_processor.process(&mut frame);
+                            if let Err(e) = output.load().send(&frame) {
+                                log::error!("Thread {}:: Failed to send idle effect: {}", thread_num, e);
+                            }
+                            idle_frame_sent = true;
+                        } else if keepalive_interval.is_some_and(|interval| last_keepalive.elapsed() >= interval) {
+                            last_keepalive = Instant::now();
+                            let result = if CONFIG.Device.IsKeepaliveResendEnabled {
+                                let mut frame = last_sent.clone();
+                                post_processor.process(&mut frame);
+                                output.load().send(&frame)
+                            } else {
+                                arduino::keepalive(&pipeline.address)
+                            };
+                            if let Err(e) = result {
+                                log::warn!("Thread {}:: Keepalive failed: {}", thread_num, e);
+                            }
+                        }
 
+                        if idle_effect == IdleEffect::Breathing {
+                            thread::sleep(IDLE_FRAME_INTERVAL);
+                        } else {
+                            // sleep 500ms
+                            thread::sleep(Duration::from_millis(500));
+                        }
+                    }
+                    if is_pipeline_active(&shared_state.lock().unwrap(), thread_num) && idle_effect == IdleEffect::Off {
+                        if let Err(e) = arduino::acquire_control(&pipeline.address, pipeline.segment_id) {
+                            log::error!("Thread {}:: Failed to acquire WLED: {}", thread_num, e);
+                        }
                     }
                 }
+
+                // Leave the loop on shutdown (Ctrl+C), optionally fading the strip out first
+                if shared_state.lock().unwrap().is_shutdown_requested {
+                    log::info!("Thread {}:: Shutting down", thread_num);
+                    if CONFIG.General.IsFadeOutOnExitEnabled {
+                        fade_out(&last_sent, transition_duration, &mut post_processor, &output);
+                    }
+                    break;
+                }
             }
         });
         handles.push(handle);
@@ -213,19 +1466,305 @@ fn process_frames_setup_map(
     handles
 }
 
+// ActiveFps when set, otherwise the highest refresh rate among the monitors so the
+// lighting updates in step with the screen instead of beating against it
+fn target_fps(monitors: &[MonitorInfo]) -> u32 {
+    if CONFIG.Grab.ActiveFps > 0 {
+        return CONFIG.Grab.ActiveFps;
+    }
+    match monitors.iter().map(|m| m.refresh_rate).max() {
+        Some(rate) if rate > 0 => rate,
+        _ => FALLBACK_FPS,
+    }
+}
+
+// Bounding box (x, y, width, height) of all monitors
+fn desktop_rect(monitors: &[SlimMonitorInfo]) -> (i32, i32, i32, i32) {
+    let min_x = monitors.iter().map(|m| m.pos_x).min().unwrap_or(0);
+    let min_y = monitors.iter().map(|m| m.pos_y).min().unwrap_or(0);
+    let max_x = monitors.iter().map(|m| m.pos_x + m.width).max().unwrap_or(0);
+    let max_y = monitors.iter().map(|m| m.pos_y + m.height).max().unwrap_or(0);
+    (min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+// Monitor ids with their (x, y, width, height), for anchored LEDs
+fn monitor_rects(monitors: &[SlimMonitorInfo]) -> Vec<(i32, (i32, i32, i32, i32))> {
+    monitors.iter().map(|m| (m.id, (m.pos_x, m.pos_y, m.width, m.height))).collect()
+}
+
+fn export_monitors(monitors: &[MonitorInfo]) -> Vec<SlimMonitorInfo> {
+    monitors.iter().map(MonitorInfo::export).collect()
+}
+
+// Foreground window rectangle clipped to the desktop, None if nothing usable is focused
+// (e.g. a minimized window parked off-screen)
+fn focus_rect(min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> Option<(i32, i32, i32, i32)> {
+    let (x, y, width, height) = foreground_window_rect()?;
+    let left = x.max(min_x);
+    let top = y.max(min_y);
+    let right = (x + width).min(max_x);
+    let bottom = (y + height).min(max_y);
+    if right - left < MIN_FOCUS_SIZE || bottom - top < MIN_FOCUS_SIZE {
+        return None;
+    }
+    Some((left, top, right - left, bottom - top))
+}
+
+// Asks WLED how many LEDs the segment has and warns if the config disagrees.
+// None if the device can't be reached, colors are then sent unchanged.
+fn query_led_count(address: &str, segment_id: u8, configured: usize, thread_num: usize) -> Option<usize> {
+    match arduino::get_led_count(address, segment_id) {
+        Ok(count) => {
+            if count != configured {
+                log::warn!(
+                    "Thread {}:: WLED segment has {} LEDs but the config defines {}, padding/truncating to match",
+                    thread_num,
+                    count,
+                    configured
+                );
+            }
+            Some(count)
+        }
+        Err(e) => {
+            log::error!("Thread {}:: Failed to query WLED LED count: {}", thread_num, e);
+            None
+        }
+    }
+}
+
+// What the strip shows while the backend is stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IdleEffect {
+    // Release the strip so WLED shows its own state
+    Off,
+    FadeToBlack,
+    Static,
+    Breathing,
+}
+
+impl IdleEffect {
+    fn from_config(value: &str) -> Self {
+        match value {
+            "FadeToBlack" => IdleEffect::FadeToBlack,
+            "Static" => IdleEffect::Static,
+            "Breathing" => IdleEffect::Breathing,
+            _ => IdleEffect::Off,
+        }
+    }
+}
+
+// Sequence sent to a device once at startup, confirming it is connected
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BootAnimation {
+    Off,
+    // The boot color runs along the strip, then fades out
+    Wipe,
+}
+
+impl BootAnimation {
+    fn from_config(value: &str) -> Self {
+        match value {
+            "Wipe" => BootAnimation::Wipe,
+            "Off" => BootAnimation::Off,
+            _ => {
+                log::warn!("Unknown BootAnimation {}, using Off", value);
+                BootAnimation::Off
+            }
+        }
+    }
+}
+
+// Frame rate of the boot animation, low enough for the WLED JSON API
+const BOOT_ANIMATION_FPS: u32 = 30;
+
+// Frame of the wipe at `t` (0..1): the first half lights the strip LED by LED, the
+// second half fades it out again
+fn boot_wipe_colors(count: usize, (r, g, b): (u8, u8, u8), t: f32) -> Vec<Color> {
+    let (lit, level) = if t < 0.5 {
+        ((t * 2.0 * count as f32).ceil() as usize, 1.0)
+    } else {
+        (count, (2.0 - t * 2.0).max(0.0))
+    };
+    (0..count)
+        .map(|i| {
+            let level = if i < lit { level } else { 0.0 };
+            Color::new(i as i32, r as f32 * level, g as f32 * level, b as f32 * level)
+        })
+        .collect()
+}
+
+// Plays General.BootAnimation on `count` LEDs through the output, brightness and power
+// limit applied. A failed send ends it early, the capture loop takes over either way.
+fn run_boot_animation(output: &SharedSink, post_processor: &mut PostProcessor, count: usize, thread_num: usize) {
+    if BootAnimation::from_config(&CONFIG.General.BootAnimation) == BootAnimation::Off {
+        return;
+    }
+    let color = parse_hex_color(&CONFIG.General.BootAnimationColor).unwrap_or_else(|e| {
+        log::error!("Invalid BootAnimationColor, using blue: {}", e);
+        (0, 128, 255)
+    });
+    let frames = (CONFIG.General.BootAnimationDuration * BOOT_ANIMATION_FPS / 1000).max(1);
+    let frame_duration = Duration::from_secs(1) / BOOT_ANIMATION_FPS;
+    for frame in 0..=frames {
+        let frame_start = Instant::now();
+        let mut colors = boot_wipe_colors(count, color, frame as f32 / frames as f32);
+        post_processor.process(&mut colors);
+        if let Err(e) = output.load().send(&colors) {
+            log::warn!("Thread {}:: Boot animation stopped: {}", thread_num, e);
+            return;
+        }
+        if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+// What the strip shows once the capture stalls for longer than CaptureTimeout
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CaptureTimeoutAction {
+    // Keep showing the colors of the last captured frame
+    Hold,
+    FadeToBlack,
+    NoSignal,
+}
+
+impl CaptureTimeoutAction {
+    fn from_config(value: &str) -> Self {
+        match value {
+            "FadeToBlack" => CaptureTimeoutAction::FadeToBlack,
+            "NoSignal" => CaptureTimeoutAction::NoSignal,
+            _ => CaptureTimeoutAction::Hold,
+        }
+    }
+}
+
+// Colors for a capture stalled for `stalled_for`, `colors` being the last captured frame.
+// The fade to black takes one transition duration.
+fn capture_timeout_colors(colors: &[Color], action: CaptureTimeoutAction, (r, g, b): (u8, u8, u8), stalled_for: Duration, fade: Duration) -> Vec<Color> {
+    match action {
+        CaptureTimeoutAction::Hold => colors.to_vec(),
+        CaptureTimeoutAction::FadeToBlack => {
+            let t = if fade.is_zero() { 1.0 } else { (stalled_for.as_secs_f32() / fade.as_secs_f32()).min(1.0) };
+            colors
+                .iter()
+                .map(|c| c.lerp(&Color::new(c.led_index, 0.0, 0.0, 0.0), t))
+                .collect()
+        }
+        CaptureTimeoutAction::NoSignal => colors
+            .iter()
+            .map(|c| Color::new(c.led_index, r as f32, g as f32, b as f32))
+            .collect(),
+    }
+}
+
+// Idle frame `elapsed` after the backend stopped. Breathing eases the idle color
+// between dim and full brightness once per IdleBreathingPeriod.
+fn idle_colors(leds: &[LED], effect: IdleEffect, (r, g, b): (u8, u8, u8), elapsed: Duration) -> Vec<Color> {
+    let colors = static_colors(leds, r, g, b);
+    if effect != IdleEffect::Breathing {
+        return colors;
+    }
+    let period = CONFIG.General.IdleBreathingPeriod.max(1) as f32 / 1000.0;
+    let phase = elapsed.as_secs_f32() / period * std::f32::consts::TAU;
+    let level = 0.15 + 0.85 * (0.5 - 0.5 * phase.cos());
+    colors
+        .into_iter()
+        .map(|c| Color::new(c.led_index, 0.0, 0.0, 0.0).lerp(&c, level))
+        .collect()
+}
+
+// Fades the strip from `from` to black over `duration`
+fn fade_out(from: &[Color], duration: Duration, post_processor: &mut PostProcessor, output: &SharedSink) {
+    let sink = output.load();
+    let steps = 10;
+    for step in 1..=steps {
+        let t = step as f32 / steps as f32;
+        let mut frame: Vec<Color> = from
+            .iter()
+            .map(|c| c.lerp(&Color::new(c.led_index, 0.0, 0.0, 0.0), t))
+            .collect();
+        post_processor.process(&mut frame);
+        if let Err(e) = sink.send(&frame) {
+            log::error!("Error while fading out: {}", e);
+            return;
+        }
+        thread::sleep(duration / steps);
+    }
+}
+
+// Measures the strip with a colorimeter on `port_name` and stores the coefficients that
+// make it show neutral white in every LED of a Prismatik profile, replacing their own
+// per-LED coefficients. Padding slots dropped when the config was read keep theirs.
+// Structured configs are left alone, the coefficients are only returned.
+#[cfg(feature = "colorimeter")]
+pub fn calibrate_colorimeter(port_name: &str) -> Result<[f32; 3], Error> {
+    check_config()?;
+    let mut sensor = crate::calibration::Colorimeter::open(port_name)?;
+    let strip_len = arduino::get_led_count(&WLED_ADDRESS, CONFIG.Device.SegmentId).unwrap_or_else(|_| CONFIG.strip_len());
+    arduino::acquire_control(&WLED_ADDRESS, CONFIG.Device.SegmentId)?;
+    let primaries = crate::calibration::measure_primaries(&mut sensor, &WLED_ADDRESS, CONFIG.Device.SegmentId, strip_len);
+    if let Err(e) = arduino::release_control(&WLED_ADDRESS, CONFIG.Device.SegmentId) {
+        log::error!("Failed to release WLED after calibration: {}", e);
+    }
+    let [red, green, blue] = crate::calibration::solve_coefficients(primaries?)?;
+    log::info!("Calibrated coefficients: red {:.3}, green {:.3}, blue {:.3}", red, green, blue);
+
+    let path = config_path();
+    let is_profile = !matches!(
+        path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()).as_deref(),
+        Some("json") | Some("toml")
+    );
+    if is_profile {
+        let content = std::fs::read_to_string(&path)?;
+        // Sections of the LEDs in use, as they are spelled in the file
+        let sections: Vec<String> = content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('[')?.strip_suffix(']'))
+            .filter(|section| {
+                section
+                    .strip_prefix("LED_")
+                    .and_then(|index| index.parse::<i32>().ok())
+                    .is_some_and(|index| CONFIG.leds_array.iter().any(|led| led.index == index))
+            })
+            .map(String::from)
+            .collect();
+        let round = |c: f32| serde_json::json!((c as f64 * 1000.0).round() / 1000.0);
+        let updates: Vec<(&str, &str, serde_json::Value)> = sections
+            .iter()
+            .flat_map(|section| {
+                [
+                    (section.as_str(), "CoefRed", round(red)),
+                    (section.as_str(), "CoefGreen", round(green)),
+                    (section.as_str(), "CoefBlue", round(blue)),
+                ]
+            })
+            .collect();
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| Error::Config(format!("Config path {} is not valid UTF-8", path.display())))?;
+        config::update_config_values(path_str, &updates)?;
+        log::info!(
+            "Coefficients saved for {} LEDs in {}, replacing their per-LED coefficients",
+            sections.len(),
+            path.display()
+        );
+    }
+    Ok([red, green, blue])
+}
+
 #[allow(dead_code)]
-fn test_arduino() -> Result<(), Box<dyn std::error::Error>> {
+fn test_arduino() -> Result<(), Error> {
     // Initialize the logger (creates a log file)
     // logger::init_logger()?;
 
     // Run the streaming function
-    let result = arduino::set_pixels_red("192.168.0.28");
+    let result = arduino::set_pixels_red(&WLED_ADDRESS, CONFIG.Device.SegmentId);
     match result {
         Ok(_) => log::info!("Color set"),
         Err(e) => log::error!("Error in setting color: {}", e),
     }
 
-    let result = arduino::set_effect("192.168.0.28", 41);
+    let result = arduino::set_effect(&WLED_ADDRESS, CONFIG.Device.SegmentId, 41);
     match result {
         Ok(_) => log::info!("Color set"),
         Err(e) => log::error!("Error in setting color: {}", e),
@@ -0,0 +1,127 @@
+// White balance of the strip measured with a colorimeter. Full red, green and blue are
+// shown one after another, the sensor readings give the strip's primaries as the sensor
+// sees them, and the coefficients are the channel drive that makes them add up to a
+// neutral white. The serial sensor is only built with the `colorimeter` feature.
+
+use crate::error::Error;
+
+// Coefficients (CoefRed, CoefGreen, CoefBlue) that make the strip show neutral white.
+// `primaries` holds the sensor RGB reading for full red, green and blue, in that order.
+// Solves primaries * coef = (1, 1, 1) and scales the result so the strongest channel is 1.
+pub fn solve_coefficients(primaries: [[f32; 3]; 3]) -> Result<[f32; 3], Error> {
+    let determinant = |columns: [[f64; 3]; 3]| {
+        columns[0][0] * (columns[1][1] * columns[2][2] - columns[2][1] * columns[1][2])
+            - columns[1][0] * (columns[0][1] * columns[2][2] - columns[2][1] * columns[0][2])
+            + columns[2][0] * (columns[0][1] * columns[1][2] - columns[1][1] * columns[0][2])
+    };
+    // Column j of the matrix is what the sensor reads for primary j
+    let columns: [[f64; 3]; 3] = primaries.map(|primary| primary.map(|value| value as f64));
+    let det = determinant(columns);
+    if det.abs() < 1e-9 {
+        return Err(Error::Validation(
+            "Colorimeter readings of the primaries are not independent, is the sensor facing the strip?".to_string(),
+        ));
+    }
+
+    // Cramer's rule with the target white in place of each column
+    let coefficients: [f64; 3] = std::array::from_fn(|column| {
+        let mut replaced = columns;
+        replaced[column] = [1.0; 3];
+        determinant(replaced) / det
+    });
+    if coefficients.iter().any(|&c| c <= 0.0) {
+        return Err(Error::Validation(format!(
+            "The strip can't reach a neutral white from these readings (solved {:?})",
+            coefficients
+        )));
+    }
+    let max = coefficients.iter().cloned().fold(f64::MIN, f64::max);
+    Ok(coefficients.map(|c| (c / max) as f32))
+}
+
+#[cfg(feature = "colorimeter")]
+pub use sensor::{measure_primaries, Colorimeter};
+
+#[cfg(feature = "colorimeter")]
+mod sensor {
+    use std::io::{BufRead, BufReader, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::arduino;
+    use crate::screen_capture::Color;
+
+    const BAUD_RATE: u32 = 115_200;
+    // Time the strip and the sensor get to settle after a pattern change
+    const SETTLE_TIME: Duration = Duration::from_millis(500);
+    // Readings averaged per pattern
+    const SAMPLES: usize = 5;
+
+    // Sensor on a serial port answering `M\n` with one line of three numbers (`r g b` or
+    // `r,g,b`), e.g. a TCS34725 on a microcontroller
+    pub struct Colorimeter {
+        reader: BufReader<Box<dyn serialport::SerialPort>>,
+    }
+
+    impl Colorimeter {
+        pub fn open(port_name: &str) -> Result<Self, Error> {
+            let port = serialport::new(port_name, BAUD_RATE)
+                .timeout(Duration::from_secs(2))
+                .open()
+                .map_err(|e| Error::Config(format!("Cannot open colorimeter on {}: {}", port_name, e)))?;
+            Ok(Colorimeter { reader: BufReader::new(port) })
+        }
+
+        pub fn read(&mut self) -> Result<[f32; 3], Error> {
+            self.reader.get_mut().write_all(b"M\n")?;
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            let values: Vec<f32> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|part| !part.is_empty())
+                .map(|part| part.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| Error::Validation(format!("Invalid colorimeter reading {:?}: {}", line.trim(), e)))?;
+            match values.as_slice() {
+                [r, g, b] => Ok([*r, *g, *b]),
+                _ => Err(Error::Validation(format!("Expected 3 values from the colorimeter, got {:?}", line.trim()))),
+            }
+        }
+
+        // Mean of SAMPLES readings
+        fn read_average(&mut self) -> Result<[f32; 3], Error> {
+            let mut sum = [0.0; 3];
+            for _ in 0..SAMPLES {
+                let reading = self.read()?;
+                for (total, value) in sum.iter_mut().zip(reading) {
+                    *total += value;
+                }
+            }
+            Ok(sum.map(|total| total / SAMPLES as f32))
+        }
+    }
+
+    // Shows full red, green and blue on the whole segment and returns the averaged sensor
+    // reading of each, minus the reading with the strip black
+    pub fn measure_primaries(sensor: &mut Colorimeter, address: &str, segment_id: u8, strip_len: usize) -> Result<[[f32; 3]; 3], Error> {
+        let mut show = |r: f32, g: f32, b: f32| -> Result<[f32; 3], Error> {
+            let colors = (0..strip_len).map(|i| Color::new(i as i32, r, g, b)).collect();
+            arduino::set_pixels(address, segment_id, colors)?;
+            thread::sleep(SETTLE_TIME);
+            sensor.read_average()
+        };
+
+        let black = show(0.0, 0.0, 0.0)?;
+        let mut primaries = [[0.0; 3]; 3];
+        for (i, primary) in primaries.iter_mut().enumerate() {
+            let mut rgb = [0.0; 3];
+            rgb[i] = 255.0;
+            let reading = show(rgb[0], rgb[1], rgb[2])?;
+            *primary = std::array::from_fn(|c| (reading[c] - black[c]).max(0.0));
+            log::info!("Colorimeter reading for primary {}: {:?}", i, primary);
+        }
+        show(0.0, 0.0, 0.0)?;
+        Ok(primaries)
+    }
+}
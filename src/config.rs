@@ -1,16 +1,80 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fs::{self};
+use std::path::Path;
 use regex::Regex;
 
+use crate::error::Error;
+
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct General {
     pub LightpackMode: String,
     pub IsBacklightEnabled: bool,
+    // Crossfade duration in milliseconds when switching modes
+    #[serde(default = "default_transition_duration")]
+    pub TransitionDuration: u32,
+    // Fade the strip to black when shutting down via Ctrl+C
+    #[serde(default = "default_true")]
+    pub IsFadeOutOnExitEnabled: bool,
+    // What the strip shows while stopped: Off (hand back to WLED), FadeToBlack, Static or Breathing
+    #[serde(default = "default_idle_effect")]
+    pub IdleEffect: String,
+    #[serde(default = "default_idle_color")]
+    pub IdleColor: String,
+    // Duration of one breathing cycle in milliseconds
+    #[serde(default = "default_idle_breathing_period")]
+    pub IdleBreathingPeriod: u32,
+    // Milliseconds to wait before enumerating monitors, for autostart before the displays are up
+    #[serde(default)]
+    pub StartupDelay: u32,
+    // Re-enumerate the monitors at startup until two readings agree on the layout
+    #[serde(default)]
+    pub IsWaitForStableMonitorsEnabled: bool,
+    // Shown on every device that answers at startup, before capturing begins: Off or Wipe
+    #[serde(default = "default_boot_animation")]
+    pub BootAnimation: String,
+    #[serde(default = "default_boot_animation_color")]
+    pub BootAnimationColor: String,
+    // Length of the boot animation in milliseconds
+    #[serde(default = "default_boot_animation_duration")]
+    pub BootAnimationDuration: u32,
+}
+
+fn default_boot_animation() -> String {
+    "Off".to_string()
+}
+
+fn default_boot_animation_color() -> String {
+    "#0080ff".to_string()
+}
+
+fn default_boot_animation_duration() -> u32 {
+    1000
+}
+
+fn default_idle_effect() -> String {
+    "Off".to_string()
+}
+
+fn default_idle_color() -> String {
+    "#ff8c3c".to_string()
+}
+
+fn default_idle_breathing_period() -> u32 {
+    4000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_transition_duration() -> u32 {
+    500
 }
 
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct Grab {
     pub Grabber: String,
     pub IsAvgColorsEnabled: bool,
@@ -25,10 +89,230 @@ pub struct Grab {
     pub IsApplyColorTemperatureEnabled: bool,
     pub ColorTemperature: u16,
     pub Gamma: f32,
+    // Frames darker than this average luminance right after a brighter frame count as glitches
+    #[serde(default = "default_black_frame_threshold")]
+    pub BlackFrameThreshold: u8,
+    // How many consecutive glitch frames are replaced by the previous colors (0 disables)
+    #[serde(default = "default_black_frame_hold_frames")]
+    pub BlackFrameHoldFrames: u8,
+    // Captured frames are shrunk by this factor before processing (1 = full resolution)
+    #[serde(default = "default_downscale_factor")]
+    pub DownscaleFactor: u8,
+    // Downscale in the capture threads, so FRAME_MAP holds and copies the smaller frames
+    #[serde(default)]
+    pub IsCaptureDownscaleEnabled: bool,
+    // Average pixels in linear light (more accurate, slightly more CPU)
+    #[serde(default)]
+    pub IsLinearAveragingEnabled: bool,
+    // Only average the LEDs over screen tiles that changed since the last frame, the rest keep their colors.
+    // Tiles are compared on the grid of sampled pixels, every 30th frame averages all LEDs.
+    #[serde(default)]
+    pub IsDirtyRegionsEnabled: bool,
+    // Edge (Top, Bottom, Left or Right) whose colors are copied to other edges, empty disables
+    #[serde(default)]
+    pub MirrorSource: String,
+    // Where the source edge is mirrored to: Opposite or All
+    #[serde(default = "default_mirror_target")]
+    pub MirrorTarget: String,
+    // Corner LEDs blend their two neighbors instead of averaging their own region
+    #[serde(default)]
+    pub IsCornerBlendingEnabled: bool,
+    // Average the LED regions on the GPU (needs the gpu feature), falls back to the CPU
+    #[serde(default)]
+    pub IsGpuAveragingEnabled: bool,
+    // Only average this many pixels from the screen border side of each LED region (0 = all)
+    #[serde(default)]
+    pub SamplingDepth: u16,
+    // Box blur of the combined frame before averaging, calms LEDs over text and fine textures
+    #[serde(default)]
+    pub IsBlurEnabled: bool,
+    // Blur radius in desktop pixels
+    #[serde(default = "default_blur_radius")]
+    pub BlurRadius: u16,
+    // Pixels left out on every side of each LED region so neighbors don't bleed into each other
+    #[serde(default)]
+    pub SamplingGap: u16,
+    // Capture rate while the picture changes, 0 follows the display refresh rate
+    #[serde(default)]
+    pub ActiveFps: u32,
+    // Lower the capture rate to IdleFps while the desktop is static
+    #[serde(default)]
+    pub IsAdaptiveFpsEnabled: bool,
+    // At least 1, 0 is taken as 1
+    #[serde(default = "default_idle_fps")]
+    pub IdleFps: u32,
+    // Mean color change (0..255) below which a frame counts as static
+    #[serde(default = "default_static_frame_threshold")]
+    pub StaticFrameThreshold: f32,
+    // Consecutive static frames before switching to IdleFps
+    #[serde(default = "default_static_frames_before_idle")]
+    pub StaticFramesBeforeIdle: u32,
+    // Pixel format requested from the capture: Rgba8, Bgra8 or Rgba16F (half float, for
+    // 10 bit panels, falls back to Rgba8 where the capture doesn't support it). Rgba16F
+    // frames are combined and averaged in f32, so dark gradients keep their steps between
+    // 8 bit values up to the LED output. GPU averaging only handles 8 bit images.
+    #[serde(default = "default_pixel_format")]
+    pub PixelFormat: String,
+    // Draw the mouse cursor into captured frames, off so it can't tint nearby LEDs
+    #[serde(default)]
+    pub IncludeCursor: bool,
+    // Which monitor shows where monitors overlap: Last (higher id wins), Priority or Blend
+    #[serde(default = "default_monitor_overlap")]
+    pub MonitorOverlap: String,
+    // Monitor drawn on top with MonitorOverlap=Priority
+    #[serde(default)]
+    pub PriorityMonitor: i32,
+    // Scale dim scenes up so their brightest content reaches AutoExposureTarget
+    #[serde(default)]
+    pub IsAutoExposureEnabled: bool,
+    #[serde(default = "default_auto_exposure_target")]
+    pub AutoExposureTarget: u8,
+    // Fraction of the way to the new gain covered per frame (0..1), lower adapts slower
+    #[serde(default = "default_auto_exposure_speed")]
+    pub AutoExposureSpeed: f32,
+    // Upper limit for the gain so noise in black scenes isn't blown up
+    #[serde(default = "default_auto_exposure_max_gain")]
+    pub AutoExposureMaxGain: f32,
+    // Milliseconds a frame may take to capture before it is dropped without averaging it
+    // (0 disables). Never drops two frames in a row.
+    #[serde(default)]
+    pub FrameDeadline: u32,
+    // Milliseconds a capture thread may go without reporting before the capture counts as stalled (0 disables).
+    // A static desktop delivers no frames but is not stalled.
+    #[serde(default = "default_capture_timeout")]
+    pub CaptureTimeout: u32,
+    // What a stalled capture shows: Hold (last colors), FadeToBlack or NoSignal
+    #[serde(default = "default_capture_timeout_action")]
+    pub CaptureTimeoutAction: String,
+    #[serde(default = "default_no_signal_color")]
+    pub NoSignalColor: String,
+    // Pixels close to this color (e.g. a solid wallpaper) are left out of the averages, empty disables
+    #[serde(default)]
+    pub IgnoreColor: String,
+    // Largest per-channel difference that still counts as the ignored color
+    #[serde(default = "default_ignore_color_tolerance")]
+    pub IgnoreColorTolerance: u8,
+    // CPU cores the processing threads (one per pipeline) and the capture threads (one per
+    // monitor) are pinned to, assigned round robin. Empty leaves scheduling to the OS.
+    #[serde(default)]
+    pub ProcessingCores: Vec<usize>,
+    #[serde(default)]
+    pub CaptureCores: Vec<usize>,
+    // Transfer function of frames from HDR monitors: Off (taken as sRGB), Pq or Hlg.
+    // Tone mapped to SDR before averaging
+    #[serde(default = "default_hdr_tone_map")]
+    pub HdrToneMap: String,
+    // Monitor ids (as in MonitorCorrection) the tone map applies to, empty = all monitors
+    #[serde(default)]
+    pub HdrMonitors: Vec<i32>,
+    // HDR brightness in nits that becomes SDR white
+    #[serde(default = "default_hdr_white_nits")]
+    pub HdrWhiteNits: f32,
+    // Brightest HDR level in nits, highlights up to it are rolled off instead of clipped
+    #[serde(default = "default_hdr_peak_nits")]
+    pub HdrPeakNits: f32,
+    // What LEDs whose region is entirely off-screen show: Black or Hold (previous color)
+    #[serde(default = "default_offscreen_leds")]
+    pub OffscreenLeds: String,
+    // How each LED region is reduced to one color: Mean (RGB) or Yuv (luma weighted chroma)
+    #[serde(default = "default_averaging_mode")]
+    pub AveragingMode: String,
+    // Worker threads the LED averaging is spread over, 0 uses one per core.
+    // The `--threads` command line flag takes precedence.
+    #[serde(default)]
+    pub AveragingThreads: usize,
+}
+
+fn default_hdr_tone_map() -> String {
+    "Off".to_string()
+}
+
+fn default_hdr_white_nits() -> f32 {
+    203.0
+}
+
+fn default_hdr_peak_nits() -> f32 {
+    1000.0
+}
+
+fn default_offscreen_leds() -> String {
+    "Black".to_string()
+}
+
+fn default_averaging_mode() -> String {
+    "Mean".to_string()
+}
+
+fn default_blur_radius() -> u16 {
+    4
+}
+
+fn default_mirror_target() -> String {
+    "Opposite".to_string()
+}
+
+fn default_ignore_color_tolerance() -> u8 {
+    16
+}
+
+fn default_capture_timeout() -> u32 {
+    2000
+}
+
+fn default_capture_timeout_action() -> String {
+    "Hold".to_string()
+}
+
+fn default_no_signal_color() -> String {
+    "#202020".to_string()
+}
+
+fn default_auto_exposure_target() -> u8 {
+    200
+}
+
+fn default_auto_exposure_speed() -> f32 {
+    0.05
+}
+
+fn default_auto_exposure_max_gain() -> f32 {
+    4.0
+}
+
+fn default_monitor_overlap() -> String {
+    "Last".to_string()
+}
+
+fn default_pixel_format() -> String {
+    "Rgba8".to_string()
+}
+
+fn default_idle_fps() -> u32 {
+    5
+}
+
+fn default_static_frame_threshold() -> f32 {
+    1.0
+}
+
+fn default_static_frames_before_idle() -> u32 {
+    48
+}
+
+fn default_black_frame_threshold() -> u8 {
+    2
+}
+
+fn default_black_frame_hold_frames() -> u8 {
+    1
+}
+
+fn default_downscale_factor() -> u8 {
+    1
 }
 
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct MoodLamp {
     pub LiquidMode: bool,
     pub Color: String,
@@ -37,7 +321,7 @@ pub struct MoodLamp {
 }
 
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct SoundVisualizer {
     pub Device: u8,
     pub Visualizer: u8,
@@ -48,20 +332,185 @@ pub struct SoundVisualizer {
 }
 
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Deserialize, Copy, Clone)]
+#[derive(Debug, Deserialize, Copy, Clone, JsonSchema)]
 pub struct Device {
     pub RefreshDelay: u8,
     pub IsUsbPowerLedDisabled: bool,
     pub Brightness: u8,
     pub BrightnessCap: u8,
     pub Smooth: u8,
+    // Applies Smooth to the output. Off by default, profiles carry a Smooth value whether
+    // or not smoothing was wanted
+    #[serde(default)]
+    pub IsSmoothingEnabled: bool,
     pub Gamma: f64,
-    pub ColorDepth: u8,
+    // Per-channel gamma for strips whose chips respond differently, unset channels use Gamma
+    #[serde(default)]
+    pub GammaRed: Option<f64>,
+    #[serde(default)]
+    pub GammaGreen: Option<f64>,
+    #[serde(default)]
+    pub GammaBlue: Option<f64>,
+    // Applies Gamma and the per-channel gammas to the output. Off by default, the colors
+    // are sent as sampled until gamma is enabled here or in the UI
+    #[serde(default)]
+    pub IsGammaEnabled: bool,
+    pub ColorDepth: u16, // Levels per channel the output is quantized to, 255 (or more) sends full 8 bit
     pub IsDitheringEnabled: bool,
+    // Saturation boost for muted colors, 0 = off, 1 = strong
+    #[serde(default)]
+    pub Vibrance: f32,
+    // Current budget of the strip's power supply in mA, frames estimated above it are
+    // dimmed uniformly (0 = no limit)
+    #[serde(default)]
+    pub PowerBudget: u32,
+    // Current of one LED channel at full brightness in mA, for the power estimate
+    #[serde(default = "default_milliamps_per_channel")]
+    pub MilliampsPerChannel: f32,
+    // Fraction of full scale (0..1) from which the brightest channel is compressed instead
+    // of clipped, keeping the hue of over-bright colors. 0 or 1 = hard clipping per channel
+    #[serde(default)]
+    pub GamutKnee: f32,
+    // How far near-white colors are pulled towards WarmWhiteTemperature, 0 = off, 1 = fully
+    #[serde(default)]
+    pub WarmWhiteBias: f32,
+    // White point in Kelvin that near-white colors are biased towards
+    #[serde(default = "default_warm_white_temperature")]
+    pub WarmWhiteTemperature: u16,
+    // Mean color change (0..255) above which a frame skips smoothing (0 = never)
+    #[serde(default = "default_scene_cut_threshold")]
+    pub SceneCutThreshold: f32,
+    // Minimum time between two sends in ms, faster frames are coalesced (0 = send every frame)
+    #[serde(default)]
+    pub MinSendInterval: u32,
+    // WLED segment the colors are written to, other segments keep their own state
+    #[serde(default)]
+    pub SegmentId: u8,
+    // LEDs on the physical strip when the config samples fewer (or more) regions, the
+    // sampled colors are interpolated across them (0 = one LED per region)
+    #[serde(default)]
+    pub PhysicalLedCount: u32,
+    // Failed sends in a row after which the output reconnects in the background and checks
+    // the device is reachable, sends pause until it is (0 = never)
+    #[serde(default = "default_reconnect_threshold")]
+    pub ReconnectThreshold: u32,
+    // Failed sends in a row before a device is skipped for a while (0 = never)
+    #[serde(default = "default_circuit_breaker_threshold")]
+    pub CircuitBreakerThreshold: u32,
+    // Smooth and crossfade in Oklab instead of RGB, keeps transitions vivid at some CPU cost
+    #[serde(default)]
+    pub IsPerceptualSmoothingEnabled: bool,
+    // While paused, contact WLED every this many seconds so output resumes without a
+    // reconnect (0 = off)
+    #[serde(default)]
+    pub KeepaliveInterval: u32,
+    // Keepalive repeats the last frame, keeping WLED in realtime mode, instead of a
+    // GET /json/state ping
+    #[serde(default)]
+    pub IsKeepaliveResendEnabled: bool,
+}
+
+fn default_milliamps_per_channel() -> f32 {
+    20.0
+}
+
+fn default_reconnect_threshold() -> u32 {
+    3
+}
+
+fn default_circuit_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_warm_white_temperature() -> u16 {
+    3500
+}
+
+fn default_scene_cut_threshold() -> f32 {
+    40.0
+}
+
+// Restricts the output to a fixed set of colors, e.g. `Colors=#000000,#ff0000,#ffffff`
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Deserialize, Clone, Default, JsonSchema)]
+#[serde(default)]
+pub struct Palette {
+    pub IsEnabled: bool,
+    pub Colors: Vec<String>,
+}
+
+// Optional HTTP server for monitoring, bound to localhost by default
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[serde(default)]
+pub struct Server {
+    pub IsEnabled: bool,
+    pub Address: String,
+    pub Port: u16,
+    // Bearer token required by the remote control endpoints, empty = no authentication
+    pub Token: String,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            IsEnabled: false,
+            Address: "127.0.0.1".to_string(),
+            Port: 8080,
+            Token: String::new(),
+        }
+    }
+}
+
+// EffectPassthrough mode: a WLED effect runs on the strip with the dominant screen
+// color as its primary color
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[serde(default)]
+pub struct EffectPassthrough {
+    // WLED effect id, e.g. 2 = Breathe, 9 = Rainbow
+    pub EffectId: u8,
+    // Minimum time in ms between color updates, the effect animates on its own in between
+    pub UpdateInterval: u32,
+}
+
+impl Default for EffectPassthrough {
+    fn default() -> Self {
+        EffectPassthrough { EffectId: 2, UpdateInterval: 250 }
+    }
+}
+
+// Where the main pipeline sends its colors
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[serde(default)]
+pub struct Output {
+    // Wled (JSON API), WledUdp (realtime DNRGB) or File to write the frames to Path
+    pub Target: String,
+    // WLED realtime UDP port
+    pub UdpPort: u16,
+    // Largest UDP datagram in bytes, bigger frames are split into several packets
+    pub MaxPacketSize: u16,
+    // File or named pipe for the File target, `-` is stdout
+    pub Path: String,
+    // Hex (one line of RRGGBB per frame) or Raw (3 bytes per LED)
+    pub Format: String,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            Target: "Wled".to_string(),
+            UdpPort: 21324,
+            MaxPacketSize: 1400,
+            Path: "-".to_string(),
+            Format: "Hex".to_string(),
+        }
+    }
 }
 
 #[allow(unused)]
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Default, JsonSchema)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -69,23 +518,160 @@ pub struct Position {
 
 
 #[allow(unused)]
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Default, JsonSchema)]
 pub struct Size {
     pub width: i32,
     pub height: i32,
 }
 
+// Position as a fraction (0.0-1.0) of the combined screen
+#[allow(unused)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, JsonSchema)]
+pub struct RelativePosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+// Size as a fraction (0.0-1.0) of the combined screen
+#[allow(unused)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, JsonSchema)]
+pub struct RelativeSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+// Ties an LED region to an edge of one monitor instead of desktop coordinates, so it
+// follows that monitor when the display arrangement or the primary monitor changes.
+// The region keeps its Size and sits flush against the edge.
 #[allow(non_snake_case, unused)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, JsonSchema)]
+pub struct Anchor {
+    // Monitor id, as in MonitorCorrection (Windows display number - 1)
+    pub Monitor: i32,
+    pub Edge: Edge,
+    // Pixels along the edge from its left or top end
+    #[serde(default)]
+    pub Offset: i32,
+    // Pixels from the edge towards the middle of the monitor
+    #[serde(default)]
+    pub Inset: i32,
+}
+
+// Gamma and white balance gains correcting one monitor's calibration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorCalibration {
+    pub gamma: f32,
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Default for MonitorCalibration {
+    fn default() -> Self {
+        MonitorCalibration {
+            gamma: 1.0,
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        }
+    }
+}
+
+// Screen edge an LED sits on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Edge {
+    pub fn from_config(value: &str) -> Option<Self> {
+        match value {
+            "Top" => Some(Edge::Top),
+            "Bottom" => Some(Edge::Bottom),
+            "Left" => Some(Edge::Left),
+            "Right" => Some(Edge::Right),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(&self) -> Edge {
+        match self {
+            Edge::Top => Edge::Bottom,
+            Edge::Bottom => Edge::Top,
+            Edge::Left => Edge::Right,
+            Edge::Right => Edge::Left,
+        }
+    }
+
+    fn is_horizontal(&self) -> bool {
+        matches!(self, Edge::Top | Edge::Bottom)
+    }
+
+    // Position of the LED's center along this edge: x for top/bottom, y for left/right
+    pub fn position_along(&self, led: &LED) -> f32 {
+        if self.is_horizontal() {
+            led.Position.x as f32 + led.Size.width as f32 / 2.0
+        } else {
+            led.Position.y as f32 + led.Size.height as f32 / 2.0
+        }
+    }
+}
+
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct LED {
     #[serde(skip)]
     pub index: i32,
+    #[serde(skip)]
+    pub edge: Option<Edge>, // Filled by classify_edges
+
     pub IsEnabled: bool,
+    #[serde(default)]
     pub Position: Position,
+    #[serde(default)]
     pub Size: Size,
+    // Used instead of Position/Size when set, so the layout survives resolution changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub RelativePosition: Option<RelativePosition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub RelativeSize: Option<RelativeSize>,
+    // Used instead of Position when set, structured formats only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub Anchor: Option<Anchor>,
     pub CoefRed: f32,
     pub CoefGreen: f32,
     pub CoefBlue: f32,
+    // Output multiplier for LEDs that look brighter or dimmer than their neighbors
+    // (corners, uneven diffusion), on top of the master brightness
+    #[serde(default = "default_led_brightness")]
+    pub Brightness: f32,
+}
+
+fn default_led_brightness() -> f32 {
+    1.0
+}
+
+impl LED {
+    // Enabled LED at a fixed position with neutral coefficients and brightness
+    pub fn new(index: i32, x: i32, y: i32, width: i32, height: i32) -> Self {
+        LED {
+            index,
+            edge: None,
+            IsEnabled: true,
+            Position: Position { x, y },
+            Size: Size { width, height },
+            RelativePosition: None,
+            RelativeSize: None,
+            Anchor: None,
+            CoefRed: 1.0,
+            CoefGreen: 1.0,
+            CoefBlue: 1.0,
+            Brightness: default_led_brightness(),
+        }
+    }
 }
 
 #[allow(non_snake_case, unused)]
@@ -96,57 +682,465 @@ pub struct Config {
     pub MoodLamp: MoodLamp,
     pub SoundVisualizer: SoundVisualizer,
     pub Device: Device,
+    #[serde(default)]
+    pub Server: Server,
+    #[serde(default)]
+    pub Palette: Palette,
+    #[serde(default)]
+    pub Output: Output,
+    #[serde(default)]
+    pub EffectPassthrough: EffectPassthrough,
+    // Per-monitor correction as `<Gamma|Red|Green|Blue>_<monitor id>` keys (Windows display number - 1), see monitor_calibrations
+    #[serde(default)]
+    pub MonitorCorrection: std::collections::HashMap<String, f32>,
+    // Extra independent zones, structured formats only
+    #[serde(default)]
+    pub Pipelines: Vec<Pipeline>,
     #[serde(flatten)]
     pub leds: std::collections::HashMap<String, LED>,
     #[serde(skip)]
     pub leds_array: Vec<LED>, // New array form
 }
 impl Config {
-    pub fn convert_leds_to_array(&mut self) {
+    // The index comes from the `LED_<n>` key. A key without a number is an error rather
+    // than a made-up index, which could collide with a real one.
+    // Prismatik pads profiles with unused slots that keep the neutral 1/1/1 coefficients.
+    // Only those after the last calibrated LED are dropped, a neutral LED within the strip
+    // is real, and a profile that was never calibrated keeps all of its LEDs.
+    pub fn convert_leds_to_array(&mut self) -> Result<(), Error> {
+        let mut invalid_keys = Vec::new();
         self.leds_array = self.leds
             .drain()
             .filter_map(|(key, value)| {
-                if value.CoefRed == 1.0 && value.CoefGreen == 1.0 && value.CoefBlue == 1.0 {
-                    None
-                } else {
-                    Some(LED {
-                        index: key.trim_start_matches("LED_").parse().unwrap_or(self.leds_array.len() as i32),
-                        IsEnabled: value.IsEnabled,
-                        Position: value.Position,
-                        Size: value.Size,
-                        CoefRed: value.CoefRed,
-                        CoefGreen: value.CoefGreen,
-                        CoefBlue: value.CoefBlue,
-                    })
+                match key.strip_prefix("LED_").and_then(|index| index.parse().ok()) {
+                    Some(index) => Some(LED { index, edge: None, ..value }),
+                    None => {
+                        invalid_keys.push(key);
+                        None
+                    }
                 }
             })
             .collect();
+        if !invalid_keys.is_empty() {
+            invalid_keys.sort_unstable();
+            return Err(Error::Validation(format!(
+                "LED sections without a valid index (expected LED_<number>): {}",
+                invalid_keys.join(", ")
+            )));
+        }
+
+        let is_neutral = |led: &LED| led.CoefRed == 1.0 && led.CoefGreen == 1.0 && led.CoefBlue == 1.0;
+        let last_calibrated = self.leds_array.iter().filter(|led| !is_neutral(led)).map(|led| led.index).max();
+        if let Some(last_calibrated) = last_calibrated {
+            let count = self.leds_array.len();
+            self.leds_array.retain(|led| led.index <= last_calibrated || !is_neutral(led));
+            if self.leds_array.len() < count {
+                log::info!("Dropped {} unused LED slots after LED {}", count - self.leds_array.len(), last_calibrated);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate_led_sizes(&self) -> Result<(), Error> {
+        validate_led_sizes(&self.leds_array)
+    }
+
+    pub fn validate_led_indices(&self) -> Result<(), Error> {
+        validate_led_indices(&self.leds_array)
+    }
+
+    pub fn has_relative_leds(&self) -> bool {
+        has_relative_leds(&self.leds_array)
+    }
+
+    pub fn resolve_relative_leds(&mut self, screen: (i32, i32, i32, i32)) {
+        resolve_relative_leds(&mut self.leds_array, screen);
+    }
+
+    pub fn resolve_anchored_leds(&mut self, monitors: &[(i32, (i32, i32, i32, i32))]) {
+        resolve_anchored_leds(&mut self.leds_array, monitors);
+    }
+
+    pub fn classify_edges(&mut self, screen: (i32, i32, i32, i32)) {
+        classify_edges(&mut self.leds_array, screen);
+    }
+
+    // Number of LEDs on the strip the config drives: Device.PhysicalLedCount if set, otherwise
+    // up to the highest LED index
+    pub fn strip_len(&self) -> usize {
+        if self.Device.PhysicalLedCount > 0 {
+            return self.Device.PhysicalLedCount as usize;
+        }
+        self.leds_array
+            .iter()
+            .map(|led| led.index + 1)
+            .max()
+            .unwrap_or(0)
+            .max(0) as usize
+    }
+
+    // Groups the MonitorCorrection keys (e.g. `Gamma_1=1.1`, `Red_1=0.95`) by monitor id
+    pub fn monitor_calibrations(&self) -> std::collections::HashMap<i32, MonitorCalibration> {
+        let mut calibrations = std::collections::HashMap::new();
+        for (key, value) in &self.MonitorCorrection {
+            let Some((name, id)) = key.rsplit_once('_') else {
+                log::warn!("Ignoring MonitorCorrection key without monitor id: {}", key);
+                continue;
+            };
+            let Ok(id) = id.parse::<i32>() else {
+                log::warn!("Ignoring MonitorCorrection key with invalid monitor id: {}", key);
+                continue;
+            };
+            let calibration: &mut MonitorCalibration = calibrations.entry(id).or_default();
+            match name {
+                "Gamma" => calibration.gamma = *value,
+                "Red" => calibration.red = *value,
+                "Green" => calibration.green = *value,
+                "Blue" => calibration.blue = *value,
+                _ => log::warn!("Unknown MonitorCorrection key: {}", key),
+            }
+        }
+        calibrations
+    }
+
+    // All LEDs on the given edge
+    pub fn leds_on_edge(&self, edge: Edge) -> Vec<&LED> {
+        self.leds_array
+            .iter()
+            .filter(|led| led.edge == Some(edge))
+            .collect()
+    }
+}
+
+// LEDs whose region has no area sample no pixels and stay black, reject them with
+// their indices instead. Relative sizes are checked once resolved to pixels.
+pub fn validate_led_sizes(leds: &[LED]) -> Result<(), Error> {
+    let mut degenerate: Vec<i32> = leds
+        .iter()
+        .filter(|led| led.IsEnabled && led.RelativeSize.is_none())
+        .filter(|led| led.Size.width <= 0 || led.Size.height <= 0)
+        .map(|led| led.index)
+        .collect();
+    if degenerate.is_empty() {
+        return Ok(());
+    }
+    degenerate.sort_unstable();
+    let indices: Vec<String> = degenerate.iter().map(|i| i.to_string()).collect();
+    Err(Error::Validation(format!(
+        "LEDs with zero or negative size (width and height must be at least 1): {}",
+        indices.join(", ")
+    )))
+}
+
+// Two LEDs with the same index would both be sent to the same strip position, one
+// silently overwriting the other
+pub fn validate_led_indices(leds: &[LED]) -> Result<(), Error> {
+    let mut indices: Vec<i32> = leds.iter().map(|led| led.index).collect();
+    indices.sort_unstable();
+    let mut duplicates: Vec<i32> = indices.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    duplicates.dedup();
+    let duplicates: Vec<String> = duplicates.iter().map(|i| i.to_string()).collect();
+    Err(Error::Validation(format!("Duplicate LED indices: {}", duplicates.join(", "))))
+}
+
+// Whether any LED's pixel position depends on the monitor layout (relative or anchored)
+pub fn has_relative_leds(leds: &[LED]) -> bool {
+    leds.iter()
+        .any(|led| led.RelativePosition.is_some() || led.RelativeSize.is_some() || led.Anchor.is_some())
+}
+
+// Places the anchored LEDs on their monitor, given as (id, (x, y, width, height)).
+// LEDs anchored to a monitor that isn't connected keep their configured Position.
+pub fn resolve_anchored_leds(leds: &mut [LED], monitors: &[(i32, (i32, i32, i32, i32))]) {
+    if !leds.iter().any(|led| led.Anchor.is_some()) {
+        return;
+    }
+    for led in leds.iter_mut() {
+        let Some(anchor) = led.Anchor else {
+            continue;
+        };
+        let Some(&(_, (x, y, width, height))) = monitors.iter().find(|(id, _)| *id == anchor.Monitor) else {
+            log::warn!("LED {} is anchored to monitor {}, which is not connected", led.index, anchor.Monitor);
+            continue;
+        };
+        led.Position = match anchor.Edge {
+            Edge::Top => Position { x: x + anchor.Offset, y: y + anchor.Inset },
+            Edge::Bottom => Position { x: x + anchor.Offset, y: y + height - led.Size.height - anchor.Inset },
+            Edge::Left => Position { x: x + anchor.Inset, y: y + anchor.Offset },
+            Edge::Right => Position { x: x + width - led.Size.width - anchor.Inset, y: y + anchor.Offset },
+        };
+    }
+}
+
+// Turns relative LED coordinates into pixels of the combined screen (x, y, width, height).
+// Fractions outside 0.0-1.0 are clamped so a typo can't place an LED off-screen.
+pub fn resolve_relative_leds(leds: &mut [LED], screen: (i32, i32, i32, i32)) {
+    let (screen_x, screen_y, screen_width, screen_height) = screen;
+    let scale = |fraction: f32, length: i32| {
+        if !(0.0..=1.0).contains(&fraction) {
+            log::warn!("Relative LED coordinate {} is outside 0.0-1.0, clamping", fraction);
+        }
+        (fraction.clamp(0.0, 1.0) * length as f32).round() as i32
+    };
+
+    if !has_relative_leds(leds) {
+        return;
+    }
+    for led in leds.iter_mut() {
+        if let Some(position) = led.RelativePosition {
+            led.Position = Position {
+                x: screen_x + scale(position.x, screen_width),
+                y: screen_y + scale(position.y, screen_height),
+            };
+        }
+        if let Some(size) = led.RelativeSize {
+            led.Size = Size {
+                width: scale(size.width, screen_width).max(1),
+                height: scale(size.height, screen_height).max(1),
+            };
+        }
+    }
+}
+
+// Bounding box (x, y, width, height) of the LED regions, stands in for the screen
+// while the monitor layout isn't known
+pub fn led_bounds(leds: &[LED]) -> (i32, i32, i32, i32) {
+    let min_x = leds.iter().map(|l| l.Position.x).min().unwrap_or(0);
+    let min_y = leds.iter().map(|l| l.Position.y).min().unwrap_or(0);
+    let max_x = leds.iter().map(|l| l.Position.x + l.Size.width).max().unwrap_or(0);
+    let max_y = leds.iter().map(|l| l.Position.y + l.Size.height).max().unwrap_or(0);
+    (min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+// Assigns each LED to the edge of the screen (x, y, width, height) its center is closest to.
+// Distances are relative to the screen size so wide screens don't favour top/bottom.
+// Run it again once relative and anchored LEDs have their final pixel positions.
+pub fn classify_edges(leds: &mut [LED], screen: (i32, i32, i32, i32)) {
+    let (min_x, min_y, screen_width, screen_height) = screen;
+    let (max_x, max_y) = (min_x + screen_width, min_y + screen_height);
+    let width = screen_width.max(1) as f32;
+    let height = screen_height.max(1) as f32;
+
+    for led in leds.iter_mut() {
+        let center_x = led.Position.x as f32 + led.Size.width as f32 / 2.0;
+        let center_y = led.Position.y as f32 + led.Size.height as f32 / 2.0;
+        let distances = [
+            (Edge::Top, (center_y - min_y as f32) / height),
+            (Edge::Bottom, (max_y as f32 - center_y) / height),
+            (Edge::Left, (center_x - min_x as f32) / width),
+            (Edge::Right, (max_x as f32 - center_x) / width),
+        ];
+        led.edge = distances
+            .iter()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(edge, _)| *edge);
+    }
+}
+
+// Parses a "#rrggbb" color string as used by Prismatik
+pub fn parse_hex_color(value: &str) -> Result<(u8, u8, u8), Error> {
+    let hex = value.trim().trim_start_matches('#');
+    let invalid = || Error::Validation(format!("Invalid color: {}", value));
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&hex[range], 16).map_err(|_| invalid());
+    Ok((channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
+
+// Writes single values back into a config file, keeping its format.
+// Updates are (section, key, value), missing sections and keys are added.
+pub fn update_config_values(
+    file_path: &str,
+    updates: &[(&str, &str, serde_json::Value)],
+) -> Result<(), Error> {
+    let content = fs::read_to_string(file_path)?;
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let updated = match extension.as_deref() {
+        Some("json") => {
+            let mut root: serde_json::Value = serde_json::from_str(&content).map_err(config_error)?;
+            let sections = root
+                .as_object_mut()
+                .ok_or_else(|| Error::Config("the config root is not an object".to_string()))?;
+            for (section, key, value) in updates {
+                sections
+                    .entry(section.to_string())
+                    .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+                    .as_object_mut()
+                    .ok_or_else(|| Error::Config(format!("{} is not a section", section)))?
+                    .insert(key.to_string(), value.clone());
+            }
+            serde_json::to_string_pretty(&root).map_err(config_error)?
+        }
+        // Edited in place so comments and the key order of the file survive
+        Some("toml") => {
+            let mut root: toml_edit::DocumentMut = content.parse().map_err(config_error)?;
+            for (section, key, value) in updates {
+                let table = root
+                    .entry(section)
+                    .or_insert(toml_edit::table())
+                    .as_table_like_mut()
+                    .ok_or_else(|| Error::Config(format!("{} is not a section", section)))?;
+                let mut value = toml_edit_value(value)?;
+                match table.get_mut(key).and_then(toml_edit::Item::as_value_mut) {
+                    // Keep the comment and spacing around a value that is replaced
+                    Some(existing) => {
+                        *value.decor_mut() = existing.decor().clone();
+                        *existing = value;
+                    }
+                    None => {
+                        table.insert(key, toml_edit::value(value));
+                    }
+                }
+            }
+            root.to_string()
+        }
+        _ => update_prismatik_values(&content, updates),
+    };
+
+    fs::write(file_path, updated)?;
+    Ok(())
+}
+
+// JSON value to its TOML counterpart, TOML has no null
+fn toml_edit_value(value: &serde_json::Value) -> Result<toml_edit::Value, Error> {
+    Ok(match value {
+        serde_json::Value::Null => return Err(Error::Config("TOML has no null value".to_string())),
+        serde_json::Value::Bool(b) => (*b).into(),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().unwrap_or_default().into(),
+        },
+        serde_json::Value::String(s) => s.as_str().into(),
+        serde_json::Value::Array(items) => items.iter().map(toml_edit_value).collect::<Result<toml_edit::Array, Error>>()?.into(),
+        serde_json::Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (key, item) in map {
+                table.insert(key, toml_edit_value(item)?);
+            }
+            table.into()
+        }
+    })
+}
+
+// Parse and serialization errors of the config formats
+fn config_error(e: impl std::fmt::Display) -> Error {
+    Error::Config(e.to_string())
+}
+
+// Replaces `Key=value` lines inside their `[Section]` of a Prismatik profile
+fn update_prismatik_values(content: &str, updates: &[(&str, &str, serde_json::Value)]) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+    for (section, key, value) in updates {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let line = format!("{}={}", key, value);
+        let header = format!("[{}]", section);
+
+        let Some(start) = lines.iter().position(|l| l.trim() == header) else {
+            lines.push(String::new());
+            lines.push(header);
+            lines.push(line);
+            continue;
+        };
+        let end = lines[start + 1..]
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .map(|i| start + 1 + i)
+            .unwrap_or(lines.len());
+        let prefix = format!("{}=", key);
+        match lines[start + 1..end].iter().position(|l| l.trim_start().starts_with(&prefix)) {
+            Some(i) => lines[start + 1 + i] = line,
+            None => lines.insert(start + 1, line),
+        }
+    }
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+    result
+}
+
+// Environment variable selecting how read_config treats profile lines it can't convert
+pub const UNMATCHED_LINES_ENV: &str = "LIGHTSHOW_UNMATCHED_LINES";
+
+// What happens to Prismatik profile lines that match none of the known value formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmatchedLines {
+    // Fail the import and list the lines, catches typos before they turn into odd values
+    Error,
+    // Drop the lines
+    Skip,
+    // Copy the lines into the TOML as they are
+    #[default]
+    PassThrough,
+}
+
+impl UnmatchedLines {
+    // Strict, Skip or PassThrough (the default)
+    pub fn from_name(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strict" | "error" => Some(UnmatchedLines::Error),
+            "skip" => Some(UnmatchedLines::Skip),
+            "passthrough" | "pass" => Some(UnmatchedLines::PassThrough),
+            _ => None,
+        }
+    }
+
+    // From LIGHTSHOW_UNMATCHED_LINES, PassThrough when unset or unknown
+    pub fn from_env() -> Self {
+        match std::env::var(UNMATCHED_LINES_ENV) {
+            Ok(value) => UnmatchedLines::from_name(&value).unwrap_or_else(|| {
+                log::warn!("Unknown {} value {:?}, passing unmatched lines through", UNMATCHED_LINES_ENV, value);
+                UnmatchedLines::PassThrough
+            }),
+            Err(_) => UnmatchedLines::PassThrough,
+        }
     }
 }
 
 // This function converts the input config to valid TOML format
-fn convert_to_toml(input: &str) -> String {
+fn convert_to_toml(input: &str, unmatched_lines: UnmatchedLines) -> Result<String, Error> {
     let mut result = String::new();
+    // Line number and content of every line no pattern matched, reported together
+    let mut unmatched = Vec::new();
 
     // Regex patterns to match sections, colors, points, sizes, and types
     let section_re = Regex::new(r"^\[(\w+)\]$").unwrap();
     let color_re = Regex::new(r"^([A-Za-z0-9_]+)=#([A-Fa-f0-9]{6})$").unwrap();    
+    let color_list_re = Regex::new(r"^([A-Za-z0-9_]+)=(#[A-Fa-f0-9]{6}(?:\s*,\s*#[A-Fa-f0-9]{6})+)$").unwrap();
     let point_re = Regex::new(r"^([A-Za-z0-9_]+)\s*=\s*@Point\((-?\d+)\s+(-?\d+)\)$").unwrap();
     let size_re = Regex::new(r"^([A-Za-z0-9_]+)=@Size\((\d+)\s+(\d+)\)$").unwrap();
     let bool_re = Regex::new(r"^(Is)([A-Za-z0-9_]+)=(true|false)$").unwrap();
-    let bool_re2 = Regex::new(r"^(LiquidMode)=(true|false)$").unwrap(); // Failure in creating actual standard \_(ツ)_/¯
+    let bool_re2 = Regex::new(r"^(LiquidMode|IncludeCursor)=(true|false)$").unwrap(); // Failure in creating actual standard \_(ツ)_/¯
     let int_re = Regex::new(r"^([A-Za-z0-9_]+)=(\d+)$").unwrap();
+    let address_re = Regex::new(r"^([A-Za-z0-9_]+)=(\d+\.\d+\.[\d.]+)$").unwrap(); // IPs, would match as floats
     let float_re = Regex::new(r"^([A-Za-z0-9_]+)=([\d.]+)$").unwrap();
     let unquoted_string_re = Regex::new(r"^([A-Za-z0-9_]+)=(\w+)$").unwrap();
+    let path_re = Regex::new(r"^([A-Za-z0-9_]*Path)=(.+)$").unwrap(); // Any characters, quoted as they are
 
-    for line in input.lines() {        
+    for (number, line) in input.lines().enumerate() {
         if line.trim().is_empty() {
             continue; // Skip empty lines
         }
         if let Some(caps) = section_re.captures(line) {
             result.push_str(&format!("[{}]\n", &caps[1]));
+        } else if let Some(caps) = path_re.captures(line) {
+            result.push_str(&format!("{} = {}\n", &caps[1], toml::Value::String(caps[2].to_string())));
         } else if let Some(caps) = color_re.captures(line) {
             result.push_str(&format!("{} = \"#{}\"\n", &caps[1], &caps[2]));
+        } else if let Some(caps) = color_list_re.captures(line) {
+            let colors: Vec<String> = caps[2].split(',').map(|c| format!("\"{}\"", c.trim())).collect();
+            result.push_str(&format!("{} = [{}]\n", &caps[1], colors.join(", ")));
         } else if let Some(caps) = point_re.captures(line) {
             result.push_str(&format!("{} = {{ x = {}, y = {} }}\n", &caps[1], &caps[2], &caps[3]));
         } else if let Some(caps) = size_re.captures(line) {
@@ -157,28 +1151,173 @@ fn convert_to_toml(input: &str) -> String {
             result.push_str(&format!("{} = {}\n", &caps[1], &caps[2]));
         } else if let Some(caps) = int_re.captures(line) {
             result.push_str(&format!("{} = {}\n", &caps[1], &caps[2]));
+        } else if let Some(caps) = address_re.captures(line) {
+            result.push_str(&format!("{} = \"{}\"\n", &caps[1], &caps[2]));
         } else if let Some(caps) = float_re.captures(line) {
             result.push_str(&format!("{} = {}\n", &caps[1], &caps[2]));
         } else if let Some(caps) = unquoted_string_re.captures(line) {
             result.push_str(&format!("{} = \"{}\"\n", &caps[1], &caps[2]));
         } else {
-            // Log or handle unmatched lines
-            eprintln!("Unmatched line: {}", line);
-            result.push_str(line);
-            result.push('\n');
+            unmatched.push(format!("{}: {}", number + 1, line));
+            if unmatched_lines == UnmatchedLines::PassThrough {
+                result.push_str(line);
+                result.push('\n');
+            }
         }
     }
 
-    result
+    if !unmatched.is_empty() {
+        match unmatched_lines {
+            UnmatchedLines::Error => {
+                return Err(Error::Validation(format!("Unrecognized config lines:\n{}", unmatched.join("\n"))));
+            }
+            UnmatchedLines::Skip => log::warn!("Skipped unrecognized config lines:\n{}", unmatched.join("\n")),
+            UnmatchedLines::PassThrough => log::warn!("Unrecognized config lines passed through:\n{}", unmatched.join("\n")),
+        }
+    }
+
+    Ok(result)
+}
+
+// LED entry of the structured config, the index defaults to the position in the list
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct StructuredLed {
+    index: Option<i32>,
+    #[serde(flatten)]
+    led: LED,
+}
+
+fn structured_leds(entries: Vec<StructuredLed>) -> Vec<LED> {
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| LED {
+            index: entry.index.unwrap_or(i as i32),
+            ..entry.led
+        })
+        .collect()
 }
 
-pub fn read_config(file_path: &str) -> Result<Config, Box<dyn std::error::Error>> {
+fn deserialize_leds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<LED>, D::Error> {
+    Ok(structured_leds(Vec::<StructuredLed>::deserialize(deserializer)?))
+}
+
+// Independent capture-to-strip zone with its own monitors, LED layout and WLED device,
+// processed by its own loop. Without any, the top-level LEDs form the only pipeline.
+#[allow(non_snake_case, unused)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct Pipeline {
+    pub Name: String,
+    #[serde(default = "default_true")]
+    pub IsEnabled: bool,
+    // Monitor ids (Windows display number - 1) captured for this pipeline, empty = all monitors
+    #[serde(default)]
+    pub Monitors: Vec<i32>,
+    // WLED address, e.g. "192.168.0.29"
+    pub Address: String,
+    #[serde(default)]
+    pub SegmentId: u8,
+    // Own send rate limit in ms for this device, unset uses Device.MinSendInterval
+    #[serde(default)]
+    pub MinSendInterval: Option<u32>,
+    // LEDs on this device's strip to interpolate the sampled colors across (0 = one LED per region)
+    #[serde(default)]
+    pub PhysicalLedCount: u32,
+    #[serde(rename = "LEDs", deserialize_with = "deserialize_leds")]
+    #[schemars(with = "Vec<StructuredLed>")]
+    pub leds_array: Vec<LED>,
+}
+
+// Structured config format (JSON or TOML) that maps onto Config without the regex
+// conversion. LEDs are a plain list instead of LED_<n> sections.
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct StructuredConfig {
+    General: General,
+    Grab: Grab,
+    MoodLamp: MoodLamp,
+    SoundVisualizer: SoundVisualizer,
+    Device: Device,
+    #[serde(default)]
+    Server: Server,
+    #[serde(default)]
+    Palette: Palette,
+    #[serde(default)]
+    Output: Output,
+    #[serde(default)]
+    EffectPassthrough: EffectPassthrough,
+    #[serde(default)]
+    MonitorCorrection: std::collections::HashMap<String, f32>,
+    #[serde(default)]
+    Pipelines: Vec<Pipeline>,
+    LEDs: Vec<StructuredLed>,
+}
+
+// JSON schema of the structured config format (config.json / config.toml), for editors
+// and validation outside the app
+pub fn schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(StructuredConfig)
+}
+
+impl From<StructuredConfig> for Config {
+    fn from(structured: StructuredConfig) -> Self {
+        let leds_array = structured_leds(structured.LEDs);
+
+        Config {
+            General: structured.General,
+            Grab: structured.Grab,
+            MoodLamp: structured.MoodLamp,
+            SoundVisualizer: structured.SoundVisualizer,
+            Device: structured.Device,
+            Server: structured.Server,
+            Palette: structured.Palette,
+            Output: structured.Output,
+            EffectPassthrough: structured.EffectPassthrough,
+            MonitorCorrection: structured.MonitorCorrection,
+            Pipelines: structured.Pipelines,
+            leds: std::collections::HashMap::new(),
+            leds_array,
+        }
+    }
+}
+
+// Reads a config file, the format is picked by extension:
+// .json and .toml use the structured format, anything else is imported as a Prismatik profile
+pub fn read_config(file_path: &str) -> Result<Config, Error> {
+    read_config_with(file_path, UnmatchedLines::from_env())
+}
+
+// read_config with an explicit choice for Prismatik lines that can't be converted
+pub fn read_config_with(file_path: &str, unmatched_lines: UnmatchedLines) -> Result<Config, Error> {
     let config_content = fs::read_to_string(file_path)?;
-    let fixed_config_content = convert_to_toml(&config_content);
-    let mut config: Config = toml::from_str(&fixed_config_content)?;
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
 
-    // Convert the HashMap to a Vec to enable parallel processing
-    config.convert_leds_to_array();
+    let mut config: Config = match extension.as_deref() {
+        Some("json") => serde_json::from_str::<StructuredConfig>(&config_content).map_err(config_error)?.into(),
+        Some("toml") => toml::from_str::<StructuredConfig>(&config_content).map_err(config_error)?.into(),
+        _ => {
+            let fixed_config_content = convert_to_toml(&config_content, unmatched_lines)?;
+            let mut config: Config = toml::from_str(&fixed_config_content).map_err(config_error)?;
+            // Convert the HashMap to a Vec to enable parallel processing
+            config.convert_leds_to_array()?;
+            config
+        }
+    };
+    config.validate_led_indices()?;
+    config.validate_led_sizes()?;
+    // The monitors aren't known here, the backend classifies again against the screen
+    config.classify_edges(led_bounds(&config.leds_array));
+    for pipeline in config.Pipelines.iter_mut() {
+        validate_led_indices(&pipeline.leds_array)
+            .and_then(|_| validate_led_sizes(&pipeline.leds_array))
+            .map_err(|e| Error::Validation(format!("Pipeline {}: {}", pipeline.Name, e)))?;
+        let bounds = led_bounds(&pipeline.leds_array);
+        classify_edges(&mut pipeline.leds_array, bounds);
+    }
 
     log::info!("Config loaded");
     Ok(config)
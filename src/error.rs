@@ -0,0 +1,29 @@
+use thiserror::Error;
+
+// Errors returned by the public functions, grouped by cause so callers can tell an
+// offline WLED from a broken config and e.g. retry only the former
+#[derive(Debug, Error)]
+pub enum Error {
+    // Config file missing, unreadable or not parseable
+    #[error("{0}")]
+    Config(String),
+    // Monitor enumeration or screen capture failed, or no frames arrived
+    #[error("{0}")]
+    Capture(String),
+    // WLED is unreachable or answered with something unexpected
+    #[error("{0}")]
+    Network(String),
+    // Values that parse but can't be used, e.g. LEDs without area or malformed colors
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e.to_string())
+    }
+}
@@ -0,0 +1,296 @@
+// Per-LED averaging on the GPU. The combined frame is uploaded once per frame and one
+// workgroup sums the sample points of one LED region, only the sums are read back.
+// Built with the `gpu` feature; without it GpuAverager can't be created and the CPU
+// path is used.
+
+use image::RgbaImage;
+
+use crate::error::Error;
+use crate::screen_capture::{AveragingOptions, Color};
+use crate::LED;
+
+#[cfg(feature = "gpu")]
+pub use enabled::GpuAverager;
+
+#[cfg(not(feature = "gpu"))]
+pub enum GpuAverager {}
+
+#[cfg(not(feature = "gpu"))]
+impl GpuAverager {
+    pub fn new() -> Option<Self> {
+        log::warn!("GPU averaging requested, but this build has no `gpu` feature; averaging on the CPU");
+        None
+    }
+
+    pub fn average(&mut self, _: &RgbaImage, _: i32, _: i32, _: &[LED], _: &AveragingOptions) -> Result<Vec<Color>, Error> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "gpu")]
+mod enabled {
+    use std::borrow::Cow;
+    use std::sync::mpsc;
+
+    use wgpu::util::DeviceExt;
+
+    use super::*;
+    use crate::screen_capture::{linear_to_srgb, sampling_area, SAMPLE_STEP};
+
+    const SHADER: &str = r#"
+struct Params {
+    width: u32,
+    height: u32,
+    linear: u32,
+    step: u32,
+}
+
+struct Region {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> pixels: array<u32>;
+@group(0) @binding(2) var<storage, read> regions: array<Region>;
+@group(0) @binding(3) var<storage, read_write> sums: array<vec4<f32>>;
+
+var<workgroup> partial: array<vec4<f32>, 64>;
+
+fn decode(value: f32) -> f32 {
+    if (params.linear == 0u) {
+        return value;
+    }
+    let c = value / 255.0;
+    if (c <= 0.04045) {
+        return c / 12.92 * 255.0;
+    }
+    return pow((c + 0.055) / 1.055, 2.4) * 255.0;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(workgroup_id) group: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let region = regions[group.x];
+    let columns = (u32(max(region.width, 0)) + params.step - 1u) / params.step;
+    let rows = (u32(max(region.height, 0)) + params.step - 1u) / params.step;
+
+    var sum = vec4<f32>(0.0);
+    for (var i = local; i < columns * rows; i = i + 64u) {
+        let x = region.x + i32((i % columns) * params.step);
+        let y = region.y + i32((i / columns) * params.step);
+        if (x >= 0 && y >= 0 && x < i32(params.width) && y < i32(params.height)) {
+            let pixel = unpack4x8unorm(pixels[u32(y) * params.width + u32(x)]) * 255.0;
+            sum = sum + vec4<f32>(decode(pixel.r), decode(pixel.g), decode(pixel.b), 1.0);
+        }
+    }
+
+    partial[local] = sum;
+    workgroupBarrier();
+    for (var stride = 32u; stride > 0u; stride = stride / 2u) {
+        if (local < stride) {
+            partial[local] = partial[local] + partial[local + stride];
+        }
+        workgroupBarrier();
+    }
+    if (local == 0u) {
+        sums[group.x] = partial[0];
+    }
+}
+"#;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Params {
+        width: u32,
+        height: u32,
+        linear: u32,
+        step: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Region {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    }
+
+    pub struct GpuAverager {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        pipeline: wgpu::ComputePipeline,
+        // Frame buffer of the last frame size, reused while the size stays the same
+        pixels: Option<wgpu::Buffer>,
+    }
+
+    impl GpuAverager {
+        // None if no usable adapter is found, the caller keeps averaging on the CPU
+        pub fn new() -> Option<Self> {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+            let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            }));
+            let Some(adapter) = adapter else {
+                log::warn!("No GPU adapter found, averaging on the CPU");
+                return None;
+            };
+            let (device, queue) = match pollster::block_on(adapter.request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("lightshow averaging"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: adapter.limits(),
+                },
+                None,
+            )) {
+                Ok(device) => device,
+                Err(e) => {
+                    log::warn!("Failed to open GPU device, averaging on the CPU: {}", e);
+                    return None;
+                }
+            };
+            log::info!("Averaging on the GPU: {}", adapter.get_info().name);
+
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("average"),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("average"),
+                layout: None,
+                module: &shader,
+                entry_point: "main",
+            });
+
+            Some(GpuAverager { device, queue, pipeline, pixels: None })
+        }
+
+        // Same result as calculate_avg_colors for the full-frame case: the LED regions
+        // are in the coordinates of `image` offset by (min_x, min_y)
+        pub fn average(&mut self, image: &RgbaImage, min_x: i32, min_y: i32, leds: &[LED], options: &AveragingOptions) -> Result<Vec<Color>, Error> {
+            if leds.is_empty() {
+                return Ok(Vec::new());
+            }
+            let frame = image.as_raw();
+            let max_binding = self.device.limits().max_storage_buffer_binding_size as usize;
+            if frame.len() > max_binding {
+                return Err(Error::Capture(format!(
+                    "Frame of {} bytes exceeds the GPU storage buffer limit of {} bytes",
+                    frame.len(),
+                    max_binding
+                )));
+            }
+
+            let pixels = match &self.pixels {
+                Some(buffer) if buffer.size() == frame.len() as u64 => buffer,
+                _ => self.pixels.insert(self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("frame"),
+                    size: frame.len() as u64,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })),
+            };
+            self.queue.write_buffer(pixels, 0, frame);
+
+            let params = Params {
+                width: image.width(),
+                height: image.height(),
+                linear: options.linear as u32,
+                step: SAMPLE_STEP as u32,
+            };
+            let regions: Vec<Region> = leds
+                .iter()
+                .map(|led| {
+                    let (x_range, y_range) = sampling_area(led, options);
+                    Region {
+                        x: led.Position.x + x_range.start - min_x,
+                        y: led.Position.y + y_range.start - min_y,
+                        width: x_range.len() as i32,
+                        height: y_range.len() as i32,
+                    }
+                })
+                .collect();
+
+            let params_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: bytemuck::bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let regions_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("regions"),
+                contents: bytemuck::cast_slice(&regions),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            let sums_size = (leds.len() * std::mem::size_of::<[f32; 4]>()) as u64;
+            let sums_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("sums"),
+                size: sums_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback"),
+                size: sums_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("average"),
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: pixels.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: regions_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: sums_buffer.as_entire_binding() },
+                ],
+            });
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("average") });
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("average"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(leds.len() as u32, 1, 1);
+            }
+            encoder.copy_buffer_to_buffer(&sums_buffer, 0, &readback, 0, sums_size);
+            self.queue.submit(Some(encoder.finish()));
+
+            let slice = readback.slice(..);
+            let (sender, receiver) = mpsc::channel();
+            slice.map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            self.device.poll(wgpu::Maintain::Wait);
+            receiver
+                .recv()
+                .map_err(|e| Error::Capture(e.to_string()))?
+                .map_err(|e| Error::Capture(e.to_string()))?;
+
+            let encode = |v: f32| if options.linear { linear_to_srgb(v) } else { v };
+            let colors = {
+                let data = slice.get_mapped_range();
+                let sums: &[[f32; 4]] = bytemuck::cast_slice(&data);
+                leds.iter()
+                    .zip(sums)
+                    .map(|(led, [r, g, b, count])| {
+                        if *count > 0.0 {
+                            Color::new(led.index, encode(r / count), encode(g / count), encode(b / count))
+                        } else {
+                            Color::new(led.index, 0.0, 0.0, 0.0)
+                        }
+                    })
+                    .collect()
+            };
+            readback.unmap();
+            Ok(colors)
+        }
+    }
+}
@@ -1,18 +1,47 @@
 use eframe::egui;
-use image::GenericImageView;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 use tray_icon::{Icon, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
 use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::{SetWindowLongW, SetWindowPos, ShowWindow, GWL_STYLE, SW_HIDE, SW_SHOWDEFAULT, WS_POPUP, HWND_TOPMOST};
+use windows::core::HSTRING;
+use windows::Win32::UI::WindowsAndMessaging::{MessageBoxW, SetWindowLongW, SetWindowPos, ShowWindow, GWL_STYLE, MB_ICONERROR, MB_OK, SW_HIDE, SW_SHOWDEFAULT, WS_POPUP, HWND_TOPMOST};
 use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
-use crate::backend::main_program_start;
-use crate::{logger, SharedState};
-use winapi::shared::windef::POINT;
-use winapi::um::winuser::{GetCursorPos, ScreenToClient};
+use crate::backend::{main_program_start, reload_config, save_output_settings};
+use crate::hardware_interaction::get_monitor_info;
+use crate::metrics::{Stage, METRICS};
+use crate::{logger, Mode, OutputSettings, PipelineState, SharedState};
+use winapi::shared::windef::{POINT, RECT};
+use winapi::um::winuser::{GetCursorPos, GetWindowRect, ScreenToClient};
 
 static VISIBLE: Mutex<bool> = Mutex::new(false);
+// Whether the settings panel below the buttons is expanded
+static SETTINGS_OPEN: AtomicBool = AtomicBool::new(false);
+
+// Tray icon built into the binary, so it doesn't depend on the res folder being deployed
+const TRAY_ICON: &[u8] = include_bytes!("../res/icon_16x16.png");
+// Path of a PNG that replaces the built-in tray icon
+const TRAY_ICON_ENV: &str = "LIGHTSHOW_TRAY_ICON";
+
+// Size of the tray popup window
+const POPUP_WIDTH: i32 = 80;
+const POPUP_HEIGHT: i32 = 270;
+// Size with the settings panel expanded
+const SETTINGS_WIDTH: i32 = 200;
+const SETTINGS_HEIGHT: i32 = 500;
+// Loop times shown in the sparkline, a few seconds at common frame rates
+const SPARKLINE_SAMPLES: usize = 240;
+// How long the result of a config reload stays visible
+const RELOAD_MESSAGE_DURATION: Duration = Duration::from_secs(4);
+
+fn popup_size() -> (i32, i32) {
+    if SETTINGS_OPEN.load(Ordering::Relaxed) {
+        (SETTINGS_WIDTH, SETTINGS_HEIGHT)
+    } else {
+        (POPUP_WIDTH, POPUP_HEIGHT)
+    }
+}
 
 pub fn start_ui(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the logger
@@ -59,15 +88,17 @@ pub fn start_ui(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std
                             }
                             *visible = false;
                         } else {
+                            let (x, y) = popup_position(position.x as i32, position.y as i32);
+                            let (width, height) = popup_size();
                             unsafe {
                                 SetWindowLongW(window_handle, GWL_STYLE, WS_POPUP.0 as i32);
                                 let _ = SetWindowPos(
                                     window_handle,
                                     HWND_TOPMOST,
-                                    position.x as i32 - 60,
-                                    position.y as i32 - 10 - 120,
-                                    80,
-                                    120,
+                                    x,
+                                    y,
+                                    width,
+                                    height,
                                     windows::Win32::UI::WindowsAndMessaging::SET_WINDOW_POS_FLAGS(0),
                                 );
                                 ShowWindow(window_handle, SW_SHOWDEFAULT);
@@ -83,7 +114,8 @@ pub fn start_ui(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std
                                 GetCursorPos(&mut cursor_pos);
                                 ScreenToClient(window_handle.0 as *mut _, &mut cursor_pos);
                             }
-                            if cursor_pos.x < 0 || cursor_pos.x > 80 || cursor_pos.y < 0 || cursor_pos.y > 120 {
+                            let (width, height) = popup_size();
+                            if cursor_pos.x < 0 || cursor_pos.x > width || cursor_pos.y < 0 || cursor_pos.y > height {
                                 unsafe {
                                     ShowWindow(window_handle, SW_HIDE);
                                 }
@@ -127,9 +159,111 @@ pub fn start_ui(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std
                     }
                 }
             };
+            let mode_button_handler = {
+                let shared_state = Arc::clone(&shared_state);
+                move || {
+                    let mut state = shared_state.lock().unwrap();
+                    state.mode = match state.mode {
+                        Mode::Ambilight => Mode::FocusFollow,
+                        Mode::FocusFollow => Mode::MoodLamp,
+                        Mode::MoodLamp => Mode::SoundVisualizer,
+                        Mode::SoundVisualizer => Mode::EffectPassthrough,
+                        Mode::EffectPassthrough => Mode::Ambilight,
+                    };
+                    println!("Mode switched to {:?}", state.mode);
+                }
+            };
+            let brightness_handler = {
+                let shared_state = Arc::clone(&shared_state);
+                move |brightness: u8| {
+                    shared_state.lock().unwrap().brightness = brightness;
+                }
+            };
+            let settings_toggle_handler = move || {
+                let open = !SETTINGS_OPEN.fetch_xor(true, Ordering::Relaxed);
+                let (width, height) = popup_size();
+                // Grow or shrink upwards so the popup stays above the tray
+                let mut rect: RECT = unsafe { std::mem::zeroed() };
+                unsafe {
+                    GetWindowRect(window_handle.0 as *mut _, &mut rect);
+                    let _ = SetWindowPos(
+                        window_handle,
+                        HWND_TOPMOST,
+                        rect.left,
+                        rect.bottom - height,
+                        width,
+                        height,
+                        windows::Win32::UI::WindowsAndMessaging::SET_WINDOW_POS_FLAGS(0),
+                    );
+                }
+                open
+            };
+            let settings_handler = {
+                let shared_state = Arc::clone(&shared_state);
+                move |settings: &OutputSettings| {
+                    shared_state.lock().unwrap().settings = *settings;
+                }
+            };
+            let settings_provider = {
+                let shared_state = Arc::clone(&shared_state);
+                move || shared_state.lock().unwrap().settings
+            };
+            let pipelines_provider = {
+                let shared_state = Arc::clone(&shared_state);
+                move || shared_state.lock().unwrap().pipelines.clone()
+            };
+            let pipeline_handler = {
+                let shared_state = Arc::clone(&shared_state);
+                move |index: usize, enabled: bool| {
+                    if let Some(pipeline) = shared_state.lock().unwrap().pipelines.get_mut(index) {
+                        pipeline.is_enabled = enabled;
+                        println!("Pipeline {} {}", pipeline.name, if enabled { "enabled" } else { "disabled" });
+                    }
+                }
+            };
+            let save_handler = |settings: &OutputSettings| {
+                if let Err(e) = save_output_settings(settings) {
+                    log::error!("Failed to save settings: {}", e);
+                }
+            };
+            let reload_handler = {
+                let shared_state = Arc::clone(&shared_state);
+                move || {
+                    reload_config(&shared_state).map_err(|e| {
+                        log::error!("Failed to reload config: {}", e);
+                        e.to_string()
+                    })
+                }
+            };
+            let latency_provider = {
+                let shared_state = Arc::clone(&shared_state);
+                move || shared_state.lock().unwrap().latency
+            };
+            let dropped_frames_provider = {
+                let shared_state = Arc::clone(&shared_state);
+                move || shared_state.lock().unwrap().dropped_frames
+            };
+            let loop_times_provider = || METRICS.recent(Stage::Loop, SPARKLINE_SAMPLES);
+            let brightness = shared_state.lock().unwrap().brightness;
             Box::new(MyApp {
                 start_button_handler: Box::new(start_button_handler),
                 stop_button_handler: Box::new(stop_button_handler),
+                mode_button_handler: Box::new(mode_button_handler),
+                brightness_handler: Box::new(brightness_handler),
+                settings_toggle_handler: Box::new(settings_toggle_handler),
+                settings_handler: Box::new(settings_handler),
+                settings_provider: Box::new(settings_provider),
+                save_handler: Box::new(save_handler),
+                reload_handler: Box::new(reload_handler),
+                pipelines_provider: Box::new(pipelines_provider),
+                pipeline_handler: Box::new(pipeline_handler),
+                latency_provider: Box::new(latency_provider),
+                dropped_frames_provider: Box::new(dropped_frames_provider),
+                loop_times_provider: Box::new(loop_times_provider),
+                brightness,
+                settings_open: false,
+                settings: OutputSettings::default(),
+                reload_message: None,
             })
         }),
     );
@@ -140,10 +274,30 @@ pub fn start_ui(shared_state: Arc<Mutex<SharedState>>) -> Result<(), Box<dyn std
 struct MyApp {
     start_button_handler: Box<dyn Fn() + Send>,
     stop_button_handler: Box<dyn Fn() + Send>,
+    mode_button_handler: Box<dyn Fn() + Send>,
+    brightness_handler: Box<dyn Fn(u8) + Send>,
+    settings_toggle_handler: Box<dyn Fn() -> bool + Send>,
+    settings_handler: Box<dyn Fn(&OutputSettings) + Send>,
+    settings_provider: Box<dyn Fn() -> OutputSettings + Send>,
+    save_handler: Box<dyn Fn(&OutputSettings) + Send>,
+    reload_handler: Box<dyn Fn() -> Result<(), String> + Send>,
+    pipelines_provider: Box<dyn Fn() -> Vec<PipelineState> + Send>,
+    pipeline_handler: Box<dyn Fn(usize, bool) + Send>,
+    latency_provider: Box<dyn Fn() -> Option<Duration> + Send>,
+    dropped_frames_provider: Box<dyn Fn() -> u64 + Send>,
+    loop_times_provider: Box<dyn Fn() -> Vec<Duration> + Send>,
+    brightness: u8,
+    settings_open: bool,
+    // Local copy edited by the settings panel
+    settings: OutputSettings,
+    // Result of the last config reload, whether it failed and when it happened
+    reload_message: Option<(String, bool, Instant)>,
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Keep the latency readout and the sparkline current while the popup is open
+        ctx.request_repaint_after(Duration::from_millis(250));
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 if ui.add_sized([80.0, 30.0], egui::Button::new("Start")).clicked() {
@@ -154,6 +308,43 @@ impl eframe::App for MyApp {
                     (self.stop_button_handler)();
                 }
 
+                if ui.add_sized([80.0, 30.0], egui::Button::new("Mode")).clicked() {
+                    (self.mode_button_handler)();
+                }
+
+                let slider = egui::Slider::new(&mut self.brightness, 0..=100).show_value(false);
+                if ui.add_sized([80.0, 20.0], slider).on_hover_text("Brightness").changed() {
+                    (self.brightness_handler)(self.brightness);
+                }
+
+                // Capture to LED latency of the last frame
+                let latency = match (self.latency_provider)() {
+                    Some(latency) => format!("{} ms", latency.as_millis()),
+                    None => "-".to_string(),
+                };
+                ui.label(latency).on_hover_text("End-to-end latency");
+
+                // Recent loop times, spikes show up as stutters on the strip
+                let loop_times = (self.loop_times_provider)();
+                let dropped = (self.dropped_frames_provider)();
+                sparkline(ui, &loop_times).on_hover_text(format!(
+                    "Loop times, max {} ms\n{} dropped frames",
+                    loop_times.iter().max().copied().unwrap_or_default().as_millis(),
+                    dropped
+                ));
+                ui.label(format!("{} dropped", dropped)).on_hover_text("Frames not sent: late or failed");
+
+                let label = if self.settings_open { "Less" } else { "Settings" };
+                if ui.add_sized([80.0, 20.0], egui::Button::new(label)).clicked() {
+                    self.settings_open = (self.settings_toggle_handler)();
+                    // Start from what the backend currently uses
+                    self.settings = (self.settings_provider)();
+                }
+
+                if self.settings_open {
+                    self.settings_panel(ui);
+                }
+
                 if ui.add_sized([80.0, 30.0], egui::Button::new("Quit")).clicked() {
                     std::process::exit(0);
                 }
@@ -162,19 +353,169 @@ impl eframe::App for MyApp {
     }
 }
 
-fn gen_tray_icon() -> Result<TrayIcon, Box<dyn std::error::Error>> {
-    let icon = if let Ok(img) = image::open("res/icon_16x16.png") {
-        let (width, height) = img.dimensions();
-        let rgba = img.to_rgba8().into_raw();
-        Icon::from_rgba(rgba, width, height)?
-    } else {
-        let mut icon_data: Vec<u8> = Vec::with_capacity(16 * 16 * 4);
-        for _ in 0..256 {
-            icon_data.extend_from_slice(&[255, 0, 0, 255]);
+impl MyApp {
+    // Output settings, applied live and written to the config on Save
+    fn settings_panel(&mut self, ui: &mut egui::Ui) {
+        let settings = &mut self.settings;
+        let mut changed = false;
+
+        ui.separator();
+        changed |= ui.checkbox(&mut settings.is_gamma_enabled, "Gamma").changed();
+        ui.add_enabled_ui(settings.is_gamma_enabled, |ui| {
+            changed |= ui
+                .add(egui::Slider::new(&mut settings.gamma, 0.5..=3.0).step_by(0.01).text("Gamma"))
+                .changed();
+        });
+        changed |= ui.checkbox(&mut settings.is_smoothing_enabled, "Smoothing").changed();
+        ui.add_enabled_ui(settings.is_smoothing_enabled, |ui| {
+            changed |= ui
+                .add(egui::Slider::new(&mut settings.smooth, 0..=255).text("Smooth"))
+                .changed();
+        });
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.vibrance, 0.0..=1.0).text("Vibrance"))
+            .changed();
+        changed |= ui
+            .checkbox(&mut settings.is_color_temperature_enabled, "Color temperature")
+            .changed();
+        ui.add_enabled_ui(settings.is_color_temperature_enabled, |ui| {
+            changed |= ui
+                .add(egui::Slider::new(&mut settings.color_temperature, 1000..=10000).step_by(100.0).suffix(" K"))
+                .changed();
+        });
+
+        if changed {
+            (self.settings_handler)(settings);
+        }
+
+        if ui.add_sized([80.0, 20.0], egui::Button::new("Save")).clicked() {
+            (self.save_handler)(settings);
+        }
+
+        let reload = ui
+            .add_sized([80.0, 20.0], egui::Button::new("Reload"))
+            .on_hover_text("Reload the config file\nOutput settings and LED layouts apply right away, other changes after a restart");
+        if reload.clicked() {
+            self.reload_message = Some(match (self.reload_handler)() {
+                Ok(()) => {
+                    // The panel shows what the backend uses now
+                    *settings = (self.settings_provider)();
+                    ("Config reloaded".to_string(), false, Instant::now())
+                }
+                Err(e) => (e, true, Instant::now()),
+            });
+        }
+        if let Some((message, is_error, at)) = &self.reload_message {
+            if at.elapsed() < RELOAD_MESSAGE_DURATION {
+                let color = if *is_error { ui.visuals().error_fg_color } else { ui.visuals().text_color() };
+                ui.label(egui::RichText::new(message).color(color));
+            }
+        }
+
+        // Independent zones from the config, only worth showing with more than one
+        let pipelines = (self.pipelines_provider)();
+        if pipelines.len() > 1 {
+            ui.separator();
+            for (index, pipeline) in pipelines.iter().enumerate() {
+                let mut enabled = pipeline.is_enabled;
+                let health = &pipeline.health;
+                // Devices the backend is backing off from are marked
+                let name = match health.backoff_remaining() {
+                    Some(_) => egui::RichText::new(&pipeline.name).color(ui.visuals().error_fg_color),
+                    None => egui::RichText::new(&pipeline.name),
+                };
+                let hover = format!(
+                    "{} sent, {} failed\nLast send: {}{}",
+                    health.sent,
+                    health.failed,
+                    health.last_latency.map_or("-".to_string(), |latency| format!("{} ms", latency.as_millis())),
+                    health
+                        .backoff_remaining()
+                        .map_or(String::new(), |remaining| format!("\nPaused for {} s", remaining.as_secs() + 1)),
+                );
+                if ui.checkbox(&mut enabled, name).on_hover_text(hover).changed() {
+                    (self.pipeline_handler)(index, enabled);
+                }
+            }
         }
-        Icon::from_rgba(icon_data, 16, 16)?
+    }
+}
+
+// Line of the durations scaled to the slowest one, drawn into an 80x24 box
+fn sparkline(ui: &mut egui::Ui, durations: &[Duration]) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(80.0, 24.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let max = durations.iter().max().copied().unwrap_or_default().as_secs_f32();
+    if durations.len() >= 2 && max > 0.0 {
+        let step = rect.width() / (durations.len() - 1) as f32;
+        let points: Vec<egui::Pos2> = durations
+            .iter()
+            .enumerate()
+            .map(|(i, duration)| {
+                let y = rect.bottom() - duration.as_secs_f32() / max * (rect.height() - 2.0) - 1.0;
+                egui::pos2(rect.left() + i as f32 * step, y)
+            })
+            .collect();
+        painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, ui.visuals().text_color())));
+    }
+    response
+}
+
+// Places the popup above the tray click, kept inside the work area of the
+// monitor that was clicked so it never ends up off-screen or under the taskbar
+fn popup_position(click_x: i32, click_y: i32) -> (i32, i32) {
+    let (width, height) = popup_size();
+    let x = click_x - width / 2;
+    let y = click_y - 10 - height;
+
+    let monitors = match get_monitor_info() {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            log::error!("Failed to get monitor info for popup placement: {}", e);
+            return (x, y);
+        }
+    };
+    let Some(monitor) = monitors
+        .iter()
+        .find(|m| m.contains(click_x, click_y))
+        .or_else(|| monitors.first())
+    else {
+        return (x, y);
     };
 
+    let max_x = monitor.work_pos_x + monitor.work_width - width;
+    let max_y = monitor.work_pos_y + monitor.work_height - height;
+    (
+        x.min(max_x).max(monitor.work_pos_x),
+        y.min(max_y).max(monitor.work_pos_y),
+    )
+}
+
+// Blocking error dialog, for problems that happen before the tray UI is up
+pub fn show_error(message: &str) {
+    unsafe {
+        MessageBoxW(None, &HSTRING::from(message), &HSTRING::from("Lightshow"), MB_OK | MB_ICONERROR);
+    }
+}
+
+// The icon from LIGHTSHOW_TRAY_ICON if set and readable, the built-in one otherwise
+fn tray_icon_image() -> Result<image::RgbaImage, image::ImageError> {
+    if let Ok(path) = std::env::var(TRAY_ICON_ENV) {
+        match image::open(&path) {
+            Ok(img) => return Ok(img.to_rgba8()),
+            Err(e) => log::warn!("Failed to load tray icon {}, using the built-in one: {}", path, e),
+        }
+    }
+    Ok(image::load_from_memory(TRAY_ICON)?.to_rgba8())
+}
+
+fn gen_tray_icon() -> Result<TrayIcon, Box<dyn std::error::Error>> {
+    let icon = tray_icon_image()?;
+    let (width, height) = icon.dimensions();
+    let icon = Icon::from_rgba(icon.into_raw(), width, height)?;
+
     let tray_icon = TrayIconBuilder::new()
         .with_icon(icon)
         .with_tooltip("My App")
@@ -1,40 +1,85 @@
 use std::{
-    io::{self, Write}, mem::zeroed, thread, time::{Duration, Instant}
+    fmt, io::{self, Write}, mem::zeroed, sync::atomic::Ordering, thread, time::{Duration, Instant}
 };
 use windows_capture::{
     capture::GraphicsCaptureApiHandler,
     frame::Frame,
     graphics_capture_api::InternalCaptureControl,
     monitor::Monitor,
+    settings::ColorFormat,
 };
 
-use winapi::um::winuser::{GetMonitorInfoW, MONITORINFOEXW};
+use windows::Win32::Foundation::{HANDLE, HWND};
+use windows::core::PCWSTR;
+use windows::Win32::Graphics::Gdi::{
+    BitBlt, CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, EnumDisplaySettingsW, GdiFlush,
+    GetDC, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DEVMODEW, DIB_RGB_COLORS,
+    ENUM_CURRENT_SETTINGS, SRCCOPY,
+};
+use winapi::shared::windef::RECT;
+use winapi::um::winuser::{GetForegroundWindow, GetMonitorInfoW, GetWindowRect, MONITORINFOEXW};
 
-use crate::backend::FRAME_MAP;
+use crate::backend::{capture_fps, capture_heartbeat, frame_interval, frame_map, CAPTURE_DOWNSCALE};
+use crate::error::Error;
+use crate::screen_capture::downscale_frame_data;
 
 // Struct to hold monitor information
 #[derive(Debug, Clone)]
 pub struct MonitorInfo {
     pub monitor: *mut std::ffi::c_void,
+    // Stable id the frames are stored under in FRAME_MAP and the config refers to, taken
+    // from the display device name (\\.\DISPLAY1 is 0) so it doesn't depend on the
+    // order Monitor::enumerate happens to return
+    pub id: i32,
     pub pos_x: i32,
     pub pos_y: i32,
     pub width: i32,
     pub height: i32,
+    // Monitor area without the taskbar and docked toolbars
+    pub work_pos_x: i32,
+    pub work_pos_y: i32,
+    pub work_width: i32,
+    pub work_height: i32,
+    // Display refresh rate in Hz, 0 if the driver doesn't report one
+    pub refresh_rate: u32,
+}
+
+impl MonitorInfo {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.pos_x && x < self.pos_x + self.width && y >= self.pos_y && y < self.pos_y + self.height
+    }
+}
+
+// Settings handed to each capture through the windows-capture flags string.
+// Formatted by the backend with Display and read back by parse_flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureFlags {
+    pub monitor_id: i32,
+    pub fps_limit: u32,
+}
+
+impl fmt::Display for CaptureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.monitor_id, self.fps_limit)
+    }
 }
 
 // Function to parse flags from a string
-fn parse_flags(flags: &str) -> Result<(i32, u32), Box<dyn std::error::Error>> {
+pub fn parse_flags(flags: &str) -> Result<CaptureFlags, Error> {
     let parts: Vec<&str> = flags.split(',').collect();
     if parts.len() != 2 {
-        return Err("Invalid flags format".into());
+        return Err(Error::Validation(format!("Invalid flags format: {:?}", flags)));
     }
-    let id = parts[0].trim().parse::<i32>()?;
-    let fps_limit = parts[1].trim().parse::<u32>()?;
-    Ok((id, fps_limit))
+    let invalid = |e: std::num::ParseIntError| Error::Validation(format!("Invalid flags {:?}: {}", flags, e));
+    let monitor_id = parts[0].trim().parse::<i32>().map_err(invalid)?;
+    let fps_limit = parts[1].trim().parse::<u32>().map_err(invalid)?;
+    Ok(CaptureFlags { monitor_id, fps_limit })
 }
 
 #[derive(Debug, Clone)]
 pub struct SlimMonitorInfo {
+    // Id the capture stores this monitor's frames under in FRAME_MAP, see MonitorInfo::id
+    pub id: i32,
     pub pos_x: i32,
     pub pos_y: i32,
     pub width: i32,
@@ -44,6 +89,7 @@ pub struct SlimMonitorInfo {
 impl MonitorInfo {
     pub fn export(&self) -> SlimMonitorInfo {
         SlimMonitorInfo {
+            id: self.id,
             pos_x: self.pos_x,
             pos_y: self.pos_y,
             width: self.width,
@@ -52,10 +98,72 @@ impl MonitorInfo {
     }
 }
 
+// Layout of the pixels in a captured frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    #[default]
+    Rgba8,
+    Bgra8,
+    // Half float per channel in linear scRGB (1.0 = SDR white), more precision than a
+    // 10 bit panel delivers. Combined and averaged as f32, only the LED colors are quantized.
+    Rgba16F,
+}
+
+impl PixelFormat {
+    // Maps the Grab PixelFormat setting, unknown values fall back to RGBA
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "Bgra8" => PixelFormat::Bgra8,
+            "Rgba16F" => PixelFormat::Rgba16F,
+            _ => PixelFormat::Rgba8,
+        }
+    }
+
+    pub fn from_color_format(format: ColorFormat) -> Option<Self> {
+        match format {
+            ColorFormat::Rgba8 => Some(PixelFormat::Rgba8),
+            ColorFormat::Bgra8 => Some(PixelFormat::Bgra8),
+            ColorFormat::Rgba16F => Some(PixelFormat::Rgba16F),
+        }
+    }
+
+    pub fn color_format(&self) -> ColorFormat {
+        match self {
+            PixelFormat::Rgba8 => ColorFormat::Rgba8,
+            PixelFormat::Bgra8 => ColorFormat::Bgra8,
+            PixelFormat::Rgba16F => ColorFormat::Rgba16F,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba16F => 8,
+            _ => 4,
+        }
+    }
+}
+
 // Struct to hold captured frame data
 #[derive(Debug, Clone)]
 pub struct FrameData {
     pub data: Vec<u8>,
+    pub format: PixelFormat,
+    // When the frame arrived from the capture, for end-to-end latency
+    pub captured_at: Instant,
+    // Factor the frame was shrunk by at capture, 1 = monitor resolution
+    pub scale: u32,
+}
+
+impl FrameData {
+    // Shrinks a frame of `width`x`height` by CAPTURE_DOWNSCALE before it is stored.
+    // Half float frames stay half float.
+    fn captured(data: Vec<u8>, width: u32, height: u32, format: PixelFormat, captured_at: Instant) -> Self {
+        let scale = CAPTURE_DOWNSCALE.load(Ordering::Relaxed).max(1);
+        if scale == 1 {
+            return FrameData { data, format, captured_at, scale };
+        }
+        FrameData { data: downscale_frame_data(&data, format, width, height, scale), format, captured_at, scale }
+    }
 }
 
 
@@ -84,19 +192,17 @@ impl GraphicsCaptureApiHandler for Capture {
 
     // Function that will be called to create the struct. The flags can be passed from settings.
     fn new(flags: Self::Flags) -> Result<Self, Self::Error> {        
-        let flags = match parse_flags(&flags) {
-            Ok(f) => f,
-            Err(_e) => (0, 10),
-        };
+        // Bad flags are a bug in the backend, fail the capture instead of guessing
+        let flags = parse_flags(&flags).map_err(|e| e.to_string())?;
         
         Ok(            
             Self {
-                id: flags.0,
+                id: flags.monitor_id,
                 process_time: Instant::now(),
                 frame_time: Instant::now(),
                 frame_count: 0,
                 last_fps_log: Instant::now(),
-                fps_limit: flags.1,
+                fps_limit: flags.fps_limit,
             }
         )
     }
@@ -133,6 +239,10 @@ impl GraphicsCaptureApiHandler for Capture {
 
         // ---------- Processing the frame ----------
         // ---------- Enqueue the frame ----------
+        let Some(format) = PixelFormat::from_color_format(frame.color_format()) else {
+            return Err(format!("Unsupported capture format: {:?}", frame.color_format()).into());
+        };
+        let captured_at = Instant::now();
         if let Ok(mut buffer) = frame.buffer() {
             let frame_bytes = match buffer.as_raw_nopadding_buffer() {
                 Ok(raw_buffer) => raw_buffer.to_vec(),
@@ -141,14 +251,8 @@ impl GraphicsCaptureApiHandler for Capture {
                     return Err(Box::new(e));
                 }
             };
-            let frame_data = FrameData {
-                data: frame_bytes,
-            };
-            if let Ok(mut map) = FRAME_MAP.lock() {
-                map.insert(self.id.clone(), frame_data);
-            } else {
-                log::error!("Failed to lock FRAME_MAP");
-            }
+            let frame_data = FrameData::captured(frame_bytes, buffer.width(), buffer.height(), format, captured_at);
+            frame_map().insert(self.id.clone(), frame_data);
         }
 
         // ---------- End of processing the frame / cleanup ----------
@@ -156,10 +260,12 @@ impl GraphicsCaptureApiHandler for Capture {
 
         // ---------- FPS Limiting ----------
         // Sleep for a short time to avoid high CPU usage
-        if self.fps_limit > 0 {
+        // The backend may lower the rate while the desktop is static, the flags only count
+        // until it sets one. A limit of 0 is uncapped, frames are taken as fast as Windows
+        // delivers them.
+        if let Some(frame_duration) = frame_interval(capture_fps(self.fps_limit)) {
             // Calc remaining frame time
             let elapsed = self.frame_time.elapsed();
-            let frame_duration = Duration::from_secs_f32(1.0 / self.fps_limit as f32);
             if let Some(remaining) = frame_duration.checked_sub(elapsed) {
                 if remaining.as_secs_f32() > 0.0 {
                     thread::sleep(remaining);
@@ -180,12 +286,80 @@ impl GraphicsCaptureApiHandler for Capture {
     }
 }
 
+// Fallback for systems where Windows.Graphics.Capture is unavailable (before Windows 10
+// 1903, some VMs and remote sessions). Copies the monitor out of the desktop with GDI
+// BitBlt into FRAME_MAP like the regular capture. Slower, but works everywhere.
+// Only returns if copying fails.
+pub fn run_gdi_capture(monitor_id: i32, monitor: &SlimMonitorInfo, fps_limit: u32) -> Result<(), Error> {
+    let (width, height) = (monitor.width, monitor.height);
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width,
+            biHeight: -height, // Negative for top-down rows like the other captures
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    unsafe {
+        let screen_dc = GetDC(HWND(0));
+        let memory_dc = CreateCompatibleDC(screen_dc);
+        let mut bits: *mut std::ffi::c_void = std::ptr::null_mut();
+        let bitmap = match CreateDIBSection(memory_dc, &info, DIB_RGB_COLORS, &mut bits, HANDLE(0), 0) {
+            Ok(bitmap) => bitmap,
+            Err(e) => {
+                DeleteDC(memory_dc);
+                ReleaseDC(HWND(0), screen_dc);
+                return Err(Error::Capture(e.to_string()));
+            }
+        };
+        let previous = SelectObject(memory_dc, bitmap);
+        let frame_len = (width * height * 4) as usize;
+
+        let mut frame_time = Instant::now();
+        let result = loop {
+            if let Err(e) = BitBlt(memory_dc, 0, 0, width, height, screen_dc, monitor.pos_x, monitor.pos_y, SRCCOPY) {
+                break Err(Error::Capture(e.to_string()));
+            }
+            let captured_at = Instant::now();
+            GdiFlush();
+
+            // GDI leaves the alpha channel empty
+            let mut data = std::slice::from_raw_parts(bits as *const u8, frame_len).to_vec();
+            for pixel in data.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+            let frame_data = FrameData::captured(data, width as u32, height as u32, PixelFormat::Bgra8, captured_at);
+            frame_map().insert(monitor_id, frame_data);
+            capture_heartbeat(monitor_id);
+
+            // Uncapped at 0, copying as fast as BitBlt allows
+            if let Some(frame_duration) = frame_interval(capture_fps(fps_limit)) {
+                if let Some(remaining) = frame_duration.checked_sub(frame_time.elapsed()) {
+                    thread::sleep(remaining);
+                }
+            }
+            frame_time = Instant::now();
+        };
+
+        SelectObject(memory_dc, previous);
+        DeleteObject(bitmap);
+        DeleteDC(memory_dc);
+        ReleaseDC(HWND(0), screen_dc);
+        result
+    }
+}
+
 // Function to retrieve monitor information
-pub fn get_monitor_info() -> Result<Vec<MonitorInfo>, Box<dyn std::error::Error>> {
-    let monitors = Monitor::enumerate()?;
+pub fn get_monitor_info() -> Result<Vec<MonitorInfo>, Error> {
+    let monitors = Monitor::enumerate().map_err(|e| Error::Capture(e.to_string()))?;
     let mut monitor_info_list = Vec::new();
 
-    for monitor in monitors.iter() {
+    for (index, monitor) in monitors.iter().enumerate() {
         let mut mi: MONITORINFOEXW = unsafe { zeroed() };
         unsafe {
             mi.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
@@ -194,21 +368,82 @@ pub fn get_monitor_info() -> Result<Vec<MonitorInfo>, Box<dyn std::error::Error>
                 let y = mi.rcMonitor.top;
                 let width: i32 = mi.rcMonitor.right - mi.rcMonitor.left;
                 let height = mi.rcMonitor.bottom - mi.rcMonitor.top;
+                let work = mi.rcWork;
+
+                // Fall back to the enumeration index for unusual device names. A taken id moves
+                // past the highest one so two monitors never share a FRAME_MAP slot
+                let mut id = display_id(&mi.szDevice).unwrap_or(index as i32);
+                if monitor_info_list.iter().any(|m: &MonitorInfo| m.id == id) {
+                    let free_id = monitor_info_list.iter().map(|m| m.id).max().unwrap_or(id) + 1;
+                    log::warn!("Monitor id {} is taken, using {} for monitor {}", id, free_id, index);
+                    id = free_id;
+                }
 
                 monitor_info_list.push(MonitorInfo {
                     monitor: monitor.as_raw_hmonitor(),
+                    id,
                     pos_x: x,
                     pos_y: y,
                     width,
                     height,
+                    work_pos_x: work.left,
+                    work_pos_y: work.top,
+                    work_width: work.right - work.left,
+                    work_height: work.bottom - work.top,
+                    refresh_rate: refresh_rate(&mi.szDevice),
                 });
             }
         }
     }
 
+    if monitor_info_list.len() != monitors.len() {
+        log::warn!(
+            "Only got info for {} of {} monitors, the others are not captured",
+            monitor_info_list.len(),
+            monitors.len()
+        );
+    }
+    monitor_info_list.sort_by_key(|m| m.id);
+
     Ok(monitor_info_list)
 }
 
+// Zero based display number from a device name like \\.\DISPLAY2 (null terminated UTF-16)
+fn display_id(device: &[u16]) -> Option<i32> {
+    let len = device.iter().position(|&c| c == 0).unwrap_or(device.len());
+    let name = String::from_utf16_lossy(&device[..len]);
+    let number: i32 = name.rsplit_once("DISPLAY")?.1.parse().ok()?;
+    Some(number - 1)
+}
+
+// Current refresh rate of the display device named `device` (from MONITORINFOEXW)
+fn refresh_rate(device: &[u16]) -> u32 {
+    let mut mode: DEVMODEW = unsafe { zeroed() };
+    mode.dmSize = std::mem::size_of::<DEVMODEW>() as u16;
+    let found = unsafe { EnumDisplaySettingsW(PCWSTR(device.as_ptr()), ENUM_CURRENT_SETTINGS, &mut mode) };
+    // 0 and 1 stand for the hardware default rate, which doesn't tell us anything
+    if found.as_bool() && mode.dmDisplayFrequency > 1 {
+        mode.dmDisplayFrequency
+    } else {
+        0
+    }
+}
+
+// Screen rectangle (x, y, width, height) of the window the user is working in
+pub fn foreground_window_rect() -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.is_null() {
+            return None;
+        }
+        let mut rect: RECT = zeroed();
+        if GetWindowRect(window, &mut rect) == 0 {
+            return None;
+        }
+        Some((rect.left, rect.top, rect.right - rect.left, rect.bottom - rect.top))
+    }
+}
+
 // Function to capture a single screenshot
 //pub fn capture_screen(queue: &ConcurrentQueue<FrameData>) -> Result<(), Box<dyn std::error::Error>> {
 //    // Enumerate monitors and select the first one
@@ -0,0 +1,159 @@
+//! Ambient lighting: captures the screen, averages the colors behind each LED
+//! region and streams them to a WLED controller.
+//!
+//! The binary adds the tray GUI on top; embedders can drive the pipeline through
+//! [`CaptureController`] or use the building blocks directly.
+
+pub mod gui;
+pub mod backend;
+pub mod screen_capture;
+pub mod arduino;
+mod logger;
+pub mod config;
+pub mod hardware_interaction;
+pub mod audio;
+pub mod post_processing;
+pub mod status_server;
+pub mod output;
+pub mod metrics;
+pub mod error;
+pub mod gpu;
+pub mod calibration;
+
+pub use backend::CaptureController;
+pub use error::Error;
+pub use config::{read_config, Config, LED};
+pub use screen_capture::{calculate_avg_colors, AveragingOptions, Color};
+
+// Output mode selected by the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Ambilight,
+    // Ambilight sampled around the foreground window instead of the whole desktop
+    FocusFollow,
+    MoodLamp,
+    SoundVisualizer,
+    // A WLED effect tinted with the dominant screen color
+    EffectPassthrough,
+}
+
+impl Mode {
+    // Maps the Prismatik `LightpackMode` value to a mode, defaulting to Ambilight
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "FocusFollow" => Mode::FocusFollow,
+            "MoodLamp" => Mode::MoodLamp,
+            "SoundVisualizer" => Mode::SoundVisualizer,
+            "EffectPassthrough" => Mode::EffectPassthrough,
+            _ => Mode::Ambilight,
+        }
+    }
+}
+
+// Output settings that can be tuned live from the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputSettings {
+    pub is_gamma_enabled: bool,
+    pub gamma: f64,
+    pub is_smoothing_enabled: bool,
+    pub smooth: u8,
+    pub vibrance: f32,
+    pub is_color_temperature_enabled: bool,
+    pub color_temperature: u16,
+}
+
+impl OutputSettings {
+    pub fn from_config(config: &Config) -> Self {
+        OutputSettings {
+            is_gamma_enabled: config.Device.IsGammaEnabled,
+            gamma: config.Device.Gamma,
+            is_smoothing_enabled: config.Device.IsSmoothingEnabled,
+            smooth: config.Device.Smooth,
+            vibrance: config.Device.Vibrance,
+            is_color_temperature_enabled: config.Grab.IsApplyColorTemperatureEnabled,
+            color_temperature: config.Grab.ColorTemperature,
+        }
+    }
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            is_gamma_enabled: false,
+            gamma: 1.0,
+            is_smoothing_enabled: false,
+            smooth: 0,
+            vibrance: 0.0,
+            is_color_temperature_enabled: false,
+            color_temperature: 6500,
+        }
+    }
+}
+
+// A capture-to-strip zone as seen by the UI
+#[derive(Debug, Clone)]
+pub struct PipelineState {
+    pub name: String,
+    pub is_enabled: bool,
+    // Send statistics of the pipeline's device, published by its processing loop
+    pub health: output::DeviceHealth,
+    // Set by a config reload, the processing loop switches to this layout on its next frame
+    pub pending_layout: Option<LedLayout>,
+    // Set by the processing loop while adaptive FPS counts its picture as static
+    pub is_static: bool,
+}
+
+// LEDs of a pipeline and the strip length they are interpolated across
+#[derive(Debug, Clone)]
+pub struct LedLayout {
+    pub leds: Vec<config::LED>,
+    // Device.PhysicalLedCount, or the pipeline's own, 0 = one LED per region
+    pub physical_led_count: usize,
+}
+
+// State shared between the UI and the backend
+pub struct SharedState {
+    // Live brightness in percent set from the UI, scales the configured brightness
+    pub brightness: u8,
+    // Filled from the config when the backend starts, then changed by the UI
+    pub settings: OutputSettings,
+    pub is_active: bool,
+    pub mode: Mode,
+    pub is_shutdown_requested: bool,
+    // Published by the processing loop
+    pub fps: f32,
+    pub dropped_frames: u64,
+    // Capture to LED latency of the last frame sent, None outside the screen modes
+    pub latency: Option<std::time::Duration>,
+    // Where frames of the main pipeline are sent, can be swapped while running
+    pub output: output::SharedSink,
+    // Set once a sink was chosen through CaptureController::set_output, the backend then
+    // doesn't replace it with the configured one
+    pub is_output_set: bool,
+    // Filled by the backend, in the order the pipelines run
+    pub pipelines: Vec<PipelineState>,
+}
+
+impl SharedState {
+    pub fn new() -> Self {
+        SharedState {
+            brightness: 100,
+            settings: OutputSettings::default(),
+            is_active: true,
+            mode: Mode::Ambilight,
+            is_shutdown_requested: false,
+            fps: 0.0,
+            dropped_frames: 0,
+            latency: None,
+            output: output::shared_sink(Box::new(output::WledHttpSink::new(&backend::WLED_ADDRESS, 0))),
+            is_output_set: false,
+            pipelines: Vec::new(),
+        }
+    }
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,31 +1,140 @@
 #![windows_subsystem = "windows"]
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::sync::mpsc::{self, Sender, Receiver};
-
-mod gui;
-mod backend;
-mod screen_capture;
-mod arduino;
-mod logger;
-mod config;
-mod hardware_interaction;
-
-struct SharedState {
-    value: i32,
-    is_active: bool,
-}
 
+use lightshow::{backend, config, gui, SharedState};
+use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // `--threads <n>` sets the averaging threads for any of the modes below
+    if let Some(position) = args.iter().position(|arg| arg == "--threads") {
+        attach_console();
+        let threads = args.get(position + 1).map(|threads| threads.parse::<usize>());
+        let result = match threads {
+            Some(Ok(threads)) => backend::set_averaging_threads(threads).map_err(|e| e.to_string()),
+            Some(Err(e)) => Err(format!("Invalid thread count: {}", e)),
+            None => Err("Usage: lightshow --threads <n>".to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        args.drain(position..position + 2);
+    }
+
+    // `--print-schema` prints the JSON schema of the structured config format
+    if args.get(1).map(String::as_str) == Some("--print-schema") {
+        attach_console();
+        match serde_json::to_string_pretty(&config::schema()) {
+            Ok(schema) => {
+                println!("{}", schema);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Failed to serialize the schema: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `--snapshot [--processed] [image.png]` prints the averaged colors of one frame and
+    // exits, `--processed` shows them after gamma, brightness and color temperature
+    if args.get(1).map(String::as_str) == Some("--snapshot") {
+        attach_console();
+        let processed = args.iter().skip(2).any(|arg| arg == "--processed");
+        let image_path = args.iter().skip(2).find(|arg| *arg != "--processed").map(String::as_str);
+        match backend::snapshot(image_path, processed) {
+            Ok(colors) => {
+                for color in colors {
+                    println!("{}: #{}", color.led_index, color.to_hex());
+                }
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Snapshot failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // `--replay <dir> [fps]` runs the PNG frames of a directory through the averaging,
+    // an fps of 0 as fast as possible
+    if args.get(1).map(String::as_str) == Some("--replay") {
+        attach_console();
+        let Some(dir) = args.get(2) else {
+            eprintln!("Usage: lightshow --replay <dir> [fps]");
+            std::process::exit(1);
+        };
+        let fps = match args.get(3).map(|fps| fps.parse::<u32>()) {
+            Some(Ok(fps)) => Some(fps),
+            Some(Err(e)) => {
+                eprintln!("Invalid fps: {}", e);
+                std::process::exit(1);
+            }
+            None => None,
+        };
+        let result = backend::replay(dir, fps, |path, colors| {
+            println!("{}", path.display());
+            for color in colors {
+                println!("{}: #{}", color.led_index, color.to_hex());
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Replay failed: {}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    // `--calibrate <port>` measures the white balance with a serial colorimeter
+    #[cfg(feature = "colorimeter")]
+    if args.get(1).map(String::as_str) == Some("--calibrate") {
+        attach_console();
+        let Some(port) = args.get(2) else {
+            eprintln!("Usage: lightshow --calibrate <serial port>");
+            std::process::exit(1);
+        };
+        match backend::calibrate_colorimeter(port) {
+            Ok([red, green, blue]) => {
+                println!("CoefRed={:.3}\nCoefGreen={:.3}\nCoefBlue={:.3}", red, green, blue);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Calibration failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Without a usable config there is nothing to run, tell the user where it belongs
+    if let Err(e) = backend::check_config() {
+        gui::show_error(&e.to_string());
+        std::process::exit(1);
+    }
+
     // Initialize the shared state
-    let shared_state = Arc::new(Mutex::new(SharedState { value: 0, is_active: true }));
+    let shared_state = Arc::new(Mutex::new(SharedState::new()));
+
+    // Ctrl+C lets the backend finish its frame and fade out, a second Ctrl+C quits immediately
+    let ctrlc_state = Arc::clone(&shared_state);
+    ctrlc::set_handler(move || {
+        let mut state = ctrlc_state.lock().unwrap();
+        if state.is_shutdown_requested {
+            std::process::exit(1);
+        }
+        state.is_shutdown_requested = true;
+    })
+    .expect("Failed to set Ctrl+C handler");
 
     // Clone the shared state for the backend
     let backend_state = Arc::clone(&shared_state);
     let backend_thread = thread::spawn(move || {
         // Start the backend
         backend::main_program_start(backend_state).unwrap();
+        // The backend only returns after a shutdown request, end capture threads and UI with it
+        std::process::exit(0);
     });
 
     // Initialize the UI on the main thread
@@ -33,4 +142,12 @@ fn main() {
 
     // Wait for the backend thread to finish
     backend_thread.join().unwrap();
-}
\ No newline at end of file
+}
+
+// The binary uses the windows subsystem, so it has to attach to the calling
+// terminal to print anything
+fn attach_console() {
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+// Samples kept per stage, older ones are overwritten
+const CAPACITY: usize = 512;
+
+// Steps of one processing loop iteration that get timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Combine,
+    Average,
+    Sort,
+    Send,
+    // Whole processing loop iteration
+    Loop,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Combine, Stage::Average, Stage::Sort, Stage::Send, Stage::Loop];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Combine => "combine",
+            Stage::Average => "average",
+            Stage::Sort => "sort",
+            Stage::Send => "send",
+            Stage::Loop => "loop",
+        }
+    }
+}
+
+// Recent durations of every stage, written by all processing threads without locking
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    stages: [StageTimings; 5],
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            stages: std::array::from_fn(|_| StageTimings::new()),
+        }
+    }
+
+    pub fn record(&self, stage: Stage, duration: Duration) {
+        self.stages[stage as usize].record(duration);
+        log::trace!("{} took {:?}", stage.name(), duration);
+    }
+
+    // Summary of the samples currently in the buffer, None if the stage never ran
+    pub fn summary(&self, stage: Stage) -> Option<StageSummary> {
+        self.stages[stage as usize].summary()
+    }
+
+    // Up to `count` of the latest durations, oldest first
+    pub fn recent(&self, stage: Stage, count: usize) -> Vec<Duration> {
+        self.stages[stage as usize].recent(count)
+    }
+}
+
+// Fixed size ring buffer of durations in microseconds. Writers claim a slot with one
+// atomic increment, readers copy whatever is there. A reader racing a writer may see
+// one slot from the previous lap, which is fine for statistics.
+struct StageTimings {
+    samples: [AtomicU32; CAPACITY],
+    next: AtomicUsize,
+}
+
+impl StageTimings {
+    fn new() -> Self {
+        StageTimings {
+            samples: std::array::from_fn(|_| AtomicU32::new(0)),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u32::MAX as u128) as u32;
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % CAPACITY;
+        self.samples[slot].store(micros, Ordering::Relaxed);
+    }
+
+    fn recent(&self, count: usize) -> Vec<Duration> {
+        let next = self.next.load(Ordering::Relaxed);
+        let count = count.min(next).min(CAPACITY);
+        (next - count..next)
+            .map(|i| Duration::from_micros(self.samples[i % CAPACITY].load(Ordering::Relaxed) as u64))
+            .collect()
+    }
+
+    fn summary(&self) -> Option<StageSummary> {
+        let count = self.next.load(Ordering::Relaxed).min(CAPACITY);
+        if count == 0 {
+            return None;
+        }
+        let mut samples: Vec<u32> = self.samples[..count].iter().map(|s| s.load(Ordering::Relaxed)).collect();
+        samples.sort_unstable();
+
+        let percentile = |p: f64| {
+            let index = ((count - 1) as f64 * p).round() as usize;
+            Duration::from_micros(samples[index] as u64)
+        };
+        let total: u64 = samples.iter().map(|&s| s as u64).sum();
+        Some(StageSummary {
+            samples: count,
+            average: Duration::from_micros(total / count as u64),
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageSummary {
+    pub samples: usize,
+    pub average: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl StageSummary {
+    pub fn to_json(&self) -> serde_json::Value {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        serde_json::json!({
+            "samples": self.samples,
+            "avg_ms": ms(self.average),
+            "p50_ms": ms(self.p50),
+            "p99_ms": ms(self.p99),
+        })
+    }
+}
+
+// All stages that have samples, keyed by stage name
+pub fn stages_json() -> serde_json::Value {
+    let stages: serde_json::Map<String, serde_json::Value> = Stage::ALL
+        .iter()
+        .filter_map(|&stage| Some((stage.name().to_string(), METRICS.summary(stage)?.to_json())))
+        .collect();
+    serde_json::Value::Object(stages)
+}
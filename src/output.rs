@@ -0,0 +1,346 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+
+use crate::arduino;
+use crate::error::Error;
+use crate::screen_capture::Color;
+
+// Destination for the final colors of every frame. The backend only talks to the
+// active sink, so protocols can be swapped while it is running.
+pub trait OutputSink: Send + Sync {
+    fn send(&self, colors: &[Color]) -> Result<(), Error>;
+
+    // Used in logs
+    fn name(&self) -> &str;
+
+    // Called after repeated send failures, on a Reconnector thread: drops connection state
+    // that may have gone stale (network change, device reboot) and checks the device is
+    // reachable again
+    fn reconnect(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// WLED JSON API over HTTP, writing into one segment
+pub struct WledHttpSink {
+    address: String,
+    segment_id: u8,
+}
+
+impl WledHttpSink {
+    pub fn new(address: &str, segment_id: u8) -> Self {
+        WledHttpSink {
+            address: address.to_string(),
+            segment_id,
+        }
+    }
+}
+
+impl OutputSink for WledHttpSink {
+    fn send(&self, colors: &[Color]) -> Result<(), Error> {
+        arduino::set_pixels(&self.address, self.segment_id, colors.to_vec())
+    }
+
+    fn name(&self) -> &str {
+        "WLED HTTP"
+    }
+
+    // Every request opens its own connection, so only the device has to be back.
+    // A rebooted WLED forgot realtime control and gets it handed over again.
+    fn reconnect(&self) -> Result<(), Error> {
+        arduino::check_wled_online(&self.address)?;
+        arduino::acquire_control(&self.address, self.segment_id)
+    }
+}
+
+// Port WLED listens on for realtime UDP
+pub const WLED_UDP_PORT: u16 = 21324;
+// Default datagram size limit, below the usual 1500 byte Ethernet MTU minus IP/UDP headers
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 1400;
+// DNRGB: protocol byte, timeout byte and a 16 bit start index, then RGB per LED
+const DNRGB_PROTOCOL: u8 = 4;
+const DNRGB_HEADER_SIZE: usize = 4;
+// WLED accepts at most this many LEDs in one DNRGB packet
+const DNRGB_MAX_LEDS: usize = 489;
+// Seconds WLED stays in realtime mode after the last packet
+const UDP_TIMEOUT_SECS: u8 = 2;
+
+// Splits a frame into DNRGB datagrams of at most `max_packet_size` bytes, each starting
+// with the index of its first LED so WLED puts every part in the right place
+pub fn dnrgb_packets(colors: &[Color], max_packet_size: usize) -> Vec<Vec<u8>> {
+    let leds_per_packet = (max_packet_size.saturating_sub(DNRGB_HEADER_SIZE) / 3).clamp(1, DNRGB_MAX_LEDS);
+    colors
+        .chunks(leds_per_packet)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let start = (i * leds_per_packet) as u16;
+            let mut packet = Vec::with_capacity(DNRGB_HEADER_SIZE + chunk.len() * 3);
+            packet.extend_from_slice(&[DNRGB_PROTOCOL, UDP_TIMEOUT_SECS]);
+            packet.extend_from_slice(&start.to_be_bytes());
+            packet.extend(chunk.iter().flat_map(Color::to_rgb8));
+            packet
+        })
+        .collect()
+}
+
+// WLED realtime UDP (DNRGB). Lower overhead than HTTP, but always covers the whole strip
+// from LED 0, segments don't apply.
+pub struct WledUdpSink {
+    // Replaced by reconnect, a socket connected before a network change keeps the old route
+    socket: Mutex<UdpSocket>,
+    address: String,
+    port: u16,
+    name: String,
+    max_packet_size: usize,
+    // Splitting is logged once, not every frame
+    split_logged: AtomicBool,
+}
+
+impl WledUdpSink {
+    pub fn new(address: &str, port: u16, max_packet_size: usize) -> Result<Self, Error> {
+        Ok(WledUdpSink {
+            socket: Mutex::new(Self::connect(address, port)?),
+            address: address.to_string(),
+            port,
+            name: format!("WLED UDP {}:{}", address, port),
+            max_packet_size,
+            split_logged: AtomicBool::new(false),
+        })
+    }
+
+    fn connect(address: &str, port: u16) -> Result<UdpSocket, Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect((address, port))?;
+        Ok(socket)
+    }
+}
+
+impl OutputSink for WledUdpSink {
+    fn send(&self, colors: &[Color]) -> Result<(), Error> {
+        let packets = dnrgb_packets(colors, self.max_packet_size);
+        if packets.len() > 1 && !self.split_logged.swap(true, Ordering::Relaxed) {
+            log::info!(
+                "{} LEDs don't fit one {} byte datagram, sending {} DNRGB packets per frame",
+                colors.len(),
+                self.max_packet_size,
+                packets.len()
+            );
+        }
+        let socket = self.socket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for packet in &packets {
+            socket.send(packet)?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+        let socket = Self::connect(&self.address, self.port)?;
+        *self.socket.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = socket;
+        // UDP doesn't notice a missing receiver, ask the device over HTTP
+        arduino::check_wled_online(&self.address)
+    }
+}
+
+// How FileSink writes a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    // One line per frame, the LED colors as space separated RRGGBB
+    Hex,
+    // 3 bytes (R, G, B) per LED, frames back to back without separator
+    Raw,
+}
+
+impl FrameFormat {
+    // Hex unless the value is Raw
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "Raw" => FrameFormat::Raw,
+            _ => FrameFormat::Hex,
+        }
+    }
+}
+
+// Writes every frame to stdout, a file or a named pipe so other tools can consume the
+// colors. Each frame is flushed right away so readers see it at the capture rate.
+pub struct FileSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+    format: FrameFormat,
+    name: String,
+}
+
+impl FileSink {
+    // `-` is stdout. Opening a FIFO blocks until a reader connects.
+    pub fn open(path: &str, format: FrameFormat) -> Result<Self, Error> {
+        let writer: Box<dyn Write + Send> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(OpenOptions::new().write(true).create(true).truncate(true).open(path)?)
+        };
+        Ok(FileSink {
+            writer: Mutex::new(writer),
+            format,
+            name: format!("file {}", path),
+        })
+    }
+}
+
+impl OutputSink for FileSink {
+    fn send(&self, colors: &[Color]) -> Result<(), Error> {
+        let frame = match self.format {
+            FrameFormat::Hex => {
+                let hex: Vec<String> = colors.iter().map(Color::to_hex).collect();
+                format!("{}\n", hex.join(" ")).into_bytes()
+            }
+            FrameFormat::Raw => colors.iter().flat_map(Color::to_rgb8).collect(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&frame)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// First and longest pause after a device failed too often in a row
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Send statistics of one device and a circuit breaker: after `threshold` failures in a
+// row the device is skipped for a backoff period that doubles with every further failure.
+// The first send after the pause is a trial, a success closes the breaker again.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceHealth {
+    pub sent: u64,
+    pub failed: u64,
+    pub consecutive_failures: u32,
+    // Duration of the last successful send
+    pub last_latency: Option<Duration>,
+    backoff: Duration,
+    backoff_until: Option<Instant>,
+}
+
+impl DeviceHealth {
+    // False while the device is backed off
+    pub fn is_available(&self) -> bool {
+        self.backoff_remaining().is_none()
+    }
+
+    pub fn record_success(&mut self, latency: Duration) {
+        self.sent += 1;
+        self.consecutive_failures = 0;
+        self.last_latency = Some(latency);
+        self.backoff = Duration::ZERO;
+        self.backoff_until = None;
+    }
+
+    // Opens the breaker once `threshold` failures happened in a row, 0 never does
+    pub fn record_failure(&mut self, threshold: u32) {
+        self.failed += 1;
+        self.consecutive_failures += 1;
+        if threshold > 0 && self.consecutive_failures >= threshold {
+            self.backoff = (self.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+            self.backoff_until = Some(Instant::now() + self.backoff);
+        }
+    }
+
+    // Current pause, None if the device is served normally
+    pub fn backoff_remaining(&self) -> Option<Duration> {
+        self.backoff_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "sent": self.sent,
+            "failed": self.failed,
+            "consecutive_failures": self.consecutive_failures,
+            "last_latency_ms": self.last_latency.map(|latency| latency.as_secs_f64() * 1000.0),
+            "backoff_ms": self.backoff_remaining().map(|remaining| remaining.as_millis() as u64),
+        })
+    }
+}
+
+// Reconnects a sink on its own thread, retrying with a pause that doubles up to
+// MAX_BACKOFF until the device answers, so an unreachable device doesn't block the
+// processing loop. The loop skips sends while an attempt is running.
+#[derive(Default)]
+pub struct Reconnector {
+    sink: Option<Arc<Box<dyn OutputSink>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Reconnector {
+    // Starts reconnecting `sink` unless an attempt is already running
+    pub fn start(&mut self, sink: Arc<Box<dyn OutputSink>>) {
+        if self.is_running() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_sink = Arc::clone(&sink);
+        let thread_stop = Arc::clone(&stop);
+        self.handle = Some(thread::spawn(move || {
+            let mut backoff = MIN_BACKOFF;
+            while !thread_stop.load(Ordering::Relaxed) {
+                match thread_sink.reconnect() {
+                    Ok(()) => {
+                        log::info!("{} reconnected", thread_sink.name());
+                        return;
+                    }
+                    Err(e) => {
+                        log::warn!("Reconnect to {} failed, retrying in {:?}: {}", thread_sink.name(), backoff, e);
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+        }));
+        self.sink = Some(sink);
+        self.stop = stop;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    // Whether the running attempt is for `sink`, a sink swapped in meanwhile isn't affected
+    pub fn is_for(&self, sink: &Arc<Box<dyn OutputSink>>) -> bool {
+        self.sink.as_ref().is_some_and(|own| Arc::ptr_eq(own, sink))
+    }
+
+    // Gives up the running attempt, its thread ends after the current pause
+    pub fn cancel(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.sink = None;
+        self.handle = None;
+    }
+}
+
+impl Drop for Reconnector {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// The active sink. Loading it is lock free, so the processing loop reads it every
+// frame and a swap from the UI takes effect on the next send.
+pub type SharedSink = Arc<ArcSwap<Box<dyn OutputSink>>>;
+
+pub fn shared_sink(sink: Box<dyn OutputSink>) -> SharedSink {
+    Arc::new(ArcSwap::from_pointee(sink))
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+
+use crate::config::{parse_hex_color, Device, Grab, Palette};
+use crate::screen_capture::Color;
+use crate::OutputSettings;
+
+// Number of entries in the gamma lookup table, values in between are interpolated
+const LUT_SIZE: usize = 1024;
+
+// Gamma curve over the 0..255 range as a lookup table
+pub struct GammaLut {
+    table: Vec<f32>,
+}
+
+impl GammaLut {
+    pub fn new(gamma: f64) -> Self {
+        let table = (0..LUT_SIZE)
+            .map(|i| {
+                let x = i as f64 / (LUT_SIZE - 1) as f64;
+                (x.powf(gamma) * 255.0) as f32
+            })
+            .collect();
+        GammaLut { table }
+    }
+
+    pub fn apply(&self, value: f32) -> f32 {
+        let pos = (value / 255.0).clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32;
+        let i = pos.floor() as usize;
+        let next = (i + 1).min(LUT_SIZE - 1);
+        let frac = pos - i as f32;
+        self.table[i] + (self.table[next] - self.table[i]) * frac
+    }
+}
+
+// One table per channel, channels without their own gamma use `gamma`
+fn channel_gamma_luts(channel_gammas: [Option<f64>; 3], gamma: f64) -> [GammaLut; 3] {
+    channel_gammas.map(|channel| GammaLut::new(channel.unwrap_or(gamma)))
+}
+
+// RGB multipliers (0..=1) of a black body at `kelvin`, after Tanner Helland's fit.
+// 6500 K is close to neutral, lower values are warmer.
+pub fn color_temperature_gains(kelvin: u16) -> [f32; 3] {
+    let t = kelvin.clamp(1000, 40000) as f32 / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.69873 * (t - 60.0).powf(-0.13320476) };
+    let g = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.075514846)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    [r, g, b].map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+// Saturation boost that scales with how muted a color already is, so vivid colors
+// stay natural while greys and pastels get punchier. `strength` 0 leaves colors untouched.
+pub fn apply_vibrance(color: &mut Color, strength: f32) {
+    let max = color.r.max(color.g).max(color.b);
+    let min = color.r.min(color.g).min(color.b);
+    if max <= 0.0 {
+        return;
+    }
+    let saturation = (max - min) / max;
+    let amount = strength * (1.0 - saturation);
+    let luma = 0.299 * color.r + 0.587 * color.g + 0.114 * color.b;
+    for channel in [&mut color.r, &mut color.g, &mut color.b] {
+        *channel = (*channel + (*channel - luma) * amount).clamp(0.0, 255.0);
+    }
+}
+
+// Tints near-white colors with the `gains` of a warmer white point. The effect fades out
+// with saturation and darkness, so mostly whites and light greys are touched.
+pub fn apply_warm_white_bias(color: &mut Color, gains: [f32; 3], strength: f32) {
+    // The weakest channel is high only for bright, unsaturated colors
+    let whiteness = color.r.min(color.g).min(color.b) / 255.0;
+    let amount = (strength * whiteness).clamp(0.0, 1.0);
+    for (channel, gain) in [&mut color.r, &mut color.g, &mut color.b].into_iter().zip(gains) {
+        *channel *= 1.0 + (gain - 1.0) * amount;
+    }
+}
+
+// Soft gamut clamp: the brightest channel is compressed smoothly from `knee * 255` so it
+// approaches 255 without ever reaching past it, and the other channels are scaled by the
+// same factor. Keeps the hue of colors pushed over full scale (auto exposure, vibrance)
+// instead of clipping each channel on its own. `knee` is 0..1, 1 disables.
+pub fn apply_soft_clamp(color: &mut Color, knee: f32) {
+    let start = knee.clamp(0.0, 1.0) * 255.0;
+    let headroom = 255.0 - start;
+    let max = color.r.max(color.g).max(color.b);
+    if max <= start || headroom <= 0.0 {
+        return;
+    }
+    let compressed = start + headroom * (1.0 - (-(max - start) / headroom).exp());
+    let scale = compressed / max;
+    for channel in [&mut color.r, &mut color.g, &mut color.b] {
+        *channel = channel.max(0.0) * scale;
+    }
+}
+
+// Estimated current of a frame in mA: every channel draws `per_channel` mA at full scale,
+// linearly less when dimmer
+pub fn estimate_current(colors: &[Color], per_channel: f32) -> f32 {
+    colors.iter().map(|c| (c.r.max(0.0) + c.g.max(0.0) + c.b.max(0.0)) / 255.0).sum::<f32>() * per_channel
+}
+
+// Scales all colors down uniformly so the estimated current stays within `budget` mA
+pub fn apply_power_limit(colors: &mut [Color], per_channel: f32, budget: f32) {
+    let current = estimate_current(colors, per_channel);
+    if current <= budget || current <= 0.0 {
+        return;
+    }
+    let scale = budget / current;
+    for color in colors.iter_mut() {
+        color.r *= scale;
+        color.g *= scale;
+        color.b *= scale;
+    }
+}
+
+// Output chain applied to the sorted colors right before they are sent: vibrance,
+// color temperature, warm white bias, soft gamut clamp, gamma, brightness (master and
+// per LED), dithering to 8 bit and finally the power limit.
+pub struct PostProcessor {
+    vibrance: f32,
+    white_balance: Option<[f32; 3]>,
+    // Strength and gains of the warm white bias, None when disabled
+    warm_white: Option<(f32, [f32; 3])>,
+    // Knee of the soft gamut clamp, None hard clamps each channel in the gamma lookup
+    gamut_knee: Option<f32>,
+    gamma_enabled: bool,
+    gamma_value: f64,
+    // Configured R/G/B gammas, None follows gamma_value
+    channel_gammas: [Option<f64>; 3],
+    gamma: [GammaLut; 3],
+    brightness: f32,
+    live_brightness: f32,
+    // Per-LED multipliers by LED index, LEDs without one stay at 1
+    led_brightness: HashMap<i32, f32>,
+    dithering: bool,
+    // Levels per channel the output is quantized to, Device.ColorDepth
+    color_depth: u16,
+    // mA per channel at full scale and the budget in mA, None when unlimited
+    power_limit: Option<(f32, f32)>,
+    // Quantization error carried over to the next frame, per LED
+    dither_error: Vec<[f32; 3]>,
+}
+
+impl PostProcessor {
+    pub fn new(device: &Device) -> Self {
+        PostProcessor {
+            vibrance: device.Vibrance,
+            white_balance: None,
+            warm_white: (device.WarmWhiteBias > 0.0)
+                .then(|| (device.WarmWhiteBias.min(1.0), color_temperature_gains(device.WarmWhiteTemperature))),
+            gamut_knee: (device.GamutKnee > 0.0 && device.GamutKnee < 1.0).then_some(device.GamutKnee),
+            gamma_enabled: device.IsGammaEnabled,
+            gamma_value: device.Gamma,
+            channel_gammas: [device.GammaRed, device.GammaGreen, device.GammaBlue],
+            gamma: channel_gamma_luts([device.GammaRed, device.GammaGreen, device.GammaBlue], device.Gamma),
+            brightness: device.Brightness.min(device.BrightnessCap) as f32 / 100.0,
+            live_brightness: 1.0,
+            led_brightness: HashMap::new(),
+            dithering: device.IsDitheringEnabled,
+            color_depth: match device.ColorDepth {
+                0 => 255,
+                depth => depth.min(255),
+            },
+            power_limit: (device.PowerBudget > 0).then_some((device.MilliampsPerChannel, device.PowerBudget as f32)),
+            dither_error: Vec::new(),
+        }
+    }
+
+    // Takes over settings changed in the UI, the gamma table is only rebuilt when needed
+    pub fn apply_settings(&mut self, settings: &OutputSettings) {
+        self.vibrance = settings.vibrance;
+        self.white_balance = settings
+            .is_color_temperature_enabled
+            .then(|| color_temperature_gains(settings.color_temperature));
+        self.gamma_enabled = settings.is_gamma_enabled;
+        if settings.gamma != self.gamma_value {
+            self.gamma_value = settings.gamma;
+            self.gamma = channel_gamma_luts(self.channel_gammas, settings.gamma);
+        }
+    }
+
+    // Scales the configured brightness, e.g. from the UI slider (0..=100 percent)
+    pub fn set_live_brightness(&mut self, percent: u8) {
+        self.live_brightness = percent.min(100) as f32 / 100.0;
+    }
+
+    // Brightness multipliers by the LED index the colors carry, e.g. from LED.Brightness
+    pub fn set_led_brightness(&mut self, led_brightness: HashMap<i32, f32>) {
+        self.led_brightness = led_brightness;
+    }
+
+    // Drops the rounding error carried between frames, e.g. when the LED layout changes
+    pub fn reset_dither(&mut self) {
+        self.dither_error.clear();
+    }
+
+    pub fn process(&mut self, colors: &mut [Color]) {
+        let brightness = self.brightness * self.live_brightness;
+        for color in colors.iter_mut() {
+            if self.vibrance != 0.0 {
+                apply_vibrance(color, self.vibrance);
+            }
+            if let Some([r, g, b]) = self.white_balance {
+                color.r *= r;
+                color.g *= g;
+                color.b *= b;
+            }
+            if let Some((strength, gains)) = self.warm_white {
+                apply_warm_white_bias(color, gains, strength);
+            }
+            if let Some(knee) = self.gamut_knee {
+                apply_soft_clamp(color, knee);
+            }
+            let brightness = brightness * self.led_brightness.get(&color.led_index).copied().unwrap_or(1.0);
+            if self.gamma_enabled {
+                let [red, green, blue] = &self.gamma;
+                color.r = red.apply(color.r);
+                color.g = green.apply(color.g);
+                color.b = blue.apply(color.b);
+            } else {
+                color.r = color.r.clamp(0.0, 255.0);
+                color.g = color.g.clamp(0.0, 255.0);
+                color.b = color.b.clamp(0.0, 255.0);
+            }
+            color.r *= brightness;
+            color.g *= brightness;
+            color.b *= brightness;
+        }
+
+        // Limit before dithering so the strip still gets whole color steps and the
+        // carried rounding error stays within the budget
+        self.limit_power(colors);
+        if self.dithering {
+            self.dither(colors);
+        } else if self.color_depth < 255 {
+            for color in colors.iter_mut() {
+                *color = color.with_depth(self.color_depth);
+            }
+        }
+    }
+
+    // Applied by `process` before dithering, callers that change colors afterwards apply it again
+    pub fn limit_power(&self, colors: &mut [Color]) {
+        if let Some((per_channel, budget)) = self.power_limit {
+            apply_power_limit(colors, per_channel, budget);
+        }
+    }
+
+    // Temporal error diffusion: each LED rounds to the output color depth and carries the
+    // rounding error into its next frame, so dark gradients average out over time instead of banding.
+    fn dither(&mut self, colors: &mut [Color]) {
+        if self.dither_error.len() != colors.len() {
+            self.dither_error = vec![[0.0; 3]; colors.len()];
+        }
+        let step = 255.0 / self.color_depth as f32;
+
+        for (color, error) in colors.iter_mut().zip(self.dither_error.iter_mut()) {
+            for (channel, err) in [&mut color.r, &mut color.g, &mut color.b]
+                .into_iter()
+                .zip(error.iter_mut())
+            {
+                let target = *channel + *err;
+                let quantized = ((target / step).round() * step).clamp(0.0, 255.0);
+                *err = target - quantized;
+                *channel = quantized;
+            }
+        }
+    }
+}
+
+// Exponential smoothing per LED index. Device.Smooth (0..255) sets how much of the
+// previous color is kept each frame. When the mean change of a frame exceeds the
+// scene-cut threshold the frame is passed through unsmoothed so cuts stay snappy.
+pub struct Smoother {
+    enabled: bool,
+    strength: f32,
+    scene_cut_threshold: f32,
+    perceptual: bool,
+    previous: HashMap<i32, Color>,
+}
+
+impl Smoother {
+    pub fn new(device: &Device) -> Self {
+        Smoother {
+            enabled: device.IsSmoothingEnabled,
+            strength: device.Smooth as f32 / 256.0,
+            scene_cut_threshold: device.SceneCutThreshold,
+            perceptual: device.IsPerceptualSmoothingEnabled,
+            previous: HashMap::new(),
+        }
+    }
+
+    // Device.Smooth scale, 0..255
+    pub fn set_smooth(&mut self, smooth: u8) {
+        self.strength = smooth as f32 / 256.0;
+    }
+
+    // Disabling drops the kept colors, so smoothing starts fresh when it is enabled again
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.reset();
+        }
+    }
+
+    // Forgets the previous frame, the next one is shown as is
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+
+    pub fn apply(&mut self, colors: &mut [Color]) {
+        if !self.enabled {
+            return;
+        }
+        if self.strength > 0.0 && !self.is_scene_cut(colors) {
+            for color in colors.iter_mut() {
+                if let Some(previous) = self.previous.get(&color.led_index) {
+                    *color = color.blend(previous, self.strength, self.perceptual);
+                }
+            }
+        }
+
+        for color in colors.iter() {
+            self.previous.insert(color.led_index, color.clone());
+        }
+    }
+
+    // Mean per-channel change against the previous output, 0..255
+    fn is_scene_cut(&self, colors: &[Color]) -> bool {
+        if self.scene_cut_threshold <= 0.0 {
+            return false;
+        }
+        let (sum, count) = colors
+            .iter()
+            .filter_map(|color| self.previous.get(&color.led_index).map(|previous| (color, previous)))
+            .fold((0.0, 0), |(sum, count), (a, b)| {
+                (sum + (a.r - b.r).abs() + (a.g - b.g).abs() + (a.b - b.b).abs(), count + 3)
+            });
+        count > 0 && sum / count as f32 > self.scene_cut_threshold
+    }
+}
+
+// Share of the sampled pixels allowed above the level treated as the brightest content,
+// so a few white pixels (cursor, subtitles) don't stop dark scenes from being lifted
+const AUTO_EXPOSURE_HIGHLIGHT_SHARE: f32 = 0.02;
+
+// Scales the output so the brightest content of the frame reaches a target level,
+// adapting gradually so the strip doesn't pump on every cut
+pub struct AutoExposure {
+    target: f32,
+    speed: f32,
+    max_gain: f32,
+    gain: f32,
+}
+
+impl AutoExposure {
+    // None when disabled
+    pub fn new(grab: &Grab) -> Option<Self> {
+        if !grab.IsAutoExposureEnabled {
+            return None;
+        }
+        Some(AutoExposure {
+            target: grab.AutoExposureTarget as f32,
+            speed: grab.AutoExposureSpeed.clamp(0.0, 1.0),
+            max_gain: grab.AutoExposureMaxGain.max(1.0),
+            gain: 1.0,
+        })
+    }
+
+    // Moves the gain towards the one that maps the frame's highlights onto the target
+    pub fn update(&mut self, histogram: &[u32; 256]) {
+        let total: u32 = histogram.iter().sum();
+        if total == 0 {
+            return;
+        }
+        let allowed = (total as f32 * AUTO_EXPOSURE_HIGHLIGHT_SHARE) as u32;
+        let mut above = 0;
+        let highlight = histogram
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, count)| {
+                above += **count;
+                above > allowed
+            })
+            .map(|(level, _)| level)
+            .unwrap_or(0);
+
+        // Only brighten: content already at the target is left alone
+        let wanted = (self.target / highlight.max(1) as f32).clamp(1.0, self.max_gain);
+        self.gain += (wanted - self.gain) * self.speed;
+    }
+
+    pub fn apply(&self, colors: &mut [Color]) {
+        for color in colors.iter_mut() {
+            color.r = (color.r * self.gain).min(255.0);
+            color.g = (color.g * self.gain).min(255.0);
+            color.b = (color.b * self.gain).min(255.0);
+        }
+    }
+}
+
+// Snaps every color to the nearest palette entry (RGB distance) for a retro look
+pub struct PaletteLock {
+    colors: Vec<[f32; 3]>,
+}
+
+impl PaletteLock {
+    // None when disabled or no valid colors are configured
+    pub fn new(palette: &Palette) -> Option<Self> {
+        if !palette.IsEnabled {
+            return None;
+        }
+        let colors: Vec<[f32; 3]> = palette
+            .Colors
+            .iter()
+            .filter_map(|hex| match parse_hex_color(hex) {
+                Ok((r, g, b)) => Some([r as f32, g as f32, b as f32]),
+                Err(e) => {
+                    log::error!("Ignoring palette entry: {}", e);
+                    None
+                }
+            })
+            .collect();
+        if colors.is_empty() {
+            log::warn!("Palette lock enabled without valid colors, disabling it");
+            return None;
+        }
+        Some(PaletteLock { colors })
+    }
+
+    pub fn apply(&self, colors: &mut [Color]) {
+        for color in colors.iter_mut() {
+            let distance = |entry: &[f32; 3]| {
+                (entry[0] - color.r).powi(2) + (entry[1] - color.g).powi(2) + (entry[2] - color.b).powi(2)
+            };
+            let nearest = self
+                .colors
+                .iter()
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+                .unwrap();
+            color.r = nearest[0];
+            color.g = nearest[1];
+            color.b = nearest[2];
+        }
+    }
+}
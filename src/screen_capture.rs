@@ -1,35 +1,283 @@
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use image::{GenericImage, GenericImageView, ImageBuffer, Pixel, Primitive, Rgba, Rgba32FImage, RgbaImage};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
+use std::ops::Range;
 use std::path::Path;
 use std::ptr::copy_nonoverlapping;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use crate::config::{Config, LED};
-use crate::hardware_interaction::{FrameData, SlimMonitorInfo};
-use crate::backend::FRAME_MAP;
+use crate::error::Error;
+use crate::config::{parse_hex_color, Config, Edge, Grab, MonitorCalibration, LED};
+use crate::hardware_interaction::{FrameData, PixelFormat, SlimMonitorInfo};
+use crate::backend::frame_map;
 
 // Define the Color struct
+// Channels are kept as f32 in the 0..255 range and only quantized when sent
 #[derive(Debug, Clone)]
 pub struct Color {
     pub led_index: i32,
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
 }
 impl Color {
-    pub fn new(led_index: i32, r: u8, g: u8, b: u8) -> Self {
+    pub fn new(led_index: i32, r: f32, g: f32, b: f32) -> Self {
         Color { led_index, r, g, b }
     }
 
+    pub fn from_rgb8(led_index: i32, r: u8, g: u8, b: u8) -> Self {
+        Color::new(led_index, r as f32, g as f32, b as f32)
+    }
+
+    // Quantizes the channels to integers in 0..=max (e.g. 255 for 8 bit, 65535 for 16 bit)
+    pub fn quantize(&self, max: u16) -> [u16; 3] {
+        let scale = max as f32 / 255.0;
+        let q = |v: f32| (v * scale).round().clamp(0.0, max as f32) as u16;
+        [q(self.r), q(self.g), q(self.b)]
+    }
+
+    // Rounds the channels to the nearest of `depth` + 1 levels spread over 0..255, the
+    // colors a controller limited to Device.ColorDepth can show. 255 and above keeps 8 bit.
+    pub fn with_depth(&self, depth: u16) -> Color {
+        if depth == 0 || depth >= 255 {
+            return self.clone();
+        }
+        let step = 255.0 / depth as f32;
+        let [r, g, b] = self.quantize(depth).map(|level| level as f32 * step);
+        Color::new(self.led_index, r, g, b)
+    }
+
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let [r, g, b] = self.quantize(255);
+        [r as u8, g as u8, b as u8]
+    }
+
     pub fn to_hex(&self) -> String {
-        format!("{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+        let [r, g, b] = self.to_rgb8();
+        format!("{:02X}{:02X}{:02X}", r, g, b)
+    }
+
+    // Parses "RRGGBB" as produced by to_hex, a leading '#' is accepted
+    pub fn from_hex(led_index: i32, hex: &str) -> Result<Self, Error> {
+        let (r, g, b) = parse_hex_color(hex)?;
+        Ok(Color::from_rgb8(led_index, r, g, b))
+    }
+
+    // Linear interpolation towards `other`, t in 0..=1
+    pub fn lerp(&self, other: &Color, t: f32) -> Color {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Color::new(
+            other.led_index,
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    // Interpolation in Oklab. Hue changes stay saturated instead of passing through the
+    // grey/muddy midpoints of RGB interpolation (e.g. red to green via olive instead of brown).
+    pub fn lerp_oklab(&self, other: &Color, t: f32) -> Color {
+        let from = srgb_to_oklab([self.r, self.g, self.b]);
+        let to = srgb_to_oklab([other.r, other.g, other.b]);
+        let mix = |i: usize| from[i] + (to[i] - from[i]) * t;
+        let [r, g, b] = oklab_to_srgb([mix(0), mix(1), mix(2)]);
+        Color::new(other.led_index, r, g, b)
+    }
+
+    // Oklab when `perceptual`, plain RGB otherwise
+    pub fn blend(&self, other: &Color, t: f32, perceptual: bool) -> Color {
+        if perceptual {
+            self.lerp_oklab(other, t)
+        } else {
+            self.lerp(other, t)
+        }
+    }
+}
+
+// sRGB channels in the 0..255 range to Oklab (L, a, b). Matrices are the reference
+// values from https://bottosson.github.io/posts/oklab/, kept verbatim.
+#[allow(clippy::excessive_precision)]
+fn srgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let to_linear = |value: f32| {
+        let c = (value / 255.0).clamp(0.0, 1.0);
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (to_linear(r), to_linear(g), to_linear(b));
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+// Oklab back to sRGB channels in the 0..255 range, out of gamut values are clipped
+#[allow(clippy::excessive_precision)]
+fn oklab_to_srgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+    let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+    let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+
+    [linear_to_srgb(r * 255.0), linear_to_srgb(g * 255.0), linear_to_srgb(b * 255.0)]
+}
+
+// Mean luminance (Rec. 601) over all colors, 0..255
+pub fn average_luminance(colors: &[Color]) -> f32 {
+    if colors.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = colors
+        .iter()
+        .map(|c| 0.299 * c.r + 0.587 * c.g + 0.114 * c.b)
+        .sum();
+    sum / colors.len() as f32
+}
+
+// Single color standing for the whole frame: the mean of all LED colors weighted by their
+// chroma, so a vivid area decides the tint instead of being washed out by grey or black
+// surroundings. Plain mean if everything is grey.
+pub fn dominant_color(colors: &[Color]) -> Color {
+    let mut sum = [0.0; 3];
+    let mut weight_sum = 0.0;
+    for color in colors {
+        let max = color.r.max(color.g).max(color.b);
+        let min = color.r.min(color.g).min(color.b);
+        let weight = max - min;
+        sum[0] += color.r * weight;
+        sum[1] += color.g * weight;
+        sum[2] += color.b * weight;
+        weight_sum += weight;
+    }
+    if weight_sum <= 0.0 {
+        let count = colors.len().max(1) as f32;
+        sum = [0.0; 3];
+        for color in colors {
+            sum[0] += color.r;
+            sum[1] += color.g;
+            sum[2] += color.b;
+        }
+        return Color::new(0, sum[0] / count, sum[1] / count, sum[2] / count);
+    }
+    Color::new(0, sum[0] / weight_sum, sum[1] / weight_sum, sum[2] / weight_sum)
+}
+
+// Histogram of the brightest channel of every `step`th pixel. The brightest channel
+// instead of luminance so saturated colors (e.g. pure blue) count as bright content.
+pub fn brightness_histogram<S: Sample>(image: &ImageBuffer<S::Pixel, Vec<S>>, step: usize) -> [u32; 256] {
+    let mut histogram = [0; 256];
+    for pixel in image.as_raw().chunks_exact(4).step_by(step.max(1)) {
+        let brightest = pixel[0].to_f32().max(pixel[1].to_f32()).max(pixel[2].to_f32());
+        histogram[(brightest as usize).min(255)] += 1;
+    }
+    histogram
+}
+
+// Box blur over (2 * radius + 1)² pixels, done as a horizontal and a vertical pass with
+// running sums, so the cost per pixel doesn't grow with the radius. Edges repeat the border
+// pixels, alpha is left alone. The buffers are kept between frames. Each pass truncates
+// its means to the image's samples, so 8 bit images blur exactly as in integer math.
+pub struct BoxBlur {
+    radius: usize,
+    // Horizontally blurred frame
+    scratch: Vec<f32>,
+    // Running RGB sums of every column in the vertical pass
+    column_sums: Vec<f32>,
+}
+
+impl BoxBlur {
+    pub fn new(radius: u32) -> Self {
+        BoxBlur { radius: radius as usize, scratch: Vec::new(), column_sums: Vec::new() }
+    }
+
+    pub fn apply<S: Sample>(&mut self, image: &mut ImageBuffer<S::Pixel, Vec<S>>) {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        if self.radius == 0 || width == 0 || height == 0 {
+            return;
+        }
+        let radius = self.radius;
+        let window = (2 * radius + 1) as f32;
+        let row_len = width * 4;
+
+        // Horizontal: slide the window along every row, rows in parallel
+        self.scratch.resize(image.as_raw().len(), 0.0);
+        self.scratch
+            .par_chunks_mut(row_len)
+            .zip(image.as_raw().par_chunks(row_len))
+            .for_each(|(out, row)| {
+                let pixel = |x: usize, c: usize| row[x.min(width - 1) * 4 + c].to_f32();
+                for c in 0..3 {
+                    let mut sum = pixel(0, c) * (radius as f32 + 1.0) + (1..=radius).map(|x| pixel(x, c)).sum::<f32>();
+                    for x in 0..width {
+                        out[x * 4 + c] = S::from_f32(sum / window).to_f32();
+                        sum = sum + pixel(x + radius + 1, c) - pixel(x.saturating_sub(radius), c);
+                    }
+                }
+                for x in 0..width {
+                    out[x * 4 + 3] = row[x * 4 + 3].to_f32();
+                }
+            });
+
+        // Vertical: one running sum per column, moved down a row at a time
+        let source = &self.scratch;
+        let row = |y: usize| &source[y.min(height - 1) * row_len..(y.min(height - 1) + 1) * row_len];
+        self.column_sums.clear();
+        self.column_sums.resize(width * 3, 0.0);
+        for y in std::iter::repeat_n(0, radius + 1).chain(1..=radius) {
+            for (sums, pixel) in self.column_sums.chunks_exact_mut(3).zip(row(y).chunks_exact(4)) {
+                for (sum, value) in sums.iter_mut().zip(pixel) {
+                    *sum += *value;
+                }
+            }
+        }
+        for (y, out) in image.chunks_mut(row_len).enumerate() {
+            let (entering, leaving) = (row(y + radius + 1), row(y.saturating_sub(radius)));
+            for x in 0..width {
+                for c in 0..3 {
+                    let sum = &mut self.column_sums[x * 3 + c];
+                    out[x * 4 + c] = S::from_f32(*sum / window);
+                    *sum = *sum + entering[x * 4 + c] - leaving[x * 4 + c];
+                }
+            }
+        }
     }
 }
 
-fn save_screenshot(image: &RgbaImage, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+// Mean absolute per-channel difference between two frames of colors, 0..255.
+// Frames with different LED counts count as completely different.
+pub fn color_difference(a: &[Color], b: &[Color]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 255.0;
+    }
+    let sum: f32 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a.r - b.r).abs() + (a.g - b.g).abs() + (a.b - b.b).abs())
+        .sum();
+    sum / (a.len() * 3) as f32
+}
+
+// Fills every LED with the same static color (mood lamp)
+pub fn static_colors(leds_array: &[LED], r: u8, g: u8, b: u8) -> Vec<Color> {
+    leds_array
+        .iter()
+        .map(|led| Color::from_rgb8(led.index, r, g, b))
+        .collect()
+}
+
+fn save_screenshot(image: &RgbaImage, path: &str) -> Result<(), Error> {
     let path = Path::new(path);
     let mut file = File::create(path)?;
     image::DynamicImage::ImageRgba8(image.clone()).write_to(&mut file, image::ImageFormat::Png)?;
@@ -37,7 +285,7 @@ fn save_screenshot(image: &RgbaImage, path: &str) -> Result<(), Box<dyn std::err
 }
 
 
-pub fn process_edge_color(screenshot_img: image::ImageBuffer<Rgba<u8>, Vec<u8>>, leds_array: &Vec<LED> ) -> Result<Vec<Color>, Box<dyn std::error::Error>> {
+pub fn process_edge_color(screenshot_img: image::ImageBuffer<Rgba<u8>, Vec<u8>>, leds_array: &Vec<LED> ) -> Result<Vec<Color>, Error> {
     let start = std::time::Instant::now();
     save_screenshot(&screenshot_img, "screenshot.png")?;
 
@@ -48,7 +296,7 @@ pub fn process_edge_color(screenshot_img: image::ImageBuffer<Rgba<u8>, Vec<u8>>,
     //    log::info!("Border image saved");
     //}
 
-    let avg_colors = calculate_avg_colors(&screenshot_img, 0, 0, 1000, 1000, leds_array)?;
+    let avg_colors = calculate_avg_colors(&screenshot_img, 0, 0, 1000, 1000, leds_array, &AveragingOptions::default())?;
     log::info!("Average colors calculated");
 
     //save_screenshot_with_avg_colors(&screenshot_img, config, &avg_colors, "screenshot_avg_colors.png", min_x, min_y, max_x, max_y)?;
@@ -63,71 +311,914 @@ pub fn save_config_border_img(
     max_x: i32, 
     max_y: i32, 
     leds_array: &Vec<LED>
-) -> Result<(), Box<dyn std::error::Error>> {
-    let screenshot = Arc::new(Mutex::new(screenshot_img.clone()));
+) -> Result<(), Error> {
+    let mut screenshot_out = screenshot_img.clone();
+    draw_led_borders(&mut screenshot_out, min_x, min_y, max_x, max_y, leds_array);
 
-    leds_array.par_iter().for_each(|led| {
-        let position = (led.Position.x, led.Position.y);
-        let size = (led.Size.width, led.Size.height);
+    // Saving the modified screenshot
+    save_screenshot(&screenshot_out, "screenshot_border.png")?;
+    Ok(())
+}
 
-        for x in 0..size.0 {
-            for y in 0..size.1 {
-                // Calculate pixel positions relative to the screen and clamp to valid image area
-                let pixel_x = (position.0 + x - min_x) as i32;
-                let pixel_y = (position.1 + y - min_y) as i32;
+// Draws the outline of every LED region in red. Regions are clipped to the image and to
+// the desktop bounds, so LEDs reaching past an edge or off-screen are drawn partially or not at all.
+pub fn draw_led_borders(image: &mut RgbaImage, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &[LED]) {
+    // Last valid pixel in image coordinates, in i64 so huge LED sizes can't overflow
+    let right = (image.width() as i64).min(max_x as i64 - min_x as i64);
+    let bottom = (image.height() as i64).min(max_y as i64 - min_y as i64);
 
-                // Skip out-of-bounds pixels entirely
-                if pixel_x < 0 || pixel_y < 0 || pixel_x >= max_x as i32 || pixel_y >= max_y as i32 {
-                    continue;
+    for led in leds_array {
+        if led.Size.width <= 0 || led.Size.height <= 0 {
+            continue;
+        }
+        let left = led.Position.x as i64 - min_x as i64;
+        let top = led.Position.y as i64 - min_y as i64;
+        let (last_x, last_y) = (left + led.Size.width as i64 - 1, top + led.Size.height as i64 - 1);
+
+        // Only the visible part of the region is walked, only its outline is drawn
+        for pixel_y in top.max(0)..=last_y.min(bottom - 1) {
+            for pixel_x in left.max(0)..=last_x.min(right - 1) {
+                if pixel_x == left || pixel_x == last_x || pixel_y == top || pixel_y == last_y {
+                    image.put_pixel(pixel_x as u32, pixel_y as u32, image::Rgba([255, 0, 0, 255]));
                 }
+            }
+        }
+    }
+}
 
-                // Only draw the border (outline)
-                if x == 0 || x == size.0 - 1 || y == 0 || y == size.1 - 1 {
-                    let mut screenshot = screenshot.lock().unwrap();
-                    screenshot.put_pixel(
-                        pixel_x as u32,
-                        pixel_y as u32,
-                        image::Rgba([255, 0, 0, 255]),
-                    );
+// Box filters an RGBA frame down by `factor` in both directions
+pub fn downscale_frame(data: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    let out_width = width / factor;
+    let out_height = height / factor;
+    let block = factor * factor;
+    let mut out = vec![0u8; (out_width * out_height * 4) as usize];
+
+    for oy in 0..out_height {
+        for ox in 0..out_width {
+            let mut sum = [0u32; 4];
+            for y in oy * factor..(oy + 1) * factor {
+                let row = (y * width * 4) as usize;
+                for x in ox * factor..(ox + 1) * factor {
+                    let i = row + (x * 4) as usize;
+                    for (total, value) in sum.iter_mut().zip(&data[i..i + 4]) {
+                        *total += *value as u32;
+                    }
                 }
             }
+            let o = ((oy * out_width + ox) * 4) as usize;
+            for (value, total) in out[o..o + 4].iter_mut().zip(sum) {
+                *value = (total / block) as u8;
+            }
         }
-    });
+    }
+    out
+}
 
-    // Unwrapping the screenshot Arc after drawing
-    let screenshot_out = Arc::try_unwrap(screenshot)
-        .expect("Screenshot still has multiple references")
-        .into_inner()
-        .unwrap();
+// Box filters an Rgba16F frame down by `factor`. The half floats are linear, so they are
+// averaged as they are and the result stays half float.
+pub fn downscale_half_frame(data: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    let out_width = width / factor;
+    let out_height = height / factor;
+    let block = (factor * factor) as f32;
+    let mut out = vec![0u8; (out_width * out_height * 8) as usize];
 
-    // Saving the modified screenshot
-    save_screenshot(&screenshot_out, "screenshot_border.png")?;
-    Ok(())
+    for oy in 0..out_height {
+        for ox in 0..out_width {
+            let mut sum = [0f32; 4];
+            for y in oy * factor..(oy + 1) * factor {
+                let row = (y * width * 8) as usize;
+                for x in ox * factor..(ox + 1) * factor {
+                    let i = row + (x * 8) as usize;
+                    for (total, value) in sum.iter_mut().zip(data[i..i + 8].chunks_exact(2)) {
+                        *total += f16_to_f32(u16::from_le_bytes([value[0], value[1]]));
+                    }
+                }
+            }
+            let o = ((oy * out_width + ox) * 8) as usize;
+            for (value, total) in out[o..o + 8].chunks_exact_mut(2).zip(sum) {
+                value.copy_from_slice(&f32_to_f16(total / block).to_le_bytes());
+            }
+        }
+    }
+    out
+}
+
+// downscale_frame or downscale_half_frame, whichever fits the frame's format
+pub fn downscale_frame_data(data: &[u8], format: PixelFormat, width: u32, height: u32, factor: u32) -> Vec<u8> {
+    match format {
+        PixelFormat::Rgba16F => downscale_half_frame(data, width, height, factor),
+        _ => downscale_frame(data, width, height, factor),
+    }
+}
+
+// IEEE 754 half float to f32
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// f32 to the nearest IEEE 754 half float, ties to even. Too large values become infinity.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let (half, dropped_bits) = match exponent - 127 + 15 {
+        31.. => return sign | 0x7c00,
+        // Below half the smallest subnormal
+        ..-10 => return sign,
+        // Subnormal, the implicit leading bit becomes part of the mantissa
+        half_exponent @ ..=0 => (0, (14 - half_exponent) as u32),
+        half_exponent => ((half_exponent as u32) << 10, 13),
+    };
+    let mantissa = if half == 0 { mantissa | 0x80_0000 } else { mantissa };
+    let truncated = half | (mantissa >> dropped_bits);
+    let remainder = mantissa & ((1 << dropped_bits) - 1);
+    let halfway = 1 << (dropped_bits - 1);
+    // A carry out of the mantissa moves on to the next exponent, or to infinity
+    let round_up = remainder > halfway || (remainder == halfway && truncated & 1 == 1);
+    sign | (truncated + round_up as u32) as u16
+}
+
+// sRGB encoded value (0..255, unrounded) of every half float bit pattern, for the color channels
+static HALF_TO_SRGB: Lazy<Vec<f32>> = Lazy::new(|| {
+    (0..=u16::MAX)
+        .map(|bits| {
+            let value = f16_to_f32(bits);
+            if value.is_nan() { 0.0 } else { linear_to_srgb(value * 255.0) }
+        })
+        .collect()
+});
+// 0..255 value of every half float bit pattern without the sRGB curve, for alpha
+static HALF_TO_UNORM: Lazy<Vec<f32>> = Lazy::new(|| {
+    (0..=u16::MAX)
+        .map(|bits| {
+            let value = f16_to_f32(bits);
+            if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) * 255.0 }
+        })
+        .collect()
+});
+
+// Converts an Rgba16F frame (linear scRGB, 1.0 = SDR white) to sRGB encoded RGBA samples
+// in the 0..255 range, unrounded. HDR highlights above 1.0 clip.
+pub fn rgba16f_to_rgba32f(data: &[u8]) -> Vec<f32> {
+    let channel = |pixel: &[u8], i: usize| u16::from_le_bytes([pixel[i * 2], pixel[i * 2 + 1]]) as usize;
+    data.chunks_exact(8)
+        .flat_map(|pixel| {
+            [
+                HALF_TO_SRGB[channel(pixel, 0)],
+                HALF_TO_SRGB[channel(pixel, 1)],
+                HALF_TO_SRGB[channel(pixel, 2)],
+                HALF_TO_UNORM[channel(pixel, 3)],
+            ]
+        })
+        .collect()
+}
+
+// rgba16f_to_rgba32f rounded to 8 bit. The sRGB curve is applied to the float values, so
+// dark gradients are rounded once instead of being quantized by the compositor first.
+pub fn rgba16f_to_rgba8(data: &[u8]) -> Vec<u8> {
+    rgba16f_to_rgba32f(data).into_iter().map(|value| value.round() as u8).collect()
+}
+
+// Channel type of a combined image. 8 bit frames combine into u8 samples. f32 samples are
+// on the same 0..255 sRGB encoded scale but unrounded, so half float frames keep their
+// precision through combining and averaging; Color quantizes only for the output.
+pub trait Sample: Primitive + Send + Sync + 'static {
+    // Rgba<Self>
+    type Pixel: Pixel<Subpixel = Self> + Send + Sync;
+    // RGBA or BGRA samples of a frame in `format`, borrowed where no conversion is needed
+    fn from_frame(data: &[u8], format: PixelFormat) -> Cow<'_, [Self]>;
+    // from_frame for a frame that is handed over, without copying 8 bit frames
+    fn from_frame_owned(data: Vec<u8>, format: PixelFormat) -> Vec<Self> {
+        Self::from_frame(&data, format).into_owned()
+    }
+    fn to_f32(self) -> f32;
+    // Truncates for u8, like integer division
+    fn from_f32(value: f32) -> Self;
+    // Linear light in the 0..255 range
+    fn to_linear(self) -> f32;
+    fn correct(correction: &MonitorCorrection, rgba: &mut [Self]);
+    fn into_combined(image: ImageBuffer<Self::Pixel, Vec<Self>>) -> CombinedImage;
 }
 
-pub fn combine_screens(value: &Vec<SlimMonitorInfo>, combined_monitor_width: u32, combined_monitor_height: u32, thread_num: u32, min_x: i32, min_y: i32) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn std::error::Error>> {
+impl Sample for u8 {
+    type Pixel = Rgba<u8>;
+
+    fn from_frame(data: &[u8], format: PixelFormat) -> Cow<'_, [u8]> {
+        match format {
+            PixelFormat::Rgba16F => Cow::Owned(rgba16f_to_rgba8(data)),
+            _ => Cow::Borrowed(data),
+        }
+    }
+
+    fn from_frame_owned(data: Vec<u8>, format: PixelFormat) -> Vec<u8> {
+        match format {
+            PixelFormat::Rgba16F => rgba16f_to_rgba8(&data),
+            _ => data,
+        }
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value as u8
+    }
+
+    fn to_linear(self) -> f32 {
+        SRGB_TO_LINEAR[self as usize]
+    }
+
+    fn correct(correction: &MonitorCorrection, rgba: &mut [u8]) {
+        correction.apply(rgba);
+    }
+
+    fn into_combined(image: RgbaImage) -> CombinedImage {
+        CombinedImage::Rgba8(image)
+    }
+}
+
+impl Sample for f32 {
+    type Pixel = Rgba<f32>;
+
+    fn from_frame(data: &[u8], format: PixelFormat) -> Cow<'_, [f32]> {
+        match format {
+            PixelFormat::Rgba16F => Cow::Owned(rgba16f_to_rgba32f(data)),
+            _ => Cow::Owned(data.iter().map(|&value| value as f32).collect()),
+        }
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb_to_linear(self)
+    }
+
+    fn correct(correction: &MonitorCorrection, rgba: &mut [f32]) {
+        correction.apply_f32(rgba);
+    }
+
+    fn into_combined(image: Rgba32FImage) -> CombinedImage {
+        CombinedImage::Rgba32F(image)
+    }
+}
+
+// Image combine_screens builds: 8 bit, or f32 samples (0..255, see Sample) as soon as one
+// of the combined frames is half float
+pub enum CombinedImage {
+    Rgba8(RgbaImage),
+    Rgba32F(Rgba32FImage),
+}
+
+impl CombinedImage {
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            CombinedImage::Rgba8(image) => image.dimensions(),
+            CombinedImage::Rgba32F(image) => image.dimensions(),
+        }
+    }
+
+    // The 8 bit image, None for f32 samples
+    pub fn as_rgba8(&self) -> Option<&RgbaImage> {
+        match self {
+            CombinedImage::Rgba8(image) => Some(image),
+            CombinedImage::Rgba32F(_) => None,
+        }
+    }
+
+    // Rounds f32 samples to 8 bit, e.g. to save the image
+    pub fn into_rgba8(self) -> RgbaImage {
+        match self {
+            CombinedImage::Rgba8(image) => image,
+            CombinedImage::Rgba32F(image) => {
+                let (width, height) = image.dimensions();
+                let data = image.into_raw().into_iter().map(|value| value.round() as u8).collect();
+                RgbaImage::from_raw(width, height, data).expect("same dimensions")
+            }
+        }
+    }
+
+    pub fn blur(&mut self, blur: &mut BoxBlur) {
+        match self {
+            CombinedImage::Rgba8(image) => blur.apply(image),
+            CombinedImage::Rgba32F(image) => blur.apply(image),
+        }
+    }
+
+    pub fn brightness_histogram(&self, step: usize) -> [u32; 256] {
+        match self {
+            CombinedImage::Rgba8(image) => brightness_histogram(image, step),
+            CombinedImage::Rgba32F(image) => brightness_histogram(image, step),
+        }
+    }
+
+    pub fn calculate_avg_colors(&self, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &Vec<LED>, options: &AveragingOptions) -> Result<Vec<Color>, Error> {
+        match self {
+            CombinedImage::Rgba8(image) => calculate_avg_colors(image, min_x, min_y, max_x, max_y, leds_array, options),
+            CombinedImage::Rgba32F(image) => calculate_avg_colors(image, min_x, min_y, max_x, max_y, leds_array, options),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_avg_colors(&self, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &Vec<LED>, options: &AveragingOptions, dirty: &[DirtyRect], previous: &[Color]) -> Result<Vec<Color>, Error> {
+        match self {
+            CombinedImage::Rgba8(image) => update_avg_colors(image, min_x, min_y, max_x, max_y, leds_array, options, dirty, previous),
+            CombinedImage::Rgba32F(image) => update_avg_colors(image, min_x, min_y, max_x, max_y, leds_array, options, dirty, previous),
+        }
+    }
+}
+
+// LED regions mapped into an image downscaled by `factor`
+pub fn scale_leds(leds_array: &[LED], factor: u32) -> Vec<LED> {
+    let factor = factor as i32;
+    leds_array
+        .iter()
+        .map(|led| {
+            let mut led = led.clone();
+            // Floor division, left and above the primary monitor positions are negative
+            led.Position.x = led.Position.x.div_euclid(factor);
+            led.Position.y = led.Position.y.div_euclid(factor);
+            led.Size.width = (led.Size.width / factor).max(1);
+            led.Size.height = (led.Size.height / factor).max(1);
+            led
+        })
+        .collect()
+}
+
+// Copies the colors of one edge's LEDs onto the LEDs of the opposite edge or all other
+// edges, for symmetric lighting from a strip that only partly faces content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mirror {
+    source: Edge,
+    to_all_edges: bool,
+}
+
+impl Mirror {
+    pub fn from_config(grab: &Grab) -> Option<Self> {
+        if grab.MirrorSource.trim().is_empty() {
+            return None;
+        }
+        let Some(source) = Edge::from_config(&grab.MirrorSource) else {
+            log::error!("Invalid MirrorSource {:?}, not mirroring", grab.MirrorSource);
+            return None;
+        };
+        Some(Mirror {
+            source,
+            to_all_edges: grab.MirrorTarget == "All",
+        })
+    }
+
+    // `colors[i]` belongs to `leds[i]`. Each target LED takes the color of the source LED at
+    // the same relative position along its edge, so LED indices and order stay untouched.
+    pub fn apply(&self, leds: &[LED], colors: &mut [Color]) {
+        let sources = relative_edge_positions(leds, self.source);
+        if sources.is_empty() {
+            return;
+        }
+        let source_colors: Vec<(f32, [f32; 3])> = sources
+            .iter()
+            .map(|&(i, position)| (position, [colors[i].r, colors[i].g, colors[i].b]))
+            .collect();
+
+        let targets = [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right]
+            .into_iter()
+            .filter(|&edge| edge != self.source && (self.to_all_edges || edge == self.source.opposite()));
+        for edge in targets {
+            for (i, position) in relative_edge_positions(leds, edge) {
+                let (_, [r, g, b]) = source_colors
+                    .iter()
+                    .min_by(|a, b| (a.0 - position).abs().total_cmp(&(b.0 - position).abs()))
+                    .unwrap();
+                colors[i] = Color::new(colors[i].led_index, *r, *g, *b);
+            }
+        }
+    }
+}
+
+// Corner regions tend to catch taskbar and menu corners and come out with odd colors.
+// The outermost LED at each end of an edge instead gets the mean of its neighbor on the
+// same edge and the closest LED of the adjoining edge. Built once per LED layout.
+#[derive(Debug, Clone, Default)]
+pub struct CornerBlend {
+    // (corner, neighbor on the same edge, closest LED on the adjoining edge) as indices
+    corners: Vec<(usize, usize, usize)>,
+}
+
+impl CornerBlend {
+    pub fn new(leds: &[LED]) -> Self {
+        let center = |led: &LED| {
+            (led.Position.x as f32 + led.Size.width as f32 / 2.0, led.Position.y as f32 + led.Size.height as f32 / 2.0)
+        };
+        let mut corners = Vec::new();
+        for edge in [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right] {
+            let mut on_edge = relative_edge_positions(leds, edge);
+            if on_edge.len() < 2 {
+                continue;
+            }
+            on_edge.sort_by(|a, b| a.1.total_cmp(&b.1));
+            // The start of an edge meets the left/top edge, the end the right/bottom one
+            let (start_edge, end_edge) = match edge {
+                Edge::Top | Edge::Bottom => (Edge::Left, Edge::Right),
+                Edge::Left | Edge::Right => (Edge::Top, Edge::Bottom),
+            };
+            let last = on_edge.len() - 1;
+            for (corner, neighbor, adjoining) in [(on_edge[0].0, on_edge[1].0, start_edge), (on_edge[last].0, on_edge[last - 1].0, end_edge)] {
+                let (x, y) = center(&leds[corner]);
+                let closest = leds
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, led)| led.edge == Some(adjoining))
+                    .min_by(|(_, a), (_, b)| {
+                        let distance = |led: &LED| {
+                            let (cx, cy) = center(led);
+                            (cx - x).powi(2) + (cy - y).powi(2)
+                        };
+                        distance(a).total_cmp(&distance(b))
+                    })
+                    .map(|(i, _)| i);
+                if let Some(closest) = closest {
+                    corners.push((corner, neighbor, closest));
+                }
+            }
+        }
+        CornerBlend { corners }
+    }
+
+    // `colors[i]` belongs to `leds[i]` of the layout the blend was built for
+    pub fn apply(&self, colors: &mut [Color]) {
+        if self.corners.iter().any(|&(a, b, c)| a.max(b).max(c) >= colors.len()) {
+            return;
+        }
+        let original: Vec<Color> = self.corners.iter().flat_map(|&(_, b, c)| [colors[b].clone(), colors[c].clone()]).collect();
+        for (&(corner, _, _), pair) in self.corners.iter().zip(original.chunks_exact(2)) {
+            let blended = pair[0].lerp(&pair[1], 0.5);
+            colors[corner] = Color::new(colors[corner].led_index, blended.r, blended.g, blended.b);
+        }
+    }
+}
+
+// Indices of the LEDs on `edge` with their position along it, scaled to 0..=1
+fn relative_edge_positions(leds: &[LED], edge: Edge) -> Vec<(usize, f32)> {
+    let positions: Vec<(usize, f32)> = leds
+        .iter()
+        .enumerate()
+        .filter(|(_, led)| led.edge == Some(edge))
+        .map(|(i, led)| (i, edge.position_along(led)))
+        .collect();
+    let min = positions.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let max = positions.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+    positions
+        .into_iter()
+        .map(|(i, position)| {
+            let relative = if max > min { (position - min) / (max - min) } else { 0.5 };
+            (i, relative)
+        })
+        .collect()
+}
+
+// Transfer function of frames captured from an HDR monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrCurve {
+    // SMPTE ST 2084, HDR10
+    Pq,
+    // Hybrid log-gamma, ARIB STD-B67
+    Hlg,
+}
+
+// Maps HDR encoded channel values to SDR (sRGB encoded) ones: decode to nits, scale so
+// `white_nits` becomes SDR white, roll the highlights off up to `peak_nits` (extended
+// Reinhard) and encode as sRGB. Per channel, so it folds into a lookup table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HdrToneMap {
+    pub curve: HdrCurve,
+    pub white_nits: f32,
+    pub peak_nits: f32,
+}
+
+impl HdrToneMap {
+    // None with HdrToneMap=Off, frames are then taken as sRGB
+    pub fn from_config(grab: &Grab) -> Option<Self> {
+        let curve = match grab.HdrToneMap.as_str() {
+            "Off" => return None,
+            "Pq" => HdrCurve::Pq,
+            "Hlg" => HdrCurve::Hlg,
+            other => {
+                log::warn!("Unknown HdrToneMap {:?}, not tone mapping", other);
+                return None;
+            }
+        };
+        Some(HdrToneMap {
+            curve,
+            white_nits: grab.HdrWhiteNits.max(1.0),
+            peak_nits: grab.HdrPeakNits.max(grab.HdrWhiteNits).max(1.0),
+        })
+    }
+
+    // Encoded HDR value to nits, both ends of the range included
+    fn nits(&self, encoded: f32) -> f32 {
+        let e = encoded.clamp(0.0, 1.0);
+        match self.curve {
+            HdrCurve::Pq => {
+                const M1: f32 = 2610.0 / 16384.0;
+                const M2: f32 = 2523.0 / 4096.0 * 128.0;
+                const C1: f32 = 3424.0 / 4096.0;
+                const C2: f32 = 2413.0 / 4096.0 * 32.0;
+                const C3: f32 = 2392.0 / 4096.0 * 32.0;
+                let p = e.powf(1.0 / M2);
+                10000.0 * ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+            }
+            HdrCurve::Hlg => {
+                const A: f32 = 0.178_832_77;
+                const B: f32 = 0.284_668_92;
+                const C: f32 = 0.559_910_7;
+                let scene = if e <= 0.5 { e * e / 3.0 } else { (((e - C) / A).exp() + B) / 12.0 };
+                // OOTF with the system gamma of a 1000 nit display, applied per channel
+                self.peak_nits * scene.powf(1.2)
+            }
+        }
+    }
+
+    // Encoded HDR value (0..1) to an sRGB encoded SDR value (0..1)
+    pub fn map(&self, encoded: f32) -> f32 {
+        let x = self.nits(encoded) / self.white_nits;
+        let peak = self.peak_nits / self.white_nits;
+        let sdr = (x * (1.0 + x / (peak * peak)) / (1.0 + x)).clamp(0.0, 1.0);
+        linear_to_srgb(sdr * 255.0) / 255.0
+    }
+}
+
+// 8 bit lookup tables applying an HDR tone map and a MonitorCalibration to RGBA pixels
+pub struct MonitorCorrection {
+    lut: [[u8; 256]; 3],
+    // The same tables unrounded, interpolated between the entries for f32 samples
+    curve: [[f32; 256]; 3],
+}
+
+impl MonitorCorrection {
+    // The tone map comes first, the calibration corrects the resulting SDR values
+    pub fn new(calibration: &MonitorCalibration, tone_map: Option<&HdrToneMap>) -> Self {
+        let mut curve = [[0f32; 256]; 3];
+        let gains = [calibration.red, calibration.green, calibration.blue];
+        for (table, gain) in curve.iter_mut().zip(gains) {
+            for (i, value) in table.iter_mut().enumerate() {
+                let encoded = i as f32 / 255.0;
+                let sdr = tone_map.map_or(encoded, |tone_map| tone_map.map(encoded));
+                *value = (sdr.powf(calibration.gamma) * gain * 255.0).clamp(0.0, 255.0);
+            }
+        }
+        let lut = curve.map(|table| table.map(|value| value.round() as u8));
+        MonitorCorrection { lut, curve }
+    }
+
+    pub fn apply(&self, rgba: &mut [u8]) {
+        for pixel in rgba.chunks_exact_mut(4) {
+            for (channel, table) in pixel.iter_mut().zip(self.lut.iter()) {
+                *channel = table[*channel as usize];
+            }
+        }
+    }
+
+    pub fn apply_f32(&self, rgba: &mut [f32]) {
+        for pixel in rgba.chunks_exact_mut(4) {
+            for (channel, curve) in pixel.iter_mut().zip(self.curve.iter()) {
+                let value = channel.clamp(0.0, 255.0);
+                let i = (value as usize).min(254);
+                *channel = curve[i] + (curve[i + 1] - curve[i]) * (value - i as f32);
+            }
+        }
+    }
+}
+
+// Maps LED regions laid out for the `from` rectangle proportionally into `to`,
+// both given as (x, y, width, height)
+pub fn map_leds_to_rect(leds_array: &[LED], from: (i32, i32, i32, i32), to: (i32, i32, i32, i32)) -> Vec<LED> {
+    let scale_x = to.2 as f32 / from.2.max(1) as f32;
+    let scale_y = to.3 as f32 / from.3.max(1) as f32;
+    leds_array
+        .iter()
+        .map(|led| {
+            let mut led = led.clone();
+            led.Position.x = to.0 + ((led.Position.x - from.0) as f32 * scale_x) as i32;
+            led.Position.y = to.1 + ((led.Position.y - from.1) as f32 * scale_y) as i32;
+            led.Size.width = ((led.Size.width as f32 * scale_x) as i32).max(1);
+            led.Size.height = ((led.Size.height as f32 * scale_y) as i32).max(1);
+            led
+        })
+        .collect()
+}
+
+// How combine_screens resolves pixels covered by more than one monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapMode {
+    // Monitors are drawn in index order, the later one wins
+    #[default]
+    Last,
+    // The given monitor is always drawn on top
+    Priority(i32),
+    // Overlapping pixels are averaged
+    Blend,
+}
+
+impl OverlapMode {
+    pub fn from_config(grab: &Grab) -> Self {
+        match grab.MonitorOverlap.as_str() {
+            "Last" => OverlapMode::Last,
+            "Priority" => OverlapMode::Priority(grab.PriorityMonitor),
+            "Blend" => OverlapMode::Blend,
+            other => {
+                log::warn!("Unknown MonitorOverlap {:?}, using Last", other);
+                OverlapMode::Last
+            }
+        }
+    }
+}
+
+// Screen area shared by two monitors, identified by their ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorOverlap {
+    pub first: i32,
+    pub second: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Every pair of monitors sharing screen area
+pub fn find_monitor_overlaps(monitors: &[SlimMonitorInfo]) -> Vec<MonitorOverlap> {
+    let mut overlaps = Vec::new();
+    for (i, a) in monitors.iter().enumerate() {
+        for b in monitors.iter().skip(i + 1) {
+            let left = a.pos_x.max(b.pos_x);
+            let top = a.pos_y.max(b.pos_y);
+            let right = (a.pos_x + a.width).min(b.pos_x + b.width);
+            let bottom = (a.pos_y + a.height).min(b.pos_y + b.height);
+            if right > left && bottom > top {
+                overlaps.push(MonitorOverlap {
+                    first: a.id,
+                    second: b.id,
+                    x: left,
+                    y: top,
+                    width: right - left,
+                    height: bottom - top,
+                });
+            }
+        }
+    }
+    overlaps
+}
+
+// Area (x, y, width, height) in desktop coordinates that changed since the previous frame
+pub type DirtyRect = (i32, i32, i32, i32);
+
+// Side of the square tiles DirtyTiles compares, in pixels of the combined image
+pub const DIRTY_TILE_SIZE: u32 = 32;
+
+// Finds the tiles of the combined image that changed since the previous frame. Only the
+// pixels on the SAMPLE_STEP grid are kept and compared, a change between them goes
+// unnoticed, so callers should average everything again now and then.
+#[derive(Default)]
+pub struct DirtyTiles {
+    // Size of the previous image, None before the first one
+    dimensions: Option<(u32, u32)>,
+    // RGB of the grid pixels of the previous image, row by row
+    samples: Vec<f32>,
+    scratch: Vec<f32>,
+}
+
+impl DirtyTiles {
+    pub fn new() -> Self {
+        DirtyTiles::default()
+    }
+
+    // Tiles that differ from the previous image, in the coordinates of the LED regions
+    // (image pixel + min_x/min_y), and keeps this image for the next call. None for the
+    // first image and after a size change, then everything has to be averaged.
+    pub fn update(&mut self, image: &CombinedImage, min_x: i32, min_y: i32) -> Option<Vec<DirtyRect>> {
+        let mut samples = std::mem::take(&mut self.scratch);
+        samples.clear();
+        match image {
+            CombinedImage::Rgba8(image) => grid_samples(image, &mut samples),
+            CombinedImage::Rgba32F(image) => grid_samples(image, &mut samples),
+        }
+        let (width, height) = image.dimensions();
+        let dirty = (self.dimensions == Some((width, height))).then(|| {
+            let grid_width = width.div_ceil(SAMPLE_STEP as u32) as usize;
+            // Grid rows or columns of the pixels from..from + len
+            let grid = |from: u32, len: u32| from as usize / SAMPLE_STEP..((from + len) as usize).div_ceil(SAMPLE_STEP);
+            let mut dirty = Vec::new();
+            for tile_y in (0..height).step_by(DIRTY_TILE_SIZE as usize) {
+                let tile_height = DIRTY_TILE_SIZE.min(height - tile_y);
+                for tile_x in (0..width).step_by(DIRTY_TILE_SIZE as usize) {
+                    let tile_width = DIRTY_TILE_SIZE.min(width - tile_x);
+                    let columns = grid(tile_x, tile_width);
+                    let changed = grid(tile_y, tile_height).any(|row| {
+                        let range = (row * grid_width + columns.start) * 3..(row * grid_width + columns.end) * 3;
+                        samples[range.clone()] != self.samples[range]
+                    });
+                    if changed {
+                        dirty.push((tile_x as i32 + min_x, tile_y as i32 + min_y, tile_width as i32, tile_height as i32));
+                    }
+                }
+            }
+            dirty
+        });
+        self.scratch = std::mem::replace(&mut self.samples, samples);
+        self.dimensions = Some((width, height));
+        dirty
+    }
+}
+
+// Appends the RGB of every SAMPLE_STEP-th pixel of every SAMPLE_STEP-th row
+fn grid_samples<S: Sample>(image: &ImageBuffer<S::Pixel, Vec<S>>, samples: &mut Vec<f32>) {
+    let row_len = image.width() as usize * 4;
+    for row in image.as_raw().chunks_exact(row_len.max(1)).step_by(SAMPLE_STEP) {
+        for pixel in row.chunks_exact(4).step_by(SAMPLE_STEP) {
+            samples.extend(pixel[..3].iter().map(|&value| value.to_f32()));
+        }
+    }
+}
+
+// `scale` > 1 box filters each frame down before combining, sizes and offsets passed in are unscaled.
+// Also returns when the oldest of the combined frames was captured.
+pub fn combine_screens(value: &Vec<SlimMonitorInfo>, combined_monitor_width: u32, combined_monitor_height: u32, thread_num: u32, min_x: i32, min_y: i32, scale: u32, corrections: &HashMap<i32, MonitorCorrection>, overlap: OverlapMode, contributing_monitors: &mut Vec<i32>) -> Result<(CombinedImage, Option<Instant>), Error> {
     let start_time = Instant::now();
 
-    let mut combined_img: ImageBuffer<Rgba<u8>, Vec<u8>> = RgbaImage::new(combined_monitor_width, combined_monitor_height);
+    let combined_monitor_width = combined_monitor_width / scale;
+    let combined_monitor_height = combined_monitor_height / scale;
 
-    // Lock the map briefly to copy the frame data, then release the lock
+    // Lock the map briefly to copy the frame data of these monitors, then release the lock
     let frame_data_copy: HashMap<i32, FrameData> = {
-        let frame_map = FRAME_MAP.lock().unwrap();
-        frame_map.clone() // Clone the map contents
+        let frame_map = frame_map();
+        value
+            .iter()
+            .filter_map(|monitor| frame_map.get(&monitor.id).map(|frame| (monitor.id, frame.clone())))
+            .collect()
     };
     //log::info!("Thread {}:: Frame data copy took: {:?}", thread_num, start_time.elapsed());
 
+    // One half float frame makes the whole image f32, so its precision reaches the averaging
+    let combine = if frame_data_copy.values().any(|frame_data| frame_data.format == PixelFormat::Rgba16F) {
+        combine_frames::<f32>
+    } else {
+        combine_frames::<u8>
+    };
+    Ok(combine(
+        frame_data_copy,
+        value,
+        combined_monitor_width,
+        combined_monitor_height,
+        thread_num,
+        min_x,
+        min_y,
+        scale,
+        corrections,
+        overlap,
+        contributing_monitors,
+        start_time,
+    ))
+}
+
+// combine_screens for one sample type, sizes are already divided by `scale`
+#[allow(clippy::too_many_arguments)]
+fn combine_frames<S: Sample>(mut frame_data_copy: HashMap<i32, FrameData>, value: &[SlimMonitorInfo], combined_monitor_width: u32, combined_monitor_height: u32, thread_num: u32, min_x: i32, min_y: i32, scale: u32, corrections: &HashMap<i32, MonitorCorrection>, overlap: OverlapMode, contributing_monitors: &mut Vec<i32>, start_time: Instant) -> (CombinedImage, Option<Instant>) {
+
+    // A single monitor covering the whole image, its frame captured at the scale used
+    // here: the frame already is the combined image, so hand it over instead of copying
+    // it row by row. The image origin stays at (min_x, min_y), the monitor position, so
+    // LED offsets are unchanged.
+    if let [monitor] = value {
+        // from_raw takes oversized buffers too, so the length is checked exactly. A frame of
+        // the wrong size goes to the regular path, which logs and skips it
+        let expected_len = combined_monitor_width as usize * combined_monitor_height as usize;
+        let covers_image = frame_data_copy.get(&monitor.id).is_some_and(|frame| {
+            frame.scale.max(1) == scale && frame.data.len() == expected_len * frame.format.bytes_per_pixel()
+        }) && (monitor.pos_x, monitor.pos_y) == (min_x, min_y)
+            && (monitor.width as u32 / scale, monitor.height as u32 / scale)
+                == (combined_monitor_width, combined_monitor_height);
+        if covers_image {
+            if let Some(frame_data) = frame_data_copy.remove(&monitor.id) {
+                let FrameData { data, format, captured_at, .. } = frame_data;
+                let data = S::from_frame_owned(data, format);
+                if let Some(mut image) = ImageBuffer::<S::Pixel, Vec<S>>::from_raw(combined_monitor_width, combined_monitor_height, data) {
+                    if format == PixelFormat::Bgra8 {
+                        for pixel in image.chunks_exact_mut(4) {
+                            pixel.swap(0, 2);
+                        }
+                    }
+                    if let Some(correction) = corrections.get(&monitor.id) {
+                        S::correct(correction, &mut image);
+                    }
+                    log_contributing_monitors(thread_num, value, &[monitor.id], contributing_monitors);
+                    log::trace!("Thread {}:: Single monitor frame taken over in {:?}", thread_num, start_time.elapsed());
+                    return (S::into_combined(image), Some(captured_at));
+                }
+            }
+        }
+    }
+
+    // Starts black, so a monitor without a usable frame (its capture failed or hasn't
+    // delivered yet) stays black instead of showing garbage while the others continue
+    let mut combined_img: ImageBuffer<S::Pixel, Vec<S>> = ImageBuffer::new(combined_monitor_width, combined_monitor_height);
+    let mut contributing: Vec<i32> = Vec::with_capacity(value.len());
+
+    // Monitors drawn later cover earlier ones, so the priority monitor goes last
+    let mut order: Vec<usize> = (0..value.len()).collect();
+    if let OverlapMode::Priority(id) = overlap {
+        if let Some(pos) = order.iter().position(|&i| value[i].id == id) {
+            let priority = order.remove(pos);
+            order.push(priority);
+        }
+    }
+    // Rectangles (x, y, width, height) in the combined image already drawn, for blending
+    let mut drawn: Vec<(u32, u32, u32, u32)> = Vec::new();
+
     // Process the copied frame data
-    for (i, monitor) in value.iter().enumerate() {
-        if let Some(frame_data) = frame_data_copy.get(&(i as i32)) {
+    for i in order {
+        let monitor = &value[i];
+        if let Some(frame_data) = frame_data_copy.get(&monitor.id) {
+            // Frames shrunk at capture come at the scale used here, others are shrunk below
+            let frame_scale = frame_data.scale.max(1);
+            if frame_scale != 1 && frame_scale != scale {
+                log::warn!(
+                    "Thread {}:: Frame of monitor {} is downscaled by {}, expected {}, skipping it",
+                    thread_num,
+                    monitor.id,
+                    frame_scale,
+                    scale
+                );
+                continue;
+            }
+            // A frame that doesn't fit the monitor came from a different one, drawing it
+            // would scramble the image or read past the buffer
+            let (frame_width, frame_height) = (monitor.width as u32 / frame_scale, monitor.height as u32 / frame_scale);
+            let expected_len = frame_width as usize * frame_height as usize * frame_data.format.bytes_per_pixel();
+            if frame_data.data.len() != expected_len {
+                log::warn!(
+                    "Thread {}:: Frame of monitor {} has {} bytes, expected {} for {}x{}, skipping it",
+                    thread_num,
+                    monitor.id,
+                    frame_data.data.len(),
+                    expected_len,
+                    frame_width,
+                    frame_height
+                );
+                continue;
+            }
+            contributing.push(monitor.id);
             let position: (i32, i32) = (monitor.pos_x, monitor.pos_y);
 
             // Ensure the subtraction does not result in a negative value
-            let x_offset = (position.0 - min_x).max(0) as u32;
-            let y_offset = (position.1 - min_y).max(0) as u32;
+            let x_offset = (position.0 - min_x).max(0) as u32 / scale;
+            let y_offset = (position.1 - min_y).max(0) as u32 / scale;
+
+            let img_width = monitor.width as u32 / scale;
+            let img_height = monitor.height as u32 / scale;
 
-            let img_width = monitor.width as u32;
-            let img_height = monitor.height as u32;
+            // Parts of this monitor already covered by a previously drawn one
+            let covered: Vec<(u32, u32, u32, u32)> = if overlap == OverlapMode::Blend {
+                drawn
+                    .iter()
+                    .filter_map(|&(x, y, width, height)| {
+                        let left = x.max(x_offset);
+                        let top = y.max(y_offset);
+                        let right = (x + width).min(x_offset + img_width);
+                        let bottom = (y + height).min(y_offset + img_height);
+                        (right > left && bottom > top).then_some((left, top, right, bottom))
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            drawn.push((x_offset, y_offset, img_width, img_height));
+
+            // Shrunk in the frame's own format, before its samples are converted
+            let scaled;
+            let data: &[u8] = if scale > frame_scale {
+                scaled = downscale_frame_data(&frame_data.data, frame_data.format, frame_width, frame_height, scale);
+                &scaled
+            } else {
+                &frame_data.data
+            };
+            let data = S::from_frame(data, frame_data.format);
 
             // Direct buffer copy using copy_from_slice
             for y in 0..img_height {
@@ -135,62 +1226,345 @@ pub fn combine_screens(value: &Vec<SlimMonitorInfo>, combined_monitor_width: u32
                 let src_end = src_start + (img_width * 4) as usize;
                 let dest_start = ((y_offset + y) * combined_monitor_width * 4 + x_offset * 4) as usize;
 
+                // Keep what the other monitor drew on this row to average it in afterwards
+                let previous: Vec<(usize, Vec<S>)> = covered
+                    .iter()
+                    .filter(|&&(_, top, _, bottom)| (top..bottom).contains(&(y_offset + y)))
+                    .map(|&(left, _, right, _)| {
+                        let start = ((y_offset + y) * combined_monitor_width * 4 + left * 4) as usize;
+                        let end = start + ((right - left) * 4) as usize;
+                        (start, combined_img.as_raw()[start..end].to_vec())
+                    })
+                    .collect();
+
                 unsafe {
-                    let src_ptr = frame_data.data.as_ptr().add(src_start);
+                    let src_ptr = data.as_ptr().add(src_start);
                     let dest_ptr = combined_img.as_mut_ptr().add(dest_start);
                     copy_nonoverlapping(src_ptr, dest_ptr, src_end - src_start);
                 }
+
+                // The combined image is always RGBA
+                let dest_end = dest_start + (src_end - src_start);
+                let row = &mut combined_img.as_mut()[dest_start..dest_end];
+                if frame_data.format == PixelFormat::Bgra8 {
+                    for pixel in row.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                }
+                if let Some(correction) = corrections.get(&monitor.id) {
+                    S::correct(correction, row);
+                }
+
+                for (start, pixels) in previous {
+                    let blended = &mut combined_img.as_mut()[start..start + pixels.len()];
+                    for (new, old) in blended.iter_mut().zip(pixels) {
+                        *new = S::from_f32((new.to_f32() + old.to_f32()) / 2.0);
+                    }
+                }
             }
 
             //log::info!("Thread {}:: Image {} copied successfully in {:?}", thread_num, i, start_time.elapsed());
         }
     }
 
-    log::info!("Thread {}:: Combined image creation took: {:?}", thread_num, start_time.elapsed());
-    Ok(combined_img)
+    log_contributing_monitors(thread_num, value, &contributing, contributing_monitors);
+    log::trace!("Thread {}:: Combined image creation took: {:?}", thread_num, start_time.elapsed());
+    // The oldest frame decides how stale the combined image is
+    let captured_at = frame_data_copy.values().map(|frame_data| frame_data.captured_at).min();
+    (S::into_combined(combined_img), captured_at)
 }
 
-pub fn calculate_avg_colors(image: &RgbaImage, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &Vec<LED>) -> Result<Vec<Color>, Box<dyn std::error::Error>> {
+// Logs which monitors a thread's image is built from whenever that set changes,
+// e.g. when a monitor's capture fails or comes back. `last` holds the set of the
+// previous combined image and is kept by the caller, one per pipeline
+fn log_contributing_monitors(thread_num: u32, monitors: &[SlimMonitorInfo], contributing: &[i32], last: &mut Vec<i32>) {
+    if last.as_slice() == contributing {
+        return;
+    }
+    let missing: Vec<i32> = monitors.iter().map(|m| m.id).filter(|id| !contributing.contains(id)).collect();
+    if missing.is_empty() {
+        log::info!("Thread {}:: Combining monitors {:?}", thread_num, contributing);
+    } else {
+        log::warn!(
+            "Thread {}:: Combining monitors {:?}, no frame from {:?} (left black)",
+            thread_num,
+            contributing,
+            missing
+        );
+    }
+    last.clear();
+    last.extend_from_slice(contributing);
+}
+
+// sRGB encoded channel value to linear light, both in the 0..255 range
+static SRGB_TO_LINEAR: Lazy<[f32; 256]> = Lazy::new(|| {
+    let mut lut = [0.0; 256];
+    for (i, value) in lut.iter_mut().enumerate() {
+        let c = i as f32 / 255.0;
+        let linear = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        *value = linear * 255.0;
+    }
+    lut
+});
+
+// Unrounded counterpart of SRGB_TO_LINEAR, for f32 samples
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    let c = (value / 255.0).clamp(0.0, 1.0);
+    let linear = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    linear * 255.0
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    let c = (value / 255.0).clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    srgb * 255.0
+}
+
+// How the pixels of an LED region are reduced to one color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AveragingMode {
+    // Plain mean of R, G and B
+    #[default]
+    Mean,
+    // Mean luma, with the chroma (U/V) averaged weighted by luma. A plain mean of Y, U and
+    // V would equal the RGB mean; weighting lets small bright highlights set the tint while
+    // the brightness still follows the whole region.
+    Yuv,
+}
+
+impl AveragingMode {
+    pub fn from_config(grab: &Grab) -> Self {
+        match grab.AveragingMode.as_str() {
+            "Mean" => AveragingMode::Mean,
+            "Yuv" => AveragingMode::Yuv,
+            other => {
+                log::warn!("Unknown AveragingMode {:?}, using Mean", other);
+                AveragingMode::Mean
+            }
+        }
+    }
+}
 
-    let scaling = 4; // Scaling factor for the image
+// BT.601 luma and U/V color differences of an RGB color, all in the 0..255 scale
+fn rgb_to_yuv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    [y, 0.492 * (b - y), 0.877 * (r - y)]
+}
+
+fn yuv_to_rgb([y, u, v]: [f32; 3]) -> [f32; 3] {
+    let r = y + v / 0.877;
+    let b = y + u / 0.492;
+    let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+    [r, g, b].map(|c| c.clamp(0.0, 255.0))
+}
+
+// What an LED whose whole region lies outside the captured image shows. Regions that are
+// only partly outside are averaged over their visible pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OffscreenLeds {
+    // What calculate_avg_colors returns for a region without pixels
+    #[default]
+    Black,
+    // The LED's color of the previous frame
+    Hold,
+}
+
+impl OffscreenLeds {
+    pub fn from_config(grab: &Grab) -> Self {
+        match grab.OffscreenLeds.as_str() {
+            "Black" => OffscreenLeds::Black,
+            "Hold" => OffscreenLeds::Hold,
+            other => {
+                log::warn!("Unknown OffscreenLeds {:?}, using Black", other);
+                OffscreenLeds::Black
+            }
+        }
+    }
+}
+
+// Indices of the LEDs of which calculate_avg_colors with the same arguments samples no
+// pixel at all, i.e. whose region is entirely off-screen
+pub fn offscreen_led_indices(min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &[LED], options: &AveragingOptions) -> Vec<i32> {
+    leds_array
+        .iter()
+        .filter(|led| {
+            let (x_range, y_range) = sampling_area(led, options);
+            let visible = |range: Range<i32>, position: i32, min: i32, max: i32| {
+                range.step_by(SAMPLE_STEP).any(|offset| (0..max).contains(&(position + offset - min)))
+            };
+            !(visible(x_range, led.Position.x, min_x, max_x) && visible(y_range, led.Position.y, min_y, max_y))
+        })
+        .map(|led| led.index)
+        .collect()
+}
+
+// Replaces the colors of the `offscreen` LEDs with their color in `previous`, LEDs
+// missing there keep their color
+pub fn hold_offscreen_colors(colors: &mut [Color], offscreen: &[i32], previous: &[Color]) {
+    for color in colors.iter_mut().filter(|color| offscreen.contains(&color.led_index)) {
+        if let Some(held) = previous.iter().find(|held| held.led_index == color.led_index) {
+            *color = held.clone();
+        }
+    }
+}
+
+// Options for calculate_avg_colors, usually built from the Grab config section
+#[derive(Debug, Clone, Default)]
+pub struct AveragingOptions {
+    // Average in linear light instead of on the sRGB encoded values. Plain sRGB averaging
+    // biases towards dark: half white / half black averages to 128 instead of the
+    // perceptually correct 188, so high-contrast regions come out brighter and more saturated.
+    pub linear: bool,
+    // Only sample this many pixels from the screen-facing edge of each region (0 = whole region)
+    pub sampling_depth: i32,
+    // Pixels left out on every side of each region, keeps neighboring LEDs from sampling
+    // the same content at their shared border
+    pub sampling_gap: i32,
+    // Pixels within the tolerance (per channel) of this color are skipped. A region made up
+    // only of that color still averages all its pixels instead of going black.
+    pub ignore_color: Option<([u8; 3], u8)>,
+    pub mode: AveragingMode,
+}
+
+impl AveragingOptions {
+    pub fn from_config(grab: &Grab) -> Self {
+        let ignore_color = if grab.IgnoreColor.trim().is_empty() {
+            None
+        } else {
+            match parse_hex_color(&grab.IgnoreColor) {
+                Ok((r, g, b)) => Some(([r, g, b], grab.IgnoreColorTolerance)),
+                Err(e) => {
+                    log::error!("Invalid IgnoreColor, not filtering: {}", e);
+                    None
+                }
+            }
+        };
+        AveragingOptions {
+            linear: grab.IsLinearAveragingEnabled,
+            sampling_depth: grab.SamplingDepth as i32,
+            sampling_gap: grab.SamplingGap as i32,
+            ignore_color,
+            mode: AveragingMode::from_config(grab),
+        }
+    }
+
+    fn is_ignored<S: Sample>(&self, pixel: &[S]) -> bool {
+        match self.ignore_color {
+            Some((color, tolerance)) => (0..3).all(|i| (pixel[i].to_f32() - color[i] as f32).abs() <= tolerance as f32),
+            None => false,
+        }
+    }
+}
+
+// Every SAMPLE_STEP-th pixel in both directions is sampled
+pub(crate) const SAMPLE_STEP: usize = 4;
+
+// Part of the LED region that gets sampled: the whole region, or only a band of
+// `sampling_depth` pixels along the side facing the screen border, shrunk by
+// `sampling_gap` on every side
+pub(crate) fn sampling_area(led: &LED, options: &AveragingOptions) -> (Range<i32>, Range<i32>) {
+    let (width, height) = (led.Size.width, led.Size.height);
+    let depth = options.sampling_depth;
+    let (x_range, y_range) = match led.edge {
+        _ if depth <= 0 => (0..width, 0..height),
+        Some(Edge::Top) => (0..width, 0..depth.min(height)),
+        Some(Edge::Bottom) => (0..width, (height - depth).max(0)..height),
+        Some(Edge::Left) => (0..depth.min(width), 0..height),
+        Some(Edge::Right) => ((width - depth).max(0)..width, 0..height),
+        None => (0..width, 0..height),
+    };
+    (inset(x_range, options.sampling_gap), inset(y_range, options.sampling_gap))
+}
+
+// Range shrunk by `gap` at both ends, at most down to its middle pixel so a large gap
+// never leaves a region without samples
+fn inset(range: Range<i32>, gap: i32) -> Range<i32> {
+    if gap <= 0 || range.is_empty() {
+        return range;
+    }
+    let max_gap = (range.end - range.start - 1) / 2;
+    let gap = gap.min(max_gap);
+    range.start + gap..range.end - gap
+}
+
+// One color per LED, in the order of `leds_array`. A region partly outside the image is
+// averaged over its visible pixels only, a region entirely outside it comes out black
+// (see OffscreenLeds for holding the previous color instead).
+pub fn calculate_avg_colors<S: Sample>(image: &ImageBuffer<S::Pixel, Vec<S>>, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &Vec<LED>, options: &AveragingOptions) -> Result<Vec<Color>, Error> {
+
+    let decode = |v: S| if options.linear { v.to_linear() } else { v.to_f32() };
+    let encode = |v: f32| if options.linear { linear_to_srgb(v) } else { v };
 
     let avg_colors: Vec<Color> = leds_array.par_iter().map(|led| {        
-        let mut r_sum = 0;
-        let mut g_sum = 0;
-        let mut b_sum = 0;
+        let mut r_sum = 0.0;
+        let mut g_sum = 0.0;
+        let mut b_sum = 0.0;
         let mut count = 0;
+        // Sums of the ignored pixels, only used if the whole region matched the ignored color
+        let mut ignored_sum = [0.0; 3];
+        let mut ignored_count = 0;
+        // Luma weighted RGB sums and the sum of the weights, for AveragingMode::Yuv
+        let mut weighted_sum = [0.0; 3];
+        let mut luma_sum = 0.0;
 
         let position = (led.Position.x, led.Position.y);
-        let size = (led.Size.width, led.Size.height);
+        let (x_range, y_range) = sampling_area(led, options);
         // Use a single loop to iterate over the pixels
-        for y in (0..size.1).step_by(scaling) {
+        for y in y_range.step_by(SAMPLE_STEP) {
             let pixel_y = (position.1 + y - min_y) as i32;
             if pixel_y < 0 || pixel_y >= max_y as i32 {
                 continue;
             }
 
-            for x in (0..size.0).step_by(scaling) {
+            for x in x_range.clone().step_by(SAMPLE_STEP) {
                 let pixel_x = (position.0 + x - min_x) as i32;
                 if pixel_x < 0 || pixel_x >= max_x as i32 {
                     continue;
                 }
 
-                let pixel = image.get_pixel(pixel_x as u32, pixel_y as u32);
-                r_sum += pixel[0] as u32;
-                g_sum += pixel[1] as u32;
-                b_sum += pixel[2] as u32;
+                let pixel = image.get_pixel(pixel_x as u32, pixel_y as u32).channels();
+                if options.is_ignored(pixel) {
+                    for (sum, &value) in ignored_sum.iter_mut().zip(pixel.iter()) {
+                        *sum += decode(value);
+                    }
+                    ignored_count += 1;
+                    continue;
+                }
+                let rgb = [decode(pixel[0]), decode(pixel[1]), decode(pixel[2])];
+                r_sum += rgb[0];
+                g_sum += rgb[1];
+                b_sum += rgb[2];
                 count += 1;
+                if options.mode == AveragingMode::Yuv {
+                    let luma = 0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2];
+                    for (sum, value) in weighted_sum.iter_mut().zip(rgb) {
+                        *sum += value * luma;
+                    }
+                    luma_sum += luma;
+                }
             }
         }
+
+        if count == 0 && ignored_count != 0 {
+            [r_sum, g_sum, b_sum] = ignored_sum;
+            count = ignored_count;
+        }
         
         if count != 0 {
+            let mut mean = [r_sum / count as f32, g_sum / count as f32, b_sum / count as f32];
+            // A black region has no luma to weight with and keeps the plain mean
+            if luma_sum > 0.0 {
+                let [luma, _, _] = rgb_to_yuv(mean);
+                let [_, u, v] = rgb_to_yuv(weighted_sum.map(|sum| sum / luma_sum));
+                mean = yuv_to_rgb([luma, u, v]);
+            }
             Color::new(
                 led.index.clone(),
-                (r_sum / count) as u8, // * (1./led.CoefRed)
-                (g_sum / count) as u8, // * (1./led.CoefGreen)
-                (b_sum / count) as u8) // * (1./led.CoefBlue)
+                encode(mean[0]), // * (1./led.CoefRed)
+                encode(mean[1]), // * (1./led.CoefGreen)
+                encode(mean[2])) // * (1./led.CoefBlue)
         } else {
-            Color::new(led.index.clone(), 0, 0, 0) // Default to black if no pixels are counted
+            Color::new(led.index.clone(), 0.0, 0.0, 0.0) // Default to black if no pixels are counted
         }
         
     }).collect();
@@ -199,13 +1573,43 @@ pub fn calculate_avg_colors(image: &RgbaImage, min_x: i32, min_y: i32, max_x: i3
 }
 
 
+// calculate_avg_colors for a frame of which only the `dirty` rectangles changed: LEDs whose
+// region touches one are averaged again, all others keep their color from `previous`,
+// which has to be the last result for the same `leds_array`. Rectangles are in the same
+// coordinates as the LED regions.
+#[allow(clippy::too_many_arguments)]
+pub fn update_avg_colors<S: Sample>(image: &ImageBuffer<S::Pixel, Vec<S>>, min_x: i32, min_y: i32, max_x: i32, max_y: i32, leds_array: &Vec<LED>, options: &AveragingOptions, dirty: &[DirtyRect], previous: &[Color]) -> Result<Vec<Color>, Error> {
+    if previous.len() != leds_array.len() {
+        return calculate_avg_colors(image, min_x, min_y, max_x, max_y, leds_array, options);
+    }
+
+    let intersects = |led: &LED| {
+        dirty.iter().any(|&(x, y, width, height)| {
+            led.Position.x < x + width
+                && x < led.Position.x + led.Size.width
+                && led.Position.y < y + height
+                && y < led.Position.y + led.Size.height
+        })
+    };
+    let changed: Vec<usize> = (0..leds_array.len()).filter(|&i| intersects(&leds_array[i])).collect();
+    let changed_leds: Vec<LED> = changed.iter().map(|&i| leds_array[i].clone()).collect();
+    log::trace!("Averaging {} of {} LEDs", changed_leds.len(), leds_array.len());
+
+    let fresh = calculate_avg_colors(image, min_x, min_y, max_x, max_y, &changed_leds, options)?;
+    let mut colors = previous.to_vec();
+    for (i, color) in changed.into_iter().zip(fresh) {
+        colors[i] = color;
+    }
+    Ok(colors)
+}
+
 pub fn save_screenshot_with_avg_colors(
     image: &RgbaImage,
     leds_array: &Vec<LED>,
     avg_colors: &Vec<Color>,
     path: &str,
     min_x: i32, min_y: i32, max_x: i32, max_y: i32
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Error> {
     //Create a clone of the image to store results, and we'll merge them later
     let result_image = Arc::new(Mutex::new(image.clone()));
     
@@ -214,7 +1618,7 @@ pub fn save_screenshot_with_avg_colors(
     leds_array.clone().par_iter().enumerate().for_each(|(i,led)| {
         let position = (led.Position.x, led.Position.y);
         let size = (led.Size.width, led.Size.height);
-        let color = Color::new(led.index.clone(), avg_colors[i].r, avg_colors[i].g, avg_colors[i].b);
+        let [r, g, b] = avg_colors[i].to_rgb8();
 
         for x in 0..size.0 {
             for y in 0..size.1 {
@@ -232,7 +1636,7 @@ pub fn save_screenshot_with_avg_colors(
                 result_image.put_pixel(
                     pixel_x as u32,
                     pixel_y as u32,
-                    Rgba([color.r, color.g, color.b, 255]),
+                    Rgba([r, g, b, 255]),
                 );
             }
         }
@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Server;
+use crate::metrics;
+use crate::SharedState;
+
+// Largest request body accepted by the control endpoints
+const MAX_BODY_SIZE: usize = 1024;
+
+// The parts of an HTTP request the endpoints look at
+#[cfg_attr(not(feature = "remote-control"), allow(dead_code))]
+struct Request {
+    method: String,
+    path: String,
+    authorization: String,
+    body: Vec<u8>,
+}
+
+// Minimal HTTP server answering `GET /status` with the backend state as JSON.
+// With the `remote-control` feature `POST /active` with a `true`/`false` body starts
+// or stops the output, guarded by `Server.Token` as a bearer token if one is set.
+// Each connection is handled on the server thread and closed after one response.
+pub fn start(
+    settings: &Server,
+    shared_state: Arc<Mutex<SharedState>>,
+    wled_address: &'static str,
+) -> Result<thread::JoinHandle<()>, crate::error::Error> {
+    let listener = TcpListener::bind((settings.Address.as_str(), settings.Port))?;
+    log::info!("Status server listening on {}:{}", settings.Address, settings.Port);
+    let settings = settings.clone();
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(|e| e.into())
+                .and_then(|stream| handle_connection(stream, &shared_state, wled_address, &settings));
+            if let Err(e) = result {
+                log::warn!("Status server request failed: {}", e);
+            }
+        }
+    }))
+}
+
+#[cfg_attr(not(feature = "remote-control"), allow(unused_variables))]
+fn handle_connection(
+    mut stream: TcpStream,
+    shared_state: &Arc<Mutex<SharedState>>,
+    wled_address: &str,
+    settings: &Server,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let Some(request) = read_request(&stream)? else {
+        return write_response(&mut stream, "413 Payload Too Large", &serde_json::json!({ "error": "body too large" }));
+    };
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => {
+            let state = shared_state.lock().unwrap();
+            let body = serde_json::json!({
+                "is_active": state.is_active,
+                "mode": format!("{:?}", state.mode),
+                "fps": state.fps,
+                "dropped_frames": state.dropped_frames,
+                "latency_ms": state.latency.map(|latency| latency.as_secs_f64() * 1000.0),
+                "wled_address": wled_address,
+                "stages": metrics::stages_json(),
+                "pipelines": state
+                    .pipelines
+                    .iter()
+                    .map(|pipeline| serde_json::json!({
+                        "name": pipeline.name,
+                        "is_enabled": pipeline.is_enabled,
+                        "health": pipeline.health.to_json(),
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            drop(state);
+            write_response(&mut stream, "200 OK", &body)
+        }
+        #[cfg(feature = "remote-control")]
+        ("POST", "/active") => set_active(&mut stream, &request, shared_state, &settings.Token),
+        _ => write_response(&mut stream, "404 Not Found", &serde_json::json!({ "error": "not found" })),
+    }
+}
+
+// Reads the request line, the headers we care about and the body.
+// Returns None if the body is larger than MAX_BODY_SIZE.
+fn read_request(stream: &TcpStream) -> Result<Option<Request>, Box<dyn Error>> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    let mut authorization = String::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = value.trim().to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Ok(None);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Request {
+        method,
+        path,
+        authorization,
+        body,
+    }))
+}
+
+// POST /active with `true` or `false`
+#[cfg(feature = "remote-control")]
+fn set_active(
+    stream: &mut TcpStream,
+    request: &Request,
+    shared_state: &Arc<Mutex<SharedState>>,
+    token: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !token.is_empty() && request.authorization != format!("Bearer {}", token) {
+        return write_response(stream, "401 Unauthorized", &serde_json::json!({ "error": "unauthorized" }));
+    }
+    let active = match String::from_utf8_lossy(&request.body).trim() {
+        "true" => true,
+        "false" => false,
+        _ => {
+            return write_response(stream, "400 Bad Request", &serde_json::json!({ "error": "expected true or false" }));
+        }
+    };
+    shared_state.lock().unwrap().is_active = active;
+    log::info!("Backend set {} remotely", if active { "active" } else { "inactive" });
+    write_response(stream, "200 OK", &serde_json::json!({ "is_active": active }))
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> Result<(), Box<dyn Error>> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
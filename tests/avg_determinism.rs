@@ -0,0 +1,74 @@
+use image::{Rgba, RgbaImage};
+use lightshow::screen_capture::AveragingMode;
+use lightshow::{calculate_avg_colors, AveragingOptions, Color, LED};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+const RUNS: usize = 50;
+
+// Every pixel differs from its neighbors, so a region mixed up with another shows
+fn gradient() -> RgbaImage {
+    RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        Rgba([(x * 7 % 256) as u8, (y * 13 % 256) as u8, ((x + y) * 3 % 256) as u8, 255])
+    })
+}
+
+// Overlapping regions along all four edges, in an index order unlike the position order
+fn leds() -> Vec<LED> {
+    let mut leds = Vec::new();
+    for i in 0..40 {
+        leds.push(LED::new(i * 4, i * 8, 0, 16, 20));
+        leds.push(LED::new(i * 4 + 1, i * 8, HEIGHT as i32 - 20, 16, 20));
+    }
+    for i in 0..20 {
+        leds.push(LED::new(i * 4 + 2, 0, i * 9, 24, 18));
+        leds.push(LED::new(i * 4 + 3, WIDTH as i32 - 24, i * 9, 24, 18));
+    }
+    leds
+}
+
+// Bit patterns, so the comparison is exact and not within a float tolerance
+fn bits(colors: &[Color]) -> Vec<(i32, u32, u32, u32)> {
+    colors
+        .iter()
+        .map(|c| (c.led_index, c.r.to_bits(), c.g.to_bits(), c.b.to_bits()))
+        .collect()
+}
+
+fn assert_deterministic(options: &AveragingOptions) {
+    let image = gradient();
+    let leds = leds();
+    let average = || calculate_avg_colors(&image, 0, 0, WIDTH as i32, HEIGHT as i32, &leds, options).unwrap();
+
+    let first = average();
+    // Output follows the order of the LED list
+    let indices: Vec<i32> = first.iter().map(|c| c.led_index).collect();
+    let expected: Vec<i32> = leds.iter().map(|led| led.index).collect();
+    assert_eq!(indices, expected);
+
+    let first = bits(&first);
+    for run in 1..RUNS {
+        assert_eq!(bits(&average()), first, "run {} differs from the first", run);
+    }
+}
+
+#[test]
+fn plain_averaging_is_deterministic() {
+    assert_deterministic(&AveragingOptions::default());
+}
+
+#[test]
+fn linear_averaging_with_depth_and_gap_is_deterministic() {
+    assert_deterministic(&AveragingOptions {
+        linear: true,
+        sampling_depth: 6,
+        sampling_gap: 2,
+        ignore_color: Some(([0, 0, 0], 8)),
+        mode: AveragingMode::Mean,
+    });
+}
+
+#[test]
+fn yuv_averaging_is_deterministic() {
+    assert_deterministic(&AveragingOptions { mode: AveragingMode::Yuv, ..AveragingOptions::default() });
+}
@@ -0,0 +1,48 @@
+use image::{Rgba, RgbaImage};
+use lightshow::screen_capture::BoxBlur;
+
+// Averages every window pixel by pixel, one pass per direction like BoxBlur
+fn naive_blur(image: &RgbaImage, radius: i64) -> RgbaImage {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let window = (2 * radius + 1) as u32;
+    let pass = |source: &RgbaImage, (step_x, step_y): (i64, i64)| {
+        RgbaImage::from_fn(source.width(), source.height(), |x, y| {
+            let mut sum = [0u32; 3];
+            for d in -radius..=radius {
+                let sx = (x as i64 + d * step_x).clamp(0, width - 1) as u32;
+                let sy = (y as i64 + d * step_y).clamp(0, height - 1) as u32;
+                let pixel = source.get_pixel(sx, sy);
+                for c in 0..3 {
+                    sum[c] += pixel[c] as u32;
+                }
+            }
+            let alpha = source.get_pixel(x, y)[3];
+            Rgba([(sum[0] / window) as u8, (sum[1] / window) as u8, (sum[2] / window) as u8, alpha])
+        })
+    };
+    pass(&pass(image, (1, 0)), (0, 1))
+}
+
+fn pattern(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 37 % 256) as u8, (y * 53 % 256) as u8, ((x * y) % 256) as u8, (x % 2) as u8 * 255])
+    })
+}
+
+#[test]
+fn uniform_image_stays_the_same() {
+    let mut image = RgbaImage::from_pixel(20, 10, Rgba([40, 80, 120, 255]));
+    BoxBlur::new(3).apply(&mut image);
+    assert!(image.pixels().all(|p| *p == Rgba([40, 80, 120, 255])));
+}
+
+#[test]
+fn running_sums_match_the_per_pixel_average() {
+    let mut blur = BoxBlur::new(4);
+    for (width, height) in [(31, 17), (5, 3), (1, 9)] {
+        let source = pattern(width, height);
+        let mut image = source.clone();
+        blur.apply(&mut image);
+        assert!(image == naive_blur(&source, 4), "{}x{} differs from the per-pixel blur", width, height);
+    }
+}
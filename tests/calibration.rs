@@ -0,0 +1,36 @@
+use lightshow::calibration::solve_coefficients;
+use lightshow::Error;
+
+fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn neutral_primaries_need_no_correction() {
+    let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    assert_close(solve_coefficients(identity).unwrap(), [1.0, 1.0, 1.0]);
+
+    // The overall sensor level doesn't matter, only the balance between the primaries
+    let brighter = [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]];
+    assert_close(solve_coefficients(brighter).unwrap(), [1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn skewed_primaries_are_balanced() {
+    // Strong red and blue with some crosstalk, 0.5 red + 1 green + 0.25 blue reads white
+    let primaries = [[1.6, 0.2, 0.0], [0.2, 0.8, 0.4], [0.0, 0.4, 2.4]];
+    let coefficients = solve_coefficients(primaries).unwrap();
+    assert_close(coefficients, [0.5, 1.0, 0.25]);
+
+    let white: [f32; 3] = std::array::from_fn(|c| (0..3).map(|p| primaries[p][c] * coefficients[p]).sum());
+    assert_close(white, [1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn dependent_primaries_are_rejected() {
+    // Green reads the same as red, e.g. the sensor saw neither
+    let primaries = [[0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [0.0, 0.0, 1.0]];
+    assert!(matches!(solve_coefficients(primaries), Err(Error::Validation(_))));
+}
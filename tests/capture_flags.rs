@@ -0,0 +1,38 @@
+use lightshow::hardware_interaction::{parse_flags, CaptureFlags};
+
+#[test]
+fn parses_valid_flags() {
+    let flags = parse_flags("2,30").unwrap();
+    assert_eq!(flags, CaptureFlags { monitor_id: 2, fps_limit: 30 });
+}
+
+#[test]
+fn tolerates_whitespace() {
+    let flags = parse_flags(" 1 , 24 ").unwrap();
+    assert_eq!(flags, CaptureFlags { monitor_id: 1, fps_limit: 24 });
+}
+
+#[test]
+fn round_trips_the_backend_format() {
+    let flags = CaptureFlags { monitor_id: 3, fps_limit: 60 };
+    assert_eq!(parse_flags(&flags.to_string()).unwrap(), flags);
+}
+
+#[test]
+fn rejects_extra_commas() {
+    assert!(parse_flags("1,24,5").is_err());
+    assert!(parse_flags("1,24,").is_err());
+}
+
+#[test]
+fn rejects_non_numeric_fields() {
+    assert!(parse_flags("a,24").is_err());
+    assert!(parse_flags("1,fast").is_err());
+    assert!(parse_flags("1,-5").is_err());
+}
+
+#[test]
+fn rejects_empty_input() {
+    assert!(parse_flags("").is_err());
+    assert!(parse_flags(",").is_err());
+}
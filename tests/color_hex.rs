@@ -0,0 +1,54 @@
+use lightshow::{Color, Error};
+
+#[test]
+fn to_hex_zero_pads_channels() {
+    assert_eq!(Color::from_rgb8(0, 0, 5, 255).to_hex(), "0005FF");
+    assert_eq!(Color::from_rgb8(0, 0, 0, 0).to_hex(), "000000");
+}
+
+#[test]
+fn to_hex_is_uppercase() {
+    assert_eq!(Color::from_rgb8(0, 171, 205, 239).to_hex(), "ABCDEF");
+}
+
+#[test]
+fn to_hex_rounds_and_clamps_float_channels() {
+    assert_eq!(Color::new(0, 15.6, -3.0, 300.0).to_hex(), "1000FF");
+}
+
+#[test]
+fn from_hex_accepts_both_cases_and_hash() {
+    let color = Color::from_hex(3, "#0005ff").unwrap();
+    assert_eq!(color.led_index, 3);
+    assert_eq!(color.to_rgb8(), [0, 5, 255]);
+    assert_eq!(Color::from_hex(0, "0005FF").unwrap().to_rgb8(), [0, 5, 255]);
+}
+
+#[test]
+fn from_hex_rejects_invalid_input() {
+    assert!(Color::from_hex(0, "").is_err());
+    assert!(Color::from_hex(0, "12345").is_err());
+    assert!(Color::from_hex(0, "GG0000").is_err());
+    assert!(Color::from_hex(0, "1234567").is_err());
+}
+
+#[test]
+fn invalid_hex_is_a_validation_error() {
+    assert!(matches!(Color::from_hex(0, "GG0000"), Err(Error::Validation(_))));
+}
+
+#[test]
+fn hex_round_trips() {
+    for hex in ["000000", "FFFFFF", "0005FF", "7F8081", "ABCDEF"] {
+        assert_eq!(Color::from_hex(0, hex).unwrap().to_hex(), hex);
+    }
+}
+
+#[test]
+fn lower_color_depth_changes_the_sent_values() {
+    let color = Color::new(0, 100.0, 7.0, 250.0);
+    assert_eq!(color.with_depth(255).to_rgb8(), [100, 7, 250]);
+    // 15 levels per channel, 17 apart
+    assert_eq!(color.with_depth(15).to_rgb8(), [102, 0, 255]);
+    assert_eq!(color.with_depth(1).to_rgb8(), [0, 0, 255]);
+}
@@ -0,0 +1,41 @@
+use lightshow::config::update_config_values;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+
+fn write_temp(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lightshow_{}_{}", std::process::id(), name));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn toml_updates_keep_comments_and_key_order() {
+    let path = write_temp(
+        "update.toml",
+        "# Output settings\n[Device]\nBrightness = 100 # percent\nGamma = 2.0\n\n[Grab]\nSlowdown = 50\n",
+    );
+    update_config_values(
+        path.to_str().unwrap(),
+        &[("Device", "Brightness", json!(40)), ("Device", "IsGammaEnabled", json!(false))],
+    )
+    .unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        content,
+        "# Output settings\n[Device]\nBrightness = 40 # percent\nGamma = 2.0\nIsGammaEnabled = false\n\n[Grab]\nSlowdown = 50\n"
+    );
+}
+
+#[test]
+fn json_update_of_a_non_section_is_an_error() {
+    let path = write_temp("update.json", r#"{"Device": 5}"#);
+    let result = update_config_values(path.to_str().unwrap(), &[("Device", "Brightness", json!(40))]);
+    let content = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+    assert_eq!(content, r#"{"Device": 5}"#);
+}
@@ -0,0 +1,54 @@
+use image::{Rgba, RgbaImage};
+use lightshow::screen_capture::{update_avg_colors, CombinedImage, DirtyTiles, DIRTY_TILE_SIZE};
+use lightshow::{AveragingOptions, Color, LED};
+
+const SIZE: u32 = 256;
+
+// One LED in every corner, on a desktop starting at (100, 50)
+fn corner_leds() -> Vec<LED> {
+    vec![
+        LED::new(0, 100, 50, 40, 40),
+        LED::new(1, 100 + SIZE as i32 - 40, 50, 40, 40),
+        LED::new(2, 100, 50 + SIZE as i32 - 40, 40, 40),
+        LED::new(3, 100 + SIZE as i32 - 40, 50 + SIZE as i32 - 40, 40, 40),
+    ]
+}
+
+#[test]
+fn unchanged_frame_has_no_dirty_tiles() {
+    let image = CombinedImage::Rgba8(RgbaImage::from_pixel(SIZE, SIZE, Rgba([10, 20, 30, 255])));
+    let mut tiles = DirtyTiles::new();
+    assert_eq!(tiles.update(&image, 0, 0), None, "the first frame has nothing to compare with");
+    assert_eq!(tiles.update(&image, 0, 0), Some(Vec::new()));
+    assert_eq!(tiles.update(&CombinedImage::Rgba8(RgbaImage::new(SIZE / 2, SIZE)), 0, 0), None);
+}
+
+#[test]
+fn only_the_leds_over_a_changed_corner_are_recomputed() {
+    let previous = RgbaImage::from_pixel(SIZE, SIZE, Rgba([0, 0, 0, 255]));
+    let mut image = previous.clone();
+    for y in 0..10 {
+        for x in SIZE - 10..SIZE {
+            image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+        }
+    }
+
+    let mut tiles = DirtyTiles::new();
+    tiles.update(&CombinedImage::Rgba8(previous), 100, 50);
+    let dirty = tiles.update(&CombinedImage::Rgba8(image.clone()), 100, 50).unwrap();
+    let tile = DIRTY_TILE_SIZE as i32;
+    assert_eq!(dirty, vec![(100 + SIZE as i32 - tile, 50, tile, tile)]);
+
+    // Colors that averaging the black frame can't produce, so untouched LEDs show
+    let leds = corner_leds();
+    let held: Vec<Color> = leds.iter().map(|led| Color::new(led.index, 1.0, 2.0, 3.0)).collect();
+    let colors = update_avg_colors(
+        &image, 100, 50, 100 + SIZE as i32, 50 + SIZE as i32, &leds, &AveragingOptions::default(), &dirty, &held,
+    )
+    .unwrap();
+
+    assert!(colors[1].r > 1.0, "the LED over the changed corner is averaged again");
+    for i in [0, 2, 3] {
+        assert_eq!(colors[i].to_rgb8(), [1, 2, 3], "LED {} keeps its previous color", i);
+    }
+}
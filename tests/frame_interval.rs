@@ -0,0 +1,28 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use lightshow::backend::{capture_fps, frame_interval, CAPTURE_FPS, CAPTURE_FPS_UNSET};
+
+#[test]
+fn zero_fps_is_uncapped_instead_of_dividing_by_zero() {
+    assert_eq!(frame_interval(0), None);
+}
+
+#[test]
+fn interval_is_one_second_divided_by_the_rate() {
+    assert_eq!(frame_interval(1), Some(Duration::from_secs(1)));
+    assert_eq!(frame_interval(50), Some(Duration::from_millis(20)));
+    let interval = frame_interval(60).unwrap();
+    assert!(interval > Duration::from_micros(16_666) && interval < Duration::from_micros(16_668));
+}
+
+#[test]
+fn capture_rate_of_zero_stays_uncapped() {
+    // The only test touching CAPTURE_FPS in this binary, it is process wide
+    assert_eq!(capture_fps(30), 30, "the start rate counts until the backend sets one");
+    CAPTURE_FPS.store(0, Ordering::Relaxed);
+    assert_eq!(frame_interval(capture_fps(30)), None);
+    CAPTURE_FPS.store(5, Ordering::Relaxed);
+    assert_eq!(capture_fps(30), 5);
+    CAPTURE_FPS.store(CAPTURE_FPS_UNSET, Ordering::Relaxed);
+}
@@ -0,0 +1,39 @@
+use std::thread;
+use std::time::Instant;
+
+use lightshow::backend::frame_map;
+use lightshow::hardware_interaction::{FrameData, PixelFormat};
+
+fn frame(value: u8) -> FrameData {
+    FrameData {
+        data: vec![value; 4],
+        format: PixelFormat::Rgba8,
+        captured_at: Instant::now(),
+        scale: 1,
+    }
+}
+
+#[test]
+fn panic_while_holding_frame_map_does_not_wedge_other_threads() {
+    frame_map().insert(0, frame(1));
+
+    // A capture thread dying mid-update poisons the mutex
+    let result = thread::spawn(|| {
+        let mut map = frame_map();
+        map.insert(1, frame(2));
+        panic!("capture thread crashed");
+    })
+    .join();
+    assert!(result.is_err());
+
+    // Other threads keep working with the frames stored so far
+    let readers: Vec<_> = (0..4)
+        .map(|_| thread::spawn(|| frame_map().len()))
+        .collect();
+    for reader in readers {
+        assert_eq!(reader.join().unwrap(), 2);
+    }
+
+    frame_map().insert(0, frame(3));
+    assert_eq!(frame_map()[&0].data, vec![3; 4]);
+}
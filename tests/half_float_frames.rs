@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use lightshow::backend::frame_map;
+use lightshow::hardware_interaction::{FrameData, PixelFormat, SlimMonitorInfo};
+use lightshow::screen_capture::{
+    combine_screens, downscale_half_frame, rgba16f_to_rgba32f, rgba16f_to_rgba8, AveragingOptions, CombinedImage, OverlapMode,
+};
+use lightshow::LED;
+
+// One pixel of little endian half floats
+fn pixel(channels: [u16; 4]) -> Vec<u8> {
+    channels.iter().flat_map(|channel| channel.to_le_bytes()).collect()
+}
+
+// Half float bit patterns
+const ZERO: u16 = 0x0000;
+const HALF: u16 = 0x3800;
+const ONE: u16 = 0x3c00;
+const TWO: u16 = 0x4000;
+const MINUS_ONE: u16 = 0xbc00;
+
+#[test]
+fn sdr_white_and_black_map_to_the_8_bit_range() {
+    assert_eq!(rgba16f_to_rgba8(&pixel([ONE, ONE, ONE, ONE])), vec![255, 255, 255, 255]);
+    assert_eq!(rgba16f_to_rgba8(&pixel([ZERO, ZERO, ZERO, ONE])), vec![0, 0, 0, 255]);
+}
+
+#[test]
+fn linear_values_are_srgb_encoded() {
+    // Linear 0.5 is sRGB 0.735
+    assert_eq!(rgba16f_to_rgba8(&pixel([HALF, ZERO, ZERO, ONE])), vec![188, 0, 0, 255]);
+}
+
+#[test]
+fn out_of_range_values_clip() {
+    // scRGB carries HDR highlights above 1.0 and out of gamut colors below 0
+    assert_eq!(rgba16f_to_rgba8(&pixel([TWO, MINUS_ONE, ONE, TWO])), vec![255, 0, 255, 255]);
+}
+
+#[test]
+fn half_float_frames_have_8_bytes_per_pixel() {
+    assert_eq!(PixelFormat::from_config("Rgba16F"), PixelFormat::Rgba16F);
+    assert_eq!(PixelFormat::Rgba16F.bytes_per_pixel(), 8);
+    assert_eq!(PixelFormat::Rgba8.bytes_per_pixel(), 4);
+    assert_eq!(rgba16f_to_rgba8(&[pixel([ONE; 4]), pixel([ZERO; 4])].concat()).len(), 8);
+}
+
+#[test]
+fn half_floats_survive_a_downscale_by_one() {
+    // Every bit pattern comes back unchanged from the f32 round trip, but NaN and -0 (summed to +0)
+    let mut halves: Vec<u16> = (0..=u16::MAX)
+        .filter(|&bits| (bits & 0x7c00 != 0x7c00 || bits & 0x3ff == 0) && bits != 0x8000)
+        .collect();
+    halves.resize(halves.len().next_multiple_of(4), ZERO);
+    let data: Vec<u8> = halves.iter().flat_map(|bits| bits.to_le_bytes()).collect();
+    assert_eq!(downscale_half_frame(&data, halves.len() as u32 / 4, 1, 1), data);
+}
+
+#[test]
+fn half_float_frames_downscale_in_linear_light() {
+    let data = [pixel([ONE; 4]), pixel([ZERO, ZERO, ZERO, ONE]), pixel([ONE; 4]), pixel([ZERO, ZERO, ZERO, ONE])].concat();
+    assert_eq!(downscale_half_frame(&data, 2, 2, 2), pixel([HALF, HALF, HALF, ONE]));
+}
+
+#[test]
+fn averages_keep_the_precision_of_half_float_frames() {
+    let monitor = SlimMonitorInfo { id: 31, pos_x: 0, pos_y: 0, width: 8, height: 8 };
+    // A dark grey between two 8 bit steps
+    let grey = pixel([0x1c00, 0x1c00, 0x1c00, ONE]);
+    let expected = rgba16f_to_rgba32f(&grey)[0];
+    assert!(expected.fract() > 0.1 && expected.fract() < 0.9);
+
+    frame_map().insert(
+        monitor.id,
+        FrameData { data: grey.repeat(8 * 8), format: PixelFormat::Rgba16F, captured_at: Instant::now(), scale: 1 },
+    );
+    let (image, _) = combine_screens(&vec![monitor.clone()], 8, 8, 0, 0, 0, 1, &HashMap::new(), OverlapMode::Last, &mut Vec::new()).unwrap();
+    assert!(matches!(image, CombinedImage::Rgba32F(_)));
+
+    let leds = vec![LED::new(0, 0, 0, 8, 8)];
+    let colors = image.calculate_avg_colors(0, 0, 8, 8, &leds, &AveragingOptions::default()).unwrap();
+    assert!((colors[0].r - expected).abs() < 1e-3);
+    assert!((colors[0].b - expected).abs() < 1e-3);
+}
@@ -0,0 +1,55 @@
+use image::{Rgba, RgbaImage};
+use lightshow::screen_capture::draw_led_borders;
+use lightshow::LED;
+
+const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+fn red_pixels(image: &RgbaImage) -> usize {
+    image.pixels().filter(|p| **p == RED).count()
+}
+
+#[test]
+fn region_past_the_right_and_bottom_edge_is_clipped() {
+    let mut image = RgbaImage::from_pixel(10, 10, BLACK);
+    draw_led_borders(&mut image, 0, 0, 10, 10, &[LED::new(0, 6, 6, 8, 8)]);
+
+    // Only the top and left edges of the outline are inside the image
+    assert_eq!(*image.get_pixel(6, 6), RED);
+    assert_eq!(*image.get_pixel(9, 6), RED);
+    assert_eq!(*image.get_pixel(6, 9), RED);
+    assert_eq!(*image.get_pixel(9, 9), BLACK);
+    assert_eq!(red_pixels(&image), 7);
+}
+
+#[test]
+fn negative_coordinates_do_not_wrap() {
+    let mut image = RgbaImage::from_pixel(10, 10, BLACK);
+    draw_led_borders(&mut image, 0, 0, 10, 10, &[LED::new(0, -4, -4, 6, 6)]);
+
+    // Right and bottom edges at x = 1 and y = 1
+    assert_eq!(*image.get_pixel(1, 0), RED);
+    assert_eq!(*image.get_pixel(0, 1), RED);
+    assert_eq!(*image.get_pixel(0, 0), BLACK);
+    assert_eq!(red_pixels(&image), 3);
+}
+
+#[test]
+fn offset_desktop_origin_is_bounded_by_the_image() {
+    // Desktop starting at -1920 (monitor left of the primary), image only covers part of it
+    let mut image = RgbaImage::from_pixel(10, 10, BLACK);
+    draw_led_borders(&mut image, -1920, 0, 1920, 1080, &[LED::new(0, -1915, 2, 100, 4)]);
+
+    assert_eq!(*image.get_pixel(5, 2), RED);
+    assert_eq!(*image.get_pixel(9, 5), RED);
+    assert_eq!(*image.get_pixel(9, 3), BLACK);
+}
+
+#[test]
+fn regions_fully_off_screen_or_empty_draw_nothing() {
+    let mut image = RgbaImage::from_pixel(10, 10, BLACK);
+    let leds = [LED::new(0, 20, 20, 5, 5), LED::new(0, -10, 0, 5, 5), LED::new(0, 2, 2, 0, 3)];
+    draw_led_borders(&mut image, 0, 0, 10, 10, &leds);
+
+    assert_eq!(red_pixels(&image), 0);
+}
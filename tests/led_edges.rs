@@ -0,0 +1,19 @@
+use lightshow::config::{classify_edges, Edge};
+use lightshow::LED;
+
+#[test]
+fn edges_are_relative_to_the_screen_not_the_led_bounds() {
+    // Two LEDs in the middle of the bottom edge, their own bounding box is only 20 pixels high
+    let mut leds = vec![LED::new(0, 400, 480, 100, 20), LED::new(1, 500, 480, 100, 20)];
+    classify_edges(&mut leds, (0, 0, 1000, 500));
+    assert_eq!(leds[0].edge, Some(Edge::Bottom));
+    assert_eq!(leds[1].edge, Some(Edge::Bottom));
+}
+
+#[test]
+fn screens_left_of_the_primary_monitor_are_classified() {
+    let mut leds = vec![LED::new(0, -1920, 500, 40, 80), LED::new(1, -40, 500, 40, 80), LED::new(2, -1000, 0, 80, 40)];
+    classify_edges(&mut leds, (-1920, 0, 1920, 1080));
+    let edges: Vec<Option<Edge>> = leds.iter().map(|led| led.edge).collect();
+    assert_eq!(edges, vec![Some(Edge::Left), Some(Edge::Right), Some(Edge::Top)]);
+}
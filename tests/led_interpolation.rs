@@ -0,0 +1,34 @@
+use lightshow::arduino::interpolate_pixels;
+use lightshow::Color;
+
+fn rgb(colors: &[Color]) -> Vec<[u8; 3]> {
+    colors.iter().map(Color::to_rgb8).collect()
+}
+
+#[test]
+fn samples_are_stretched_over_the_strip() {
+    let samples = vec![Color::from_rgb8(0, 0, 0, 0), Color::from_rgb8(1, 200, 100, 0)];
+    let pixels = interpolate_pixels(&samples, 5);
+    assert_eq!(
+        rgb(&pixels),
+        vec![[0, 0, 0], [50, 25, 0], [100, 50, 0], [150, 75, 0], [200, 100, 0]]
+    );
+    let indices: Vec<i32> = pixels.iter().map(|c| c.led_index).collect();
+    assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn strip_ends_keep_the_outer_samples() {
+    let samples: Vec<Color> = (0..30).map(|i| Color::from_rgb8(i, i as u8 * 8, 0, 0)).collect();
+    let pixels = interpolate_pixels(&samples, 60);
+    assert_eq!(pixels.len(), 60);
+    assert_eq!(rgb(&pixels[..1]), rgb(&samples[..1]));
+    assert_eq!(rgb(&pixels[59..]), rgb(&samples[29..]));
+}
+
+#[test]
+fn single_sample_fills_the_strip() {
+    let pixels = interpolate_pixels(&[Color::from_rgb8(0, 10, 20, 30)], 3);
+    assert_eq!(rgb(&pixels), vec![[10, 20, 30]; 3]);
+    assert!(interpolate_pixels(&[], 3).is_empty());
+}
@@ -0,0 +1,13 @@
+use lightshow::screen_capture::scale_leds;
+use lightshow::LED;
+
+#[test]
+fn negative_positions_round_towards_negative_infinity() {
+    // A monitor left of the primary one starts at x = -1920
+    let leds = vec![LED::new(0, -1920, -3, 64, 64), LED::new(1, -5, 0, 3, 8)];
+    let scaled = scale_leds(&leds, 4);
+    assert_eq!((scaled[0].Position.x, scaled[0].Position.y), (-480, -1));
+    assert_eq!((scaled[0].Size.width, scaled[0].Size.height), (16, 16));
+    assert_eq!((scaled[1].Position.x, scaled[1].Position.y), (-2, 0));
+    assert_eq!((scaled[1].Size.width, scaled[1].Size.height), (1, 2));
+}
@@ -0,0 +1,58 @@
+use image::{Rgba, RgbaImage};
+use lightshow::screen_capture::{hold_offscreen_colors, offscreen_led_indices};
+use lightshow::{calculate_avg_colors, AveragingOptions, Color, LED};
+
+fn rgb(color: &Color) -> [u8; 3] {
+    color.to_rgb8()
+}
+
+fn red_image() -> RgbaImage {
+    RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]))
+}
+
+#[test]
+fn fully_offscreen_region_is_black() {
+    let leds = vec![LED::new(0, 20, 20, 8, 8), LED::new(1, -12, 0, 8, 8)];
+    let options = AveragingOptions::default();
+    let colors = calculate_avg_colors(&red_image(), 0, 0, 16, 16, &leds, &options).unwrap();
+    assert_eq!(rgb(&colors[0]), [0, 0, 0]);
+    assert_eq!(rgb(&colors[1]), [0, 0, 0]);
+    assert_eq!(offscreen_led_indices(0, 0, 16, 16, &leds, &options), vec![0, 1]);
+}
+
+#[test]
+fn partially_offscreen_region_averages_only_visible_pixels() {
+    // Half of the region hangs past the right edge, the visible half is not dimmed
+    let leds = vec![LED::new(0, 10, 0, 12, 8)];
+    let options = AveragingOptions::default();
+    let colors = calculate_avg_colors(&red_image(), 0, 0, 16, 16, &leds, &options).unwrap();
+    assert_eq!(rgb(&colors[0]), [255, 0, 0]);
+    assert!(offscreen_led_indices(0, 0, 16, 16, &leds, &options).is_empty());
+}
+
+#[test]
+fn desktop_offset_is_taken_into_account() {
+    // The image starts at (-16, 0), e.g. a monitor left of the primary one
+    let leds = vec![LED::new(0, -12, 4, 8, 8), LED::new(1, 4, 4, 8, 8)];
+    let options = AveragingOptions::default();
+    let colors = calculate_avg_colors(&red_image(), -16, 0, 16, 16, &leds, &options).unwrap();
+    assert_eq!(rgb(&colors[0]), [255, 0, 0]);
+    assert_eq!(rgb(&colors[1]), [0, 0, 0]);
+    assert_eq!(offscreen_led_indices(-16, 0, 16, 16, &leds, &options), vec![1]);
+}
+
+#[test]
+fn hold_keeps_the_previous_color_of_offscreen_leds_only() {
+    let mut colors = vec![Color::from_rgb8(0, 0, 0, 0), Color::from_rgb8(1, 10, 20, 30)];
+    let previous = vec![Color::from_rgb8(0, 1, 2, 3), Color::from_rgb8(1, 4, 5, 6)];
+    hold_offscreen_colors(&mut colors, &[0], &previous);
+    assert_eq!(rgb(&colors[0]), [1, 2, 3]);
+    assert_eq!(rgb(&colors[1]), [10, 20, 30]);
+}
+
+#[test]
+fn hold_without_a_previous_frame_keeps_black() {
+    let mut colors = vec![Color::from_rgb8(0, 0, 0, 0)];
+    hold_offscreen_colors(&mut colors, &[0], &[]);
+    assert_eq!(rgb(&colors[0]), [0, 0, 0]);
+}
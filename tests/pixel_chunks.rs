@@ -0,0 +1,68 @@
+use lightshow::arduino::{build_pixel_chunks, effect_color_state, pixel_state, segment_state, CHUNK_SIZE};
+use lightshow::Color;
+
+fn colors(count: i32) -> Vec<Color> {
+    (0..count).map(|i| Color::from_rgb8(i, 255, 0, 0)).collect()
+}
+
+#[test]
+fn chunks_start_at_multiples_of_chunk_size() {
+    let chunks = build_pixel_chunks(colors(600), CHUNK_SIZE);
+    assert_eq!(chunks.len(), 3);
+
+    // The first chunk has no start index, later ones lead with theirs
+    assert!(chunks[0][0].is_string());
+    assert_eq!(chunks[1][0].as_u64(), Some(256));
+    assert_eq!(chunks[2][0].as_u64(), Some(512));
+
+    let color_counts: Vec<usize> = chunks
+        .iter()
+        .map(|chunk| chunk.iter().filter(|v| v.is_string()).count())
+        .collect();
+    assert_eq!(color_counts, vec![256, 256, 88]);
+}
+
+#[test]
+fn single_chunk_has_no_start_index() {
+    let chunks = build_pixel_chunks(colors(10), CHUNK_SIZE);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].len(), 10);
+    assert_eq!(chunks[0][0].as_str(), Some("FF0000"));
+}
+
+#[test]
+fn start_index_follows_chunk_size() {
+    let chunks = build_pixel_chunks(colors(25), 10);
+    let starts: Vec<Option<u64>> = chunks.iter().map(|chunk| chunk[0].as_u64()).collect();
+    assert_eq!(starts, vec![None, Some(10), Some(20)]);
+}
+
+#[test]
+fn pixel_payload_leaves_segment_bounds_alone() {
+    let chunks = build_pixel_chunks(colors(3), CHUNK_SIZE);
+    let state = pixel_state(2, &chunks[0]);
+    let segment = state["seg"][0].as_object().unwrap();
+    let mut keys: Vec<&str> = segment.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, vec!["i", "id"]);
+    assert_eq!(segment["id"], 2);
+    assert_eq!(segment["i"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn effect_color_payload_leaves_segment_bounds_alone() {
+    let state = effect_color_state(1, 41, [255, 128, 0]);
+    let segment = state["seg"][0].as_object().unwrap();
+    let mut keys: Vec<&str> = segment.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, vec!["col", "fx", "id"]);
+    assert_eq!(segment["col"], serde_json::json!([[255, 128, 0]]));
+}
+
+#[test]
+fn segments_are_addressed_without_overlapping() {
+    let ambient = segment_state(0, serde_json::json!({ "fx": 0 }));
+    let other = segment_state(2, serde_json::json!({ "fx": 41 }));
+    assert_eq!(ambient, serde_json::json!({ "id": 0, "fx": 0 }));
+    assert_eq!(other, serde_json::json!({ "id": 2, "fx": 41 }));
+}
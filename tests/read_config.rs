@@ -0,0 +1,64 @@
+use lightshow::config::{read_config_with, Config, UnmatchedLines, LED};
+
+// Trimmed from a real Prismatik profile: the first six LEDs of a strip (one disabled, one
+// left at the neutral coefficients) and two of the unused slots Prismatik pads the profile with
+const PROFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/prismatik_profile.txt");
+
+fn read_profile() -> Config {
+    read_config_with(PROFILE, UnmatchedLines::Error).unwrap()
+}
+
+fn led(config: &Config, index: i32) -> &LED {
+    config.leds_array.iter().find(|led| led.index == index).unwrap()
+}
+
+#[test]
+fn sections_are_parsed() {
+    let config = read_profile();
+    assert_eq!(config.General.LightpackMode, "Ambilight");
+    assert!(config.General.IsBacklightEnabled);
+    assert_eq!(config.Grab.Grabber, "DDupl");
+    assert_eq!(config.Grab.Slowdown, 50);
+    assert_eq!(config.MoodLamp.Color, "#ff743d");
+    assert_eq!(config.SoundVisualizer.Device, 59);
+    assert_eq!(config.Device.Brightness, 100);
+    assert_eq!(config.Device.Smooth, 100);
+    assert!((config.Device.Gamma - 1.644).abs() < 1e-9);
+    assert!(config.Device.IsDitheringEnabled);
+}
+
+#[test]
+fn leds_are_populated_without_the_trailing_unused_slots() {
+    let config = read_profile();
+    let mut indices: Vec<i32> = config.leds_array.iter().map(|led| led.index).collect();
+    indices.sort_unstable();
+    assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(config.strip_len(), 6);
+}
+
+#[test]
+fn neutral_leds_within_the_strip_are_kept() {
+    let config = read_profile();
+    let neutral = led(&config, 2);
+    assert_eq!((neutral.CoefRed, neutral.CoefGreen, neutral.CoefBlue), (1.0, 1.0, 1.0));
+    assert_eq!((neutral.Position.x, neutral.Position.y), (4192, 1231));
+}
+
+#[test]
+fn led_positions_sizes_and_coefficients_are_kept() {
+    let config = read_profile();
+
+    let first = led(&config, 0);
+    assert_eq!((first.Position.x, first.Position.y), (3740, 1024));
+    assert_eq!((first.Size.width, first.Size.height), (100, 56));
+    assert_eq!((first.CoefRed, first.CoefGreen, first.CoefBlue), (1.0, 0.79, 0.6));
+    assert!(first.IsEnabled);
+
+    let side = led(&config, 3);
+    assert_eq!((side.Position.x, side.Position.y), (4192, 1175));
+    assert_eq!((side.Size.width, side.Size.height), (288, 56));
+    assert_eq!((side.CoefRed, side.CoefGreen, side.CoefBlue), (0.92, 0.61, 0.48));
+
+    // Disabled LEDs stay in the layout
+    assert!(!led(&config, 5).IsEnabled);
+}
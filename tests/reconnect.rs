@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lightshow::output::{OutputSink, Reconnector};
+use lightshow::{Color, Error};
+
+// Answers the reconnect only after `failures` attempts
+struct FlakySink {
+    failures: u32,
+    attempts: Arc<AtomicU32>,
+}
+
+impl OutputSink for FlakySink {
+    fn send(&self, _colors: &[Color]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "flaky"
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt > self.failures {
+            Ok(())
+        } else {
+            Err(Error::Network("offline".to_string()))
+        }
+    }
+}
+
+fn flaky_sink(failures: u32) -> (Arc<Box<dyn OutputSink>>, Arc<AtomicU32>) {
+    let attempts = Arc::new(AtomicU32::new(0));
+    let sink: Box<dyn OutputSink> = Box::new(FlakySink { failures, attempts: Arc::clone(&attempts) });
+    (Arc::new(sink), attempts)
+}
+
+fn wait_until_done(reconnector: &Reconnector, timeout: Duration) {
+    let start = Instant::now();
+    while reconnector.is_running() && start.elapsed() < timeout {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn reconnect_retries_in_the_background_until_the_device_answers() {
+    let (sink, attempts) = flaky_sink(1);
+    let mut reconnector = Reconnector::default();
+    assert!(!reconnector.is_running());
+
+    let start = Instant::now();
+    reconnector.start(Arc::clone(&sink));
+    // The caller isn't held up by the failing attempt and its pause
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert!(reconnector.is_for(&sink));
+
+    // A second start while running doesn't spawn another attempt
+    reconnector.start(Arc::clone(&sink));
+    wait_until_done(&reconnector, Duration::from_secs(5));
+    assert!(!reconnector.is_running());
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cancel_stops_retrying_and_forgets_the_sink() {
+    let (sink, attempts) = flaky_sink(u32::MAX);
+    let (other, _) = flaky_sink(0);
+    let mut reconnector = Reconnector::default();
+    reconnector.start(Arc::clone(&sink));
+    assert!(!reconnector.is_for(&other));
+
+    reconnector.cancel();
+    assert!(!reconnector.is_running());
+    assert!(!reconnector.is_for(&sink));
+    // The thread ends after its current pause without another attempt
+    thread::sleep(Duration::from_millis(1500));
+    assert!(attempts.load(Ordering::SeqCst) <= 1);
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use lightshow::backend::frame_map;
+use lightshow::hardware_interaction::{FrameData, PixelFormat, SlimMonitorInfo};
+use lightshow::screen_capture::{combine_screens, OverlapMode};
+
+fn combine(monitor: &SlimMonitorInfo) -> (Vec<u8>, Vec<i32>) {
+    let mut contributing = Vec::new();
+    let (image, _) = combine_screens(
+        &vec![monitor.clone()],
+        monitor.width as u32,
+        monitor.height as u32,
+        0,
+        monitor.pos_x,
+        monitor.pos_y,
+        1,
+        &HashMap::new(),
+        OverlapMode::Last,
+        &mut contributing,
+    )
+    .unwrap();
+    (image.into_rgba8().into_raw(), contributing)
+}
+
+#[test]
+fn oversized_frame_is_not_taken_over() {
+    let monitor = SlimMonitorInfo { id: 7, pos_x: 0, pos_y: 0, width: 2, height: 2 };
+    let frame = |len: usize| FrameData {
+        data: vec![200; len],
+        format: PixelFormat::Rgba8,
+        captured_at: Instant::now(),
+        scale: 1,
+    };
+
+    // A frame left from a larger monitor with the same id
+    frame_map().insert(monitor.id, frame(2 * 2 * 4 * 2));
+    let (pixels, contributing) = combine(&monitor);
+    assert_eq!(pixels.len(), 2 * 2 * 4);
+    assert!(pixels.chunks_exact(4).all(|pixel| pixel[..3] == [0, 0, 0]));
+    assert!(contributing.is_empty());
+
+    frame_map().insert(monitor.id, frame(2 * 2 * 4));
+    let (pixels, contributing) = combine(&monitor);
+    assert_eq!(pixels, vec![200; 2 * 2 * 4]);
+    assert_eq!(contributing, vec![monitor.id]);
+}
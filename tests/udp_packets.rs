@@ -0,0 +1,35 @@
+use lightshow::output::{dnrgb_packets, DEFAULT_MAX_PACKET_SIZE};
+use lightshow::Color;
+
+fn colors(count: i32) -> Vec<Color> {
+    (0..count).map(|i| Color::from_rgb8(i, 255, 0, 0)).collect()
+}
+
+#[test]
+fn small_frame_is_one_packet() {
+    let packets = dnrgb_packets(&colors(10), DEFAULT_MAX_PACKET_SIZE);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(&packets[0][..4], &[4, 2, 0, 0]);
+    assert_eq!(packets[0].len(), 4 + 10 * 3);
+    assert_eq!(&packets[0][4..7], &[255, 0, 0]);
+}
+
+#[test]
+fn packets_stay_under_the_size_limit() {
+    let packets = dnrgb_packets(&colors(1000), DEFAULT_MAX_PACKET_SIZE);
+    assert_eq!(packets.len(), 3);
+    assert!(packets.iter().all(|packet| packet.len() <= DEFAULT_MAX_PACKET_SIZE));
+
+    // Every packet leads with the index of its first LED, big endian
+    let starts: Vec<u16> = packets.iter().map(|packet| u16::from_be_bytes([packet[2], packet[3]])).collect();
+    assert_eq!(starts, vec![0, 465, 930]);
+    let leds: usize = packets.iter().map(|packet| (packet.len() - 4) / 3).sum();
+    assert_eq!(leds, 1000);
+}
+
+#[test]
+fn large_limit_is_capped_at_the_dnrgb_maximum() {
+    let packets = dnrgb_packets(&colors(600), 65_000);
+    assert_eq!(packets.len(), 2);
+    assert_eq!((packets[0].len() - 4) / 3, 489);
+}